@@ -4,4 +4,59 @@ Registry:set_block("grass", {
         side = "textures/blocks/grass_side_carried.png",
         bottom = "textures/blocks/dirt.png"
     }
-});
\ No newline at end of file
+});
+
+Registry:set_block("log", {
+    properties = {
+        axis = { "y", "x", "z" }
+    },
+    textures = {
+        top = "textures/blocks/log_oak_top.png",
+        side = "textures/blocks/log_oak.png"
+    },
+    states = {
+        {
+            when = { axis = "x" },
+            textures = {
+                top = "textures/blocks/log_oak.png",
+                right = "textures/blocks/log_oak_top.png",
+                left = "textures/blocks/log_oak_top.png"
+            }
+        },
+        {
+            when = { axis = "z" },
+            textures = {
+                top = "textures/blocks/log_oak.png",
+                front = "textures/blocks/log_oak_top.png",
+                back = "textures/blocks/log_oak_top.png"
+            }
+        }
+    }
+});
+
+Registry:set_block("furnace", {
+    properties = {
+        facing = { "north", "south", "east", "west" },
+        lit = "bool"
+    },
+    textures = {
+        top = "textures/blocks/furnace_top.png",
+        side = "textures/blocks/furnace_side.png",
+        back = "textures/blocks/furnace_front_off.png"
+    },
+    entity = {
+        burn_time = 0,
+        cook_time = 0,
+        items = {}
+    },
+    states = {
+        {
+            when = { facing = "north", lit = true },
+            textures = {
+                top = "textures/blocks/furnace_top.png",
+                side = "textures/blocks/furnace_side.png",
+                back = "textures/blocks/furnace_front_on.png"
+            }
+        }
+    }
+});
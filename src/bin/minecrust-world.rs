@@ -0,0 +1,339 @@
+//! Inspect and maintain a minecrust world without starting the game.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::Context;
+use bevy::math::IVec3;
+use minecrust::voxel::anvil::{self, BlockMapping};
+use minecrust::voxel::chunk::{ChunkData, PaddedChunkShape, CHUNK_SIZE};
+use minecrust::voxel::snapshot::Snapshots;
+use minecrust::voxel::storage::{
+    ChunkStorage, WorldDatabase, WorldMetadata, WorldStorage, CHUNKS,
+};
+use ndshape::ConstShape;
+use redb::{ReadableTable, ReadableTableMetadata};
+
+const USAGE: &str = "usage: minecrust-world <world> <command>
+
+commands:
+    info                 print world metadata
+    list                 list stored chunks
+    dump <x> <y> <z>     print palette, voxel histogram, block entities and hash of a chunk
+    stats                report database size and compression ratios
+    compact              compact the database file
+    verify               check that every stored chunk decodes
+    quarantine           list chunks that failed to decode in game and were regenerated
+    import-anvil <path> [mapping]
+                         import a Minecraft region file, region directory or world
+    export-anvil <dir> [mapping]
+                         export all chunks as Minecraft region files
+    snapshot [--incremental]
+                         snapshot the world into <world>.snapshots
+    snapshots            list snapshots
+    restore <id>         replace the world with a snapshot, the game must be closed
+
+[mapping] is a block mapping file, defaults to assets/anvil/blocks.txt";
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (Some(path), Some(command)) = (args.first(), args.get(1)) else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let path = PathBuf::from(path);
+
+    let result = match command.as_str() {
+        "info" => info(&path),
+        "list" => list(&path),
+        "dump" => parse_pos(&args[2..]).and_then(|pos| dump(&path, pos)),
+        "stats" => stats(&path),
+        "compact" => compact(&path),
+        "verify" => verify(&path),
+        "quarantine" => quarantine(&path),
+        "import-anvil" => match args.get(2) {
+            Some(source) => import_anvil(&path, Path::new(source), args.get(3)),
+            None => Err(anyhow::anyhow!("expected a region file or directory")),
+        },
+        "export-anvil" => match args.get(2) {
+            Some(dir) => export_anvil(&path, Path::new(dir), args.get(3)),
+            None => Err(anyhow::anyhow!("expected an output directory")),
+        },
+        "snapshot" => snapshot(&path, args.get(2).is_some_and(|arg| arg == "--incremental")),
+        "snapshots" => list_snapshots(&path),
+        "restore" => match args.get(2).map(|id| id.parse()) {
+            Some(Ok(id)) => restore(&path, id),
+            _ => Err(anyhow::anyhow!("expected a snapshot id")),
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("error: {err:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_pos(args: &[String]) -> anyhow::Result<IVec3> {
+    anyhow::ensure!(args.len() == 3, "expected chunk position <x> <y> <z>");
+    let mut pos = [0; 3];
+    for (i, arg) in args.iter().enumerate() {
+        pos[i] = arg
+            .parse()
+            .with_context(|| format!("invalid coordinate `{arg}`"))?;
+    }
+    Ok(IVec3::from_array(pos))
+}
+
+fn info(path: &Path) -> anyhow::Result<bool> {
+    let db = WorldDatabase::open(path)?;
+    match db.metadata()? {
+        Some(metadata) => println!("{metadata:#?}"),
+        None => println!("no metadata, world predates it"),
+    }
+    Ok(true)
+}
+
+fn list(path: &Path) -> anyhow::Result<bool> {
+    let db = WorldDatabase::open(path)?;
+    db.read(CHUNKS, |_, table| {
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let [x, y, z] = key.value();
+            println!("{x} {y} {z}\t{} bytes", value.value().len());
+        }
+        anyhow::Ok(true)
+    })
+    .and_then(|v| v)
+}
+
+fn dump(path: &Path, pos: IVec3) -> anyhow::Result<bool> {
+    let db = WorldDatabase::open(path)?;
+    let bytes = db
+        .read(CHUNKS, |_, table| {
+            anyhow::Ok(table.get(pos.to_array())?.map(|v| v.value().to_vec()))
+        })
+        .and_then(|v| v)?
+        .with_context(|| format!("chunk {pos} is not stored"))?;
+
+    let (version, payload) = ChunkData::decompress(&bytes)?;
+    let chunk = ChunkData::decode(&bytes)?;
+
+    println!("chunk {}", chunk.pos);
+    println!("hash: {:016x}", ChunkData::read_header(&bytes)?);
+    println!("format version: {version}");
+    println!(
+        "size: {} bytes stored, {} bytes decompressed",
+        bytes.len(),
+        payload.len()
+    );
+
+    // only count voxels owned by this chunk, not the padding copied from neighbors
+    let mut histogram = BTreeMap::<&str, u32>::new();
+    for x in 1..=CHUNK_SIZE {
+        for y in 1..=CHUNK_SIZE {
+            for z in 1..=CHUNK_SIZE {
+                let index = PaddedChunkShape::linearize([x, y, z]);
+                let id = chunk.get_block_id(index).map_or("core::air", |id| id.as_str());
+                *histogram.entry(id).or_default() += 1;
+            }
+        }
+    }
+
+    println!("palette:");
+    let mut idx = 0;
+    while let Some(id) = chunk.palette.block_id(idx) {
+        println!("    {idx}: {id}");
+        idx += 1;
+    }
+    println!("histogram:");
+    for (id, count) in histogram {
+        println!("    {id}: {count}");
+    }
+    println!("block entities:");
+    for (index, entity) in &chunk.block_entities {
+        let [x, y, z] = PaddedChunkShape::delinearize(*index);
+        println!("    ({x}, {y}, {z}) {}: {:?}", entity.block, entity.data);
+    }
+    Ok(true)
+}
+
+fn stats(path: &Path) -> anyhow::Result<bool> {
+    let file_size = std::fs::metadata(path)?.len();
+    let db = WorldDatabase::open(path)?;
+
+    let mut count = 0u64;
+    let mut stored = 0u64;
+    let mut decompressed = 0u64;
+    let mut versions = BTreeMap::<u16, u64>::new();
+    let table_stats = db
+        .read(CHUNKS, |_, table| {
+            for entry in table.iter()? {
+                let (_, value) = entry?;
+                let bytes = value.value();
+                let (version, payload) = ChunkData::decompress(bytes)?;
+                count += 1;
+                stored += bytes.len() as u64;
+                decompressed += payload.len() as u64;
+                *versions.entry(version).or_default() += 1;
+            }
+            anyhow::Ok(table.stats()?)
+        })
+        .and_then(|v| v)?;
+
+    println!("file size: {file_size} bytes");
+    println!("chunks: {count}");
+    println!(
+        "chunk table: {} bytes stored, {} bytes metadata, {} bytes fragmented",
+        table_stats.stored_bytes(),
+        table_stats.metadata_bytes(),
+        table_stats.fragmented_bytes()
+    );
+    if count != 0 {
+        println!(
+            "chunk payload: {stored} bytes compressed, {decompressed} bytes decompressed, ratio {:.2}",
+            decompressed as f64 / stored as f64
+        );
+        println!("average chunk: {} bytes", stored / count);
+    }
+    for (version, count) in versions {
+        println!("format version {version}: {count} chunks");
+    }
+    Ok(true)
+}
+
+fn compact(path: &Path) -> anyhow::Result<bool> {
+    let before = std::fs::metadata(path)?.len();
+    let mut db = WorldDatabase::open(path)?;
+    db.compact()?;
+    drop(db);
+    let after = std::fs::metadata(path)?.len();
+    println!("compacted: {before} -> {after} bytes");
+    Ok(true)
+}
+
+fn verify(path: &Path) -> anyhow::Result<bool> {
+    let db = WorldDatabase::open(path)?;
+    let mut checked = 0u64;
+    let mut failed = 0u64;
+    db.read(CHUNKS, |_, table| {
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let pos = IVec3::from_array(key.value());
+            checked += 1;
+            match ChunkData::decode(value.value()) {
+                Ok(chunk) if chunk.pos == pos => {}
+                Ok(chunk) => {
+                    failed += 1;
+                    println!("{pos}: stored under wrong key, chunk says {}", chunk.pos);
+                }
+                Err(err) => {
+                    failed += 1;
+                    println!("{pos}: {err:#}");
+                }
+            }
+        }
+        anyhow::Ok(())
+    })
+    .and_then(|v| v)?;
+
+    println!("verified {checked} chunks, {failed} failed");
+    Ok(failed == 0)
+}
+
+fn quarantine(path: &Path) -> anyhow::Result<bool> {
+    let db = WorldDatabase::open(path)?;
+    let positions = db.quarantined()?;
+    for pos in &positions {
+        println!("{} {} {}", pos.x, pos.y, pos.z);
+    }
+    println!("{} quarantined chunks", positions.len());
+    Ok(true)
+}
+
+fn block_mapping(path: Option<&String>) -> anyhow::Result<BlockMapping> {
+    match path {
+        Some(path) => BlockMapping::load(path),
+        None => Ok(BlockMapping::builtin()),
+    }
+}
+
+fn import_anvil(path: &Path, source: &Path, mapping: Option<&String>) -> anyhow::Result<bool> {
+    let mapping = block_mapping(mapping)?;
+    let storage = WorldStorage::open(WorldDatabase::new(path)?, WorldMetadata::default)?;
+    let stats = anvil::import(source, &mapping, &**storage)?;
+
+    println!(
+        "imported {} chunks from {} columns in {} regions, skipped {} columns",
+        stats.chunks, stats.columns, stats.regions, stats.skipped_columns
+    );
+    if !stats.unmapped.is_empty() {
+        println!("unmapped blocks, imported as air:");
+        for (name, count) in stats.unmapped {
+            println!("    {name}: {count}");
+        }
+    }
+    Ok(true)
+}
+
+fn export_anvil(path: &Path, dir: &Path, mapping: Option<&String>) -> anyhow::Result<bool> {
+    let mapping = block_mapping(mapping)?;
+    let db = WorldDatabase::open(path)?;
+    let stats = anvil::export(&db, &mapping, dir)?;
+
+    println!(
+        "exported {} columns in {} regions to {}",
+        stats.columns,
+        stats.regions,
+        dir.display()
+    );
+    if stats.clipped != 0 {
+        println!("{} blocks outside the Minecraft height were dropped", stats.clipped);
+    }
+    if !stats.unmapped.is_empty() {
+        println!("unmapped blocks, exported as air:");
+        for (id, count) in stats.unmapped {
+            println!("    {id}: {count}");
+        }
+    }
+    Ok(true)
+}
+
+fn snapshot(path: &Path, incremental: bool) -> anyhow::Result<bool> {
+    let db = WorldDatabase::open(path)?;
+    let info = Snapshots::for_world(path).take(&db, incremental)?;
+    println!(
+        "snapshot {}: {} chunks, {} stored, {} pending edits",
+        info.id, info.chunks, info.stored_chunks, info.pending_edits
+    );
+    Ok(true)
+}
+
+fn list_snapshots(path: &Path) -> anyhow::Result<bool> {
+    for info in Snapshots::for_world(path).list()? {
+        let kind = match info.parent {
+            Some(parent) => format!("incremental to {parent}"),
+            None => "full".to_owned(),
+        };
+        println!(
+            "{}\tcreated {}\t{kind}\t{} chunks, {} stored",
+            info.id, info.created, info.chunks, info.stored_chunks
+        );
+    }
+    Ok(true)
+}
+
+fn restore(path: &Path, id: u64) -> anyhow::Result<bool> {
+    let db = WorldDatabase::new(path)?;
+    let info = Snapshots::for_world(path).restore(id, &db)?;
+    println!("restored snapshot {}: {} chunks", info.id, info.chunks);
+    Ok(true)
+}
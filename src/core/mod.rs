@@ -10,8 +10,12 @@ use bevy_flycam::{FlyCam, MovementSettings, NoCameraPlayerPlugin};
 use registry::{register_core_items, Registry, RegistryAssets};
 
 use crate::state::AppState;
+use crate::voxel::chunk::CHUNK_SIZE;
+use crate::voxel::config::VoxelConfig;
 use crate::voxel::modifier::VoxelModifier;
-use crate::voxel::storage::WorldDatabase;
+use crate::voxel::origin::FloatingOrigin;
+use crate::voxel::snapshot::Snapshots;
+use crate::voxel::storage::{WorldDatabase, WorldMetadata, WorldStorage};
 use crate::voxel::VoxelWorldCamera;
 
 pub mod registry;
@@ -22,11 +26,11 @@ pub struct CorePlugin;
 impl Plugin for CorePlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_plugins(NoCameraPlayerPlugin)
-            .add_systems(OnEnter(AppState::Loading), register_core_items)
             .add_systems(
-                OnEnter(AppState::InGame),
-                ((setup_game,).chain(), setup_world_storage),
+                OnEnter(AppState::Loading),
+                (register_core_items, setup_world_storage),
             )
+            .add_systems(OnEnter(AppState::InGame), (setup_game,).chain())
             .init_resource::<Registry>()
             .configure_loading_state(
                 LoadingStateConfig::new(AppState::PrepareAssets)
@@ -35,11 +39,22 @@ impl Plugin for CorePlugin {
     }
 }
 
-fn setup_game(mut commands: Commands, modifier: Res<VoxelModifier>) {
+fn setup_game(
+    mut commands: Commands,
+    modifier: Res<VoxelModifier>,
+    storage: Res<WorldStorage>,
+    mut origin: ResMut<FloatingOrigin>,
+) {
+    let spawn = storage
+        .world_metadata()
+        .expect("reading world metadata failed")
+        .spawn();
+    // rendering starts around the spawn, however far from the world origin it is
+    origin.chunk = spawn.div_euclid(IVec3::splat(CHUNK_SIZE as i32));
     commands.spawn((
         Camera3dBundle {
-            transform: Transform::from_xyz(50.0, 5.0, 50.0)
-                .looking_at(Vec3::new(0.0, 0.0, 0.0), Vec3::Y),
+            transform: Transform::from_translation(origin.render_position(spawn))
+                .looking_at(origin.render_position(IVec3::ZERO), Vec3::Y),
 
             ..Default::default()
         },
@@ -78,6 +93,14 @@ fn setup_game(mut commands: Commands, modifier: Res<VoxelModifier>) {
     }
 }
 
-fn setup_world_storage(mut commands: Commands) {;
-    commands.insert_resource(WorldDatabase::new("world").unwrap());
+// opened before `InGame` so the voxel world can be built from its metadata
+fn setup_world_storage(mut commands: Commands, config: Res<VoxelConfig>) {
+    let database = WorldDatabase::new("world").unwrap();
+    let metadata = || WorldMetadata {
+        seed: config.seed,
+        generator: config.generator.clone(),
+        ..Default::default()
+    };
+    commands.insert_resource(WorldStorage::open(database, metadata).unwrap());
+    commands.insert_resource(Snapshots::for_world("world"));
 }
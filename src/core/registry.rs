@@ -2,6 +2,8 @@ use std::sync::Arc;
 
 use crate::atom::Atom;
 use crate::script::{LuaEngine, LuaScript};
+use crate::voxel::block_entity::BlockEntity;
+use crate::voxel::voxel_block::{BlockId, BlockState};
 use bevy::prelude::*;
 use bevy::utils::hashbrown::HashMap;
 use bevy_asset_loader::asset_collection::AssetCollection;
@@ -36,12 +38,27 @@ impl Registry {
     pub fn get_block_with<T>(&self, id: &Atom, f: impl FnOnce(&BlockRegistry) -> T) -> Option<T> {
         self.blocks.pin().get(id).map(f)
     }
+
+    /// Block entity a newly placed `id` gets, `None` if it has none or is not registered.
+    pub fn new_block_entity(&self, id: &BlockId) -> Option<BlockEntity> {
+        self.get_block_with(id, BlockRegistry::new_block_entity).flatten()
+    }
+
+    /// `Err` if the block is not registered or `state` sets a property it does not allow.
+    pub fn check_state(&self, state: &BlockState) -> Result<(), String> {
+        self.get_block_with(state.id(), |block| block.check_state(state))
+            .unwrap_or_else(|| Err(format!("unknown block {}", state.id())))
+    }
 }
 
 impl UserData for Registry {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method_mut::<_, (String, Table), _>("set_block", |lua, this, (id, table)| {
-            let metadata = Arc::new(lua.from_value::<BlockMetadata>(mlua::Value::Table(table))?);
+            let metadata = lua.from_value::<BlockMetadata>(mlua::Value::Table(table))?;
+            metadata
+                .check()
+                .map_err(|err| mlua::Error::runtime(format!("block `{id}`: {err}")))?;
+            let metadata = Arc::new(metadata);
             let namespace = lua
                 .globals()
                 .get::<String>("namespace")
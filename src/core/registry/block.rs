@@ -1,9 +1,12 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 use crate::atom::Atom;
+use crate::voxel::block_entity::{BlockEntity, BlockValue};
 use crate::voxel::textures::Face;
+use crate::voxel::voxel_block::BlockState;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct BlockRegistry {
@@ -12,9 +15,215 @@ pub struct BlockRegistry {
     pub metadata: Arc<BlockMetadata>,
 }
 
+impl BlockRegistry {
+    /// Every property at its default value.
+    pub fn default_state(&self) -> BlockState {
+        let defaults = self
+            .metadata
+            .properties
+            .iter()
+            .map(|(name, kind)| (name.clone(), kind.default_value()))
+            .collect::<Vec<_>>();
+        BlockState::new(
+            self.id.clone(),
+            defaults
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+    }
+
+    /// `Err` names the first property `state` does not declare or sets to a value it does not allow.
+    pub fn check_state(&self, state: &BlockState) -> Result<(), String> {
+        for (name, value) in state.properties() {
+            let kind = self
+                .metadata
+                .properties
+                .get(name)
+                .ok_or_else(|| format!("{} has no property `{name}`", self.id))?;
+            if !kind.allows(value) {
+                return Err(format!("{} does not allow {name}={value}", self.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Value of `name` in `state`, the default if `state` does not set it.
+    pub fn property(&self, state: &BlockState, name: &str) -> Option<String> {
+        match state.property(name) {
+            Some(value) => Some(value.to_owned()),
+            None => self
+                .metadata
+                .properties
+                .get(name)
+                .map(PropertyKind::default_value),
+        }
+    }
+
+    pub fn textures(&self, state: &BlockState) -> &BlockTextures {
+        self.variant(state)
+            .and_then(|variant| variant.textures.as_ref())
+            .unwrap_or(&self.metadata.textures)
+    }
+
+    pub fn model(&self, state: &BlockState) -> &str {
+        self.variant(state)
+            .and_then(|variant| variant.model.as_deref())
+            .or(self.metadata.model.as_deref())
+            .unwrap_or(DEFAULT_MODEL)
+    }
+
+    /// Block entity of a newly placed block, `None` if the block has none.
+    pub fn new_block_entity(&self) -> Option<BlockEntity> {
+        let data = self.metadata.entity.clone()?;
+        Some(BlockEntity {
+            block: self.id.clone(),
+            data,
+        })
+    }
+
+    /// First entry of `states` whose `when` matches. Runs for every meshed face, so no allocations.
+    fn variant(&self, state: &BlockState) -> Option<&StateVariant> {
+        self.metadata.states.iter().find(|variant| {
+            variant.when.iter().all(|(name, value)| match state.property(name) {
+                Some(set) => set == value,
+                None => self
+                    .metadata
+                    .properties
+                    .get(name)
+                    .is_some_and(|kind| kind.is_default(value)),
+            })
+        })
+    }
+}
+
+/// Every block is a full cube for now.
+pub const DEFAULT_MODEL: &str = "cube";
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct BlockMetadata {
     pub textures: BlockTextures,
+    /// `cube` if not set
+    #[serde(default)]
+    pub model: Option<String>,
+    /// name -> allowed values, the first allowed value is the default
+    #[serde(default)]
+    pub properties: BTreeMap<String, PropertyKind>,
+    /// overrides for some states, the first match wins
+    #[serde(default)]
+    pub states: Vec<StateVariant>,
+    /// data of the block entity a newly placed block gets, none if not set
+    #[serde(default)]
+    pub entity: Option<BlockValue>,
+    /// like water: motion blocking, but not the ocean floor
+    #[serde(default)]
+    pub liquid: bool,
+    /// nothing collides with it, like flowers. Only the world surface heightmap sees it
+    #[serde(default)]
+    pub passable: bool,
+}
+
+impl BlockMetadata {
+    /// `Err` if a state override matches on a property that is not declared,
+    /// or on a value the property does not allow.
+    pub fn check(&self) -> Result<(), String> {
+        for variant in &self.states {
+            for (name, value) in &variant.when {
+                let kind = self
+                    .properties
+                    .get(name)
+                    .ok_or_else(|| format!("state override uses undeclared property `{name}`"))?;
+                if !kind.allows(value) {
+                    return Err(format!("property `{name}` does not allow `{value}`"));
+                }
+            }
+        }
+        for (name, kind) in &self.properties {
+            if let PropertyKind::Enum(values) = kind {
+                if values.is_empty() {
+                    return Err(format!("property `{name}` allows no values"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// In Lua: `"bool"`, `{ min = 0, max = 15 }` or `{ "north", "south", "east", "west" }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PropertyKind {
+    Bool(BoolProperty),
+    Int { min: i32, max: i32 },
+    Enum(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum BoolProperty {
+    #[serde(rename = "bool")]
+    Bool,
+}
+
+impl PropertyKind {
+    pub fn allows(&self, value: &str) -> bool {
+        match self {
+            PropertyKind::Bool(_) => matches!(value, "true" | "false"),
+            PropertyKind::Int { min, max } => value
+                .parse::<i32>()
+                .is_ok_and(|value| (*min..=*max).contains(&value)),
+            PropertyKind::Enum(values) => values.iter().any(|allowed| allowed == value),
+        }
+    }
+
+    #[inline]
+    pub fn is_default(&self, value: &str) -> bool {
+        match self {
+            PropertyKind::Bool(_) => value == "false",
+            PropertyKind::Int { min, .. } => value.parse() == Ok(*min),
+            PropertyKind::Enum(values) => values.first().is_some_and(|first| first == value),
+        }
+    }
+
+    pub fn default_value(&self) -> String {
+        match self {
+            PropertyKind::Bool(_) => "false".to_owned(),
+            PropertyKind::Int { min, .. } => min.to_string(),
+            PropertyKind::Enum(values) => values.first().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StateVariant {
+    /// property -> value, Lua booleans and numbers are accepted as values
+    #[serde(deserialize_with = "property_values")]
+    pub when: BTreeMap<String, String>,
+    pub textures: Option<BlockTextures>,
+    pub model: Option<String>,
+}
+
+fn property_values<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Bool(bool),
+        Int(i64),
+        String(String),
+    }
+
+    let values = BTreeMap::<String, Value>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::Bool(value) => value.to_string(),
+                Value::Int(value) => value.to_string(),
+                Value::String(value) => value,
+            };
+            (name, value)
+        })
+        .collect())
 }
 
 #[derive(Debug, Clone, Deserialize)]
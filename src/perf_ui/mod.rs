@@ -2,7 +2,7 @@ use bevy::app::Plugin;
 use bevy::prelude::*;
 use iyes_perf_ui::entries::*;
 use iyes_perf_ui::prelude::*;
-use voxel::PerfUiEntryLoadedChunkCount;
+use voxel::{PerfUiEntryLoadedChunkCount, PerfUiEntrySaveQueue};
 
 use crate::state::AppState;
 
@@ -14,9 +14,22 @@ impl Plugin for PerfUiPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_plugins(iyes_perf_ui::PerfUiPlugin)
             .add_perf_ui_simple_entry::<PerfUiEntryLoadedChunkCount>()
+            .add_perf_ui_simple_entry::<PerfUiEntrySaveQueue>()
             .add_systems(Startup, setup_perf_ui)
-            .add_systems(OnEnter(AppState::InGame), add_perf_ui_entry::<PerfUiEntryLoadedChunkCount>)
-            .add_systems(OnExit(AppState::InGame), remove_perf_ui_entry::<PerfUiEntryLoadedChunkCount>);
+            .add_systems(
+                OnEnter(AppState::InGame),
+                (
+                    add_perf_ui_entry::<PerfUiEntryLoadedChunkCount>,
+                    add_perf_ui_entry::<PerfUiEntrySaveQueue>,
+                ),
+            )
+            .add_systems(
+                OnExit(AppState::InGame),
+                (
+                    remove_perf_ui_entry::<PerfUiEntryLoadedChunkCount>,
+                    remove_perf_ui_entry::<PerfUiEntrySaveQueue>,
+                ),
+            );
     }
 }
 
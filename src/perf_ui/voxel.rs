@@ -2,6 +2,7 @@ use bevy::ecs::system::lifetimeless::SRes;
 use bevy::prelude::*;
 use iyes_perf_ui::entry::PerfUiEntry;
 
+use crate::voxel::saver::ChunkSaver;
 use crate::voxel::world::VoxelWorld;
 
 #[derive(Component, Default)]
@@ -27,3 +28,27 @@ impl PerfUiEntry for PerfUiEntryLoadedChunkCount {
         Some(param.loaded_chunks.len())
     }
 }
+
+#[derive(Component, Default)]
+pub struct PerfUiEntrySaveQueue;
+
+impl PerfUiEntry for PerfUiEntrySaveQueue {
+    type SystemParam = Option<SRes<ChunkSaver>>;
+
+    type Value = usize;
+
+    fn label(&self) -> &str {
+        "Save queue"
+    }
+
+    fn sort_key(&self) -> i32 {
+        11
+    }
+
+    fn update_value(
+        &self,
+        param: &mut <Self::SystemParam as bevy::ecs::system::SystemParam>::Item<'_, '_>,
+    ) -> Option<Self::Value> {
+        param.as_ref().map(|saver| saver.pending())
+    }
+}
@@ -0,0 +1,578 @@
+//! Import and export Minecraft Java Edition worlds (Anvil `.mca` region files).
+//!
+//! Only the 1.18+ chunk layout is understood, older chunks are skipped.
+//! Block names go through a [`BlockMapping`], properties are ignored.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ahash::{AHashMap, AHashSet};
+use anyhow::Context;
+use bevy::math::{IVec3, UVec3};
+use bevy::prelude::Entity;
+use fastnbt::LongArray;
+use indexmap::IndexSet;
+use ndshape::ConstShape;
+use rayon::prelude::*;
+
+use crate::core::registry::Registry;
+
+use super::chunk::{ChunkData, PaddedChunkShape, CHUNK_SIZE};
+use super::storage::ChunkStorage;
+use super::voxel_block::{BlockId, BlockState, AIR};
+
+mod nbt;
+mod region;
+
+pub use region::{AnvilRegion, REGION_COLUMNS};
+
+/// Blocks per side of a Minecraft section.
+const SECTION_SIZE: i32 = 16;
+const SECTION_VOLUME: usize = 16 * 16 * 16;
+/// Minecraft columns (and sections) per side of a minecrust chunk.
+const COLUMNS_PER_CHUNK: i32 = CHUNK_SIZE as i32 / SECTION_SIZE;
+/// First data version with the 1.18 layout: no `Level` compound, `sections[].block_states`.
+const MIN_DATA_VERSION: i32 = 2860;
+/// 1.21.1
+pub const EXPORT_DATA_VERSION: i32 = 3955;
+/// Overworld height, -64..320.
+const MIN_SECTION_Y: i32 = -4;
+const MAX_SECTION_Y: i32 = 19;
+const AIR_NAME: &str = "minecraft:air";
+const EXPORT_BIOME: &str = "minecraft:plains";
+
+pub const DEFAULT_BLOCK_MAPPING: &str = include_str!("../../assets/anvil/blocks.txt");
+
+/// Minecraft block names <-> minecrust block ids.
+#[derive(Debug, Clone, Default)]
+pub struct BlockMapping {
+    import: AHashMap<String, BlockId>,
+    export: AHashMap<BlockId, String>,
+}
+
+impl BlockMapping {
+    /// One `minecraft:name = namespace::id` per line, `#` starts a comment.
+    /// When several names map to the same id, the first one is used for export.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut mapping = BlockMapping::default();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, id) = line.split_once('=').with_context(|| {
+                format!(
+                    "line {}: expected `minecraft:name = namespace::id`",
+                    line_no + 1
+                )
+            })?;
+            let (name, id) = (name.trim(), BlockId::new(id.trim()));
+            mapping
+                .export
+                .entry(id.clone())
+                .or_insert_with(|| name.to_owned());
+            mapping.import.insert(name.to_owned(), id);
+        }
+        Ok(mapping)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("read block mapping {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("parse block mapping {}", path.display()))
+    }
+
+    /// `assets/anvil/blocks.txt`
+    pub fn builtin() -> Self {
+        Self::parse(DEFAULT_BLOCK_MAPPING).expect("builtin block mapping is invalid")
+    }
+
+    /// `None` if the block has no mapping.
+    #[inline]
+    pub fn to_minecrust(&self, name: &str) -> Option<&BlockId> {
+        self.import.get(name)
+    }
+
+    /// `None` if the block has no mapping.
+    #[inline]
+    pub fn to_minecraft(&self, id: &BlockId) -> Option<&str> {
+        self.export.get(id).map(String::as_str)
+    }
+
+    /// Ids the mapping points at that are not in the registry.
+    pub fn unregistered(&self, registry: &Registry) -> Vec<BlockId> {
+        self.export
+            .keys()
+            .filter(|id| **id != *AIR && registry.get_block_with(id, |_| ()).is_none())
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub regions: u64,
+    pub columns: u64,
+    /// not fully generated or older than 1.18
+    pub skipped_columns: u64,
+    pub chunks: u64,
+    /// Minecraft block name -> blocks imported as air for lack of a mapping
+    pub unmapped: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct ExportStats {
+    pub regions: u64,
+    pub columns: u64,
+    /// minecrust block id -> blocks exported as air for lack of a mapping
+    pub unmapped: BTreeMap<String, u64>,
+    /// solid blocks outside the overworld height
+    pub clipped: u64,
+}
+
+/// A section with its block states still packed, palette already mapped.
+struct Section {
+    /// `None` == air, mapped blocks are in their default state
+    palette: Vec<Option<BlockState>>,
+    bits: u32,
+    data: Vec<i64>,
+}
+
+impl Section {
+    fn new(
+        states: nbt::PalettedStates<nbt::PaletteEntry>,
+        mapping: &BlockMapping,
+        unmapped: &mut BTreeMap<String, u64>,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(!states.palette.is_empty(), "empty block palette");
+        let bits = bits_for(states.palette.len());
+        let data = states.data.map(|data| data.to_vec()).unwrap_or_default();
+        anyhow::ensure!(
+            states.palette.len() == 1 || data.len() == packed_len(bits),
+            "{} longs of block states, expected {}",
+            data.len(),
+            packed_len(bits)
+        );
+
+        let section = Section {
+            palette: states
+                .palette
+                .iter()
+                .map(|entry| {
+                    mapping
+                        .to_minecrust(&entry.name)
+                        .filter(|id| **id != *AIR)
+                        .map(|id| BlockState::from(id.clone()))
+                })
+                .collect(),
+            bits,
+            data,
+        };
+        for (idx, entry) in states.palette.iter().enumerate() {
+            if mapping.to_minecrust(&entry.name).is_some() {
+                continue;
+            }
+            let count = (0..SECTION_VOLUME)
+                .filter(|i| section.index(*i) == idx)
+                .count();
+            if count != 0 {
+                *unmapped.entry(entry.name.clone()).or_default() += count as u64;
+            }
+        }
+        Ok(section)
+    }
+
+    #[inline]
+    fn index(&self, i: usize) -> usize {
+        if self.data.is_empty() {
+            0
+        } else {
+            unpack(&self.data, self.bits, i)
+        }
+    }
+
+    /// `pos` inside the section.
+    #[inline]
+    fn get(&self, pos: IVec3) -> Option<&BlockState> {
+        let i = (pos.y * 256 + pos.z * 16 + pos.x) as usize;
+        self.palette.get(self.index(i))?.as_ref()
+    }
+}
+
+/// Bits per palette index, at least 4 like the game writes them.
+fn bits_for(palette_len: usize) -> u32 {
+    (usize::BITS - palette_len.saturating_sub(1).leading_zeros()).max(4)
+}
+
+fn packed_len(bits: u32) -> usize {
+    SECTION_VOLUME.div_ceil((64 / bits) as usize)
+}
+
+/// Indices never span two longs (1.16+ layout).
+fn pack(indices: &[u16], bits: u32) -> Vec<i64> {
+    let per_long = (64 / bits) as usize;
+    indices
+        .chunks(per_long)
+        .map(|chunk| {
+            chunk.iter().enumerate().fold(0u64, |long, (i, idx)| {
+                long | (*idx as u64) << (i * bits as usize)
+            }) as i64
+        })
+        .collect()
+}
+
+#[inline]
+fn unpack(data: &[i64], bits: u32, i: usize) -> usize {
+    let per_long = (64 / bits) as usize;
+    let long = data[i / per_long] as u64;
+    ((long >> ((i % per_long) * bits as usize)) & ((1 << bits) - 1)) as usize
+}
+
+/// Import a region file, or every region of a world or `region` directory.
+pub fn import(
+    path: &Path,
+    mapping: &BlockMapping,
+    storage: &dyn ChunkStorage,
+) -> anyhow::Result<ImportStats> {
+    let files = if path.is_dir() {
+        region_files(path)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut stats = ImportStats::default();
+    for file in files {
+        import_region(&file, mapping, storage, &mut stats)?;
+        stats.regions += 1;
+    }
+    Ok(stats)
+}
+
+fn region_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    // a world directory keeps its overworld regions in `region/`
+    let region_dir = dir.join("region");
+    let dir = if region_dir.is_dir() { &region_dir } else { dir };
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if AnvilRegion::parse_file_name(&path).is_ok() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// A minecrust chunk covers 2×2 columns and two sections of each.
+/// Padding voxels only come from the same region file, past its border they are air.
+fn import_region(
+    path: &Path,
+    mapping: &BlockMapping,
+    storage: &dyn ChunkStorage,
+    stats: &mut ImportStats,
+) -> anyhow::Result<()> {
+    let _span = tracing::info_span!("profiling::{import anvil region}").entered();
+    let (region_x, region_z) = AnvilRegion::parse_file_name(path)?;
+    let mut region = AnvilRegion::open(path)?;
+
+    let mut columns = AHashSet::new();
+    let mut sections = AHashMap::new();
+    for z in 0..REGION_COLUMNS {
+        for x in 0..REGION_COLUMNS {
+            let Some(bytes) = region
+                .read_chunk(x, z)
+                .with_context(|| format!("read {}", path.display()))?
+            else {
+                continue;
+            };
+            let column: nbt::Column = fastnbt::from_bytes(&bytes)
+                .with_context(|| format!("parse chunk {x} {z} of {}", path.display()))?;
+            if column.data_version < MIN_DATA_VERSION || !column.is_full() {
+                stats.skipped_columns += 1;
+                continue;
+            }
+            stats.columns += 1;
+            columns.insert((column.x_pos, column.z_pos));
+
+            for section in column.sections {
+                let Some(states) = section.block_states else {
+                    continue;
+                };
+                let pos = IVec3::new(column.x_pos, section.y as i32, column.z_pos);
+                let section = Section::new(states, mapping, &mut stats.unmapped)
+                    .with_context(|| format!("section {pos} of {}", path.display()))?;
+                sections.insert(pos, section);
+            }
+        }
+    }
+
+    let Some((min_y, max_y)) = sections
+        .keys()
+        .map(|pos| (pos.y, pos.y))
+        .reduce(|(min, max), (y, _)| (min.min(y), max.max(y)))
+    else {
+        return Ok(());
+    };
+    let chunk_min_y = (min_y * SECTION_SIZE).div_euclid(CHUNK_SIZE as i32);
+    let chunk_max_y = ((max_y + 1) * SECTION_SIZE - 1).div_euclid(CHUNK_SIZE as i32);
+    let chunks_per_region = REGION_COLUMNS as i32 / COLUMNS_PER_CHUNK;
+
+    let mut positions = Vec::new();
+    for cz in region_z * chunks_per_region..(region_z + 1) * chunks_per_region {
+        for cx in region_x * chunks_per_region..(region_x + 1) * chunks_per_region {
+            let covered = (0..COLUMNS_PER_CHUNK).any(|dx| {
+                (0..COLUMNS_PER_CHUNK).any(|dz| {
+                    columns.contains(&(cx * COLUMNS_PER_CHUNK + dx, cz * COLUMNS_PER_CHUNK + dz))
+                })
+            });
+            if covered {
+                positions.extend((chunk_min_y..=chunk_max_y).map(|cy| IVec3::new(cx, cy, cz)));
+            }
+        }
+    }
+
+    let block_at = |pos: IVec3| {
+        let section = pos.div_euclid(IVec3::splat(SECTION_SIZE));
+        sections
+            .get(&section)
+            .and_then(|section| section.get(pos.rem_euclid(IVec3::splat(SECTION_SIZE))))
+    };
+    let encoded = positions
+        .par_iter()
+        .map(|pos| import_chunk(*pos, &block_at)?.encode())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let batch = positions
+        .iter()
+        .zip(&encoded)
+        .map(|(pos, bytes)| (*pos, &**bytes))
+        .collect::<Vec<_>>();
+    storage.save(&batch)?;
+    stats.chunks += batch.len() as u64;
+    Ok(())
+}
+
+fn import_chunk<'a>(
+    pos: IVec3,
+    block_at: impl Fn(IVec3) -> Option<&'a BlockState>,
+) -> anyhow::Result<ChunkData> {
+    let mut chunk = ChunkData::new(pos, Entity::PLACEHOLDER);
+    let origin = pos * CHUNK_SIZE as i32 - IVec3::ONE;
+    for i in 0..PaddedChunkShape::SIZE {
+        let local = UVec3::from_array(PaddedChunkShape::delinearize(i));
+        if let Some(state) = block_at(origin + local.as_ivec3()) {
+            let voxel = chunk.voxel_block(state)?;
+            chunk.voxels.set(i as usize, voxel);
+            chunk.solid_count += 1;
+        }
+    }
+    // air is always palette entry 0
+    if chunk.is_full() && chunk.palette.block_id(2).is_none() {
+        chunk.voxels.collapse();
+    }
+    chunk.generate_hash();
+    Ok(chunk)
+}
+
+/// Export every stored chunk into region files in `dir`.
+/// Blocks without a mapping are written as air.
+pub fn export(
+    storage: &dyn ChunkStorage,
+    mapping: &BlockMapping,
+    dir: &Path,
+) -> anyhow::Result<ExportStats> {
+    fs::create_dir_all(dir)?;
+    let chunks_per_region = REGION_COLUMNS as i32 / COLUMNS_PER_CHUNK;
+
+    // region -> chunk column -> chunk y
+    let mut regions = BTreeMap::<(i32, i32), BTreeMap<(i32, i32), Vec<i32>>>::new();
+    storage.iterate(&mut |pos, _| {
+        regions
+            .entry((
+                pos.x.div_euclid(chunks_per_region),
+                pos.z.div_euclid(chunks_per_region),
+            ))
+            .or_default()
+            .entry((pos.x, pos.z))
+            .or_default()
+            .push(pos.y);
+        Ok(())
+    })?;
+
+    let mut stats = ExportStats::default();
+    for ((region_x, region_z), chunk_columns) in regions {
+        let _span = tracing::info_span!("profiling::{export anvil region}").entered();
+        let mut columns = Vec::new();
+        for ((cx, cz), ys) in chunk_columns {
+            let mut chunks = BTreeMap::new();
+            for cy in ys {
+                let pos = IVec3::new(cx, cy, cz);
+                if let Some(bytes) = storage.load(pos)? {
+                    let chunk =
+                        ChunkData::decode(&bytes).with_context(|| format!("decode chunk {pos}"))?;
+                    chunks.insert(cy, chunk);
+                }
+            }
+            for dz in 0..COLUMNS_PER_CHUNK {
+                for dx in 0..COLUMNS_PER_CHUNK {
+                    let x_pos = cx * COLUMNS_PER_CHUNK + dx;
+                    let z_pos = cz * COLUMNS_PER_CHUNK + dz;
+                    let column = export_column(x_pos, z_pos, &chunks, mapping, &mut stats);
+                    columns.push((
+                        x_pos.rem_euclid(REGION_COLUMNS as i32) as usize,
+                        z_pos.rem_euclid(REGION_COLUMNS as i32) as usize,
+                        fastnbt::to_bytes(&column)?,
+                    ));
+                    stats.columns += 1;
+                }
+            }
+        }
+        AnvilRegion::write(&dir.join(format!("r.{region_x}.{region_z}.mca")), &columns)?;
+        stats.regions += 1;
+    }
+    Ok(stats)
+}
+
+/// `chunks` are the chunk column containing this column, by chunk y.
+fn export_column(
+    x_pos: i32,
+    z_pos: i32,
+    chunks: &BTreeMap<i32, ChunkData>,
+    mapping: &BlockMapping,
+    stats: &mut ExportStats,
+) -> nbt::Column {
+    let offset_x = x_pos.rem_euclid(COLUMNS_PER_CHUNK) * SECTION_SIZE;
+    let offset_z = z_pos.rem_euclid(COLUMNS_PER_CHUNK) * SECTION_SIZE;
+    let mut sections = Vec::new();
+
+    for (cy, chunk) in chunks {
+        for sub in 0..COLUMNS_PER_CHUNK {
+            let section_y = cy * COLUMNS_PER_CHUNK + sub;
+            let mut palette = IndexSet::<&str>::new();
+            let mut indices = [0u16; SECTION_VOLUME];
+            let mut solid = 0;
+            for y in 0..SECTION_SIZE {
+                for z in 0..SECTION_SIZE {
+                    for x in 0..SECTION_SIZE {
+                        // +1 skips the padding
+                        let index = PaddedChunkShape::linearize([
+                            (offset_x + x + 1) as u32,
+                            (sub * SECTION_SIZE + y + 1) as u32,
+                            (offset_z + z + 1) as u32,
+                        ]);
+                        let name = match chunk.get_block_id(index) {
+                            None => AIR_NAME,
+                            Some(id) => {
+                                solid += 1;
+                                mapping.to_minecraft(id).unwrap_or_else(|| {
+                                    *stats.unmapped.entry(id.as_str().to_owned()).or_default() += 1;
+                                    AIR_NAME
+                                })
+                            }
+                        };
+                        let (idx, _) = palette.insert_full(name);
+                        indices[(y * 256 + z * 16 + x) as usize] = idx as u16;
+                    }
+                }
+            }
+
+            if !(MIN_SECTION_Y..=MAX_SECTION_Y).contains(&section_y) {
+                stats.clipped += solid;
+                continue;
+            }
+            if solid == 0 {
+                continue;
+            }
+            let data = (palette.len() > 1)
+                .then(|| LongArray::new(pack(&indices, bits_for(palette.len()))));
+            sections.push(nbt::Section {
+                y: section_y as i8,
+                block_states: Some(nbt::PalettedStates {
+                    palette: palette
+                        .iter()
+                        .map(|name| nbt::PaletteEntry {
+                            name: (*name).to_owned(),
+                            properties: None,
+                        })
+                        .collect(),
+                    data,
+                }),
+                biomes: Some(nbt::PalettedStates {
+                    palette: vec![EXPORT_BIOME.to_owned()],
+                    data: None,
+                }),
+            });
+        }
+    }
+
+    nbt::Column {
+        data_version: EXPORT_DATA_VERSION,
+        x_pos,
+        y_pos: MIN_SECTION_Y,
+        z_pos,
+        status: "minecraft:full".to_owned(),
+        is_light_on: false,
+        sections,
+    }
+}
+
+#[test]
+fn test_pack_roundtrip() {
+    assert_eq!(bits_for(1), 4);
+    assert_eq!(bits_for(16), 4);
+    assert_eq!(bits_for(17), 5);
+    assert_eq!(bits_for(33), 6);
+
+    let indices = (0..SECTION_VOLUME)
+        .map(|i| (i * 7 % 33) as u16)
+        .collect::<Vec<_>>();
+    let data = pack(&indices, 6);
+    assert_eq!(data.len(), packed_len(6));
+    for (i, idx) in indices.iter().enumerate() {
+        assert_eq!(unpack(&data, 6, i), *idx as usize);
+    }
+}
+
+#[test]
+fn test_export_import_roundtrip() {
+    use super::storage::MemoryStorage;
+
+    let dir = std::env::temp_dir().join(format!("minecrust-anvil-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let mapping = BlockMapping::builtin();
+    let grass = BlockId::new("core::grass");
+    let grass_state = BlockState::from(grass.clone());
+
+    let source = MemoryStorage::new();
+    let mut chunk = ChunkData::new(IVec3::new(-1, 0, 2), Entity::PLACEHOLDER);
+    chunk.set_block(UVec3::new(1, 1, 1), &grass_state).unwrap();
+    // properties are not exported
+    let snowy = grass_state.with("snowy", "true");
+    chunk.set_block(UVec3::new(32, 20, 17), &snowy).unwrap();
+    let unknown = BlockState::parse("test::unknown").unwrap();
+    chunk.set_block(UVec3::new(5, 5, 5), &unknown).unwrap();
+    source
+        .save(&[(chunk.pos, &chunk.encode().unwrap()[..])])
+        .unwrap();
+
+    let exported = export(&source, &mapping, &dir).unwrap();
+    assert_eq!(exported.regions, 1);
+    assert_eq!(exported.columns, 4);
+    assert_eq!(exported.unmapped.get("test::unknown"), Some(&1));
+
+    let target = MemoryStorage::new();
+    let imported = import(&dir, &mapping, &target).unwrap();
+    assert_eq!(imported.columns, 4);
+    assert_eq!(imported.chunks, 1);
+
+    let bytes = target.load(chunk.pos).unwrap().unwrap();
+    let chunk = ChunkData::decode(&bytes).unwrap();
+    for pos in [[1, 1, 1], [32, 20, 17]] {
+        let index = PaddedChunkShape::linearize(pos);
+        assert_eq!(chunk.get_block_id(index), Some(&grass));
+    }
+    assert_eq!(chunk.get_block_id(PaddedChunkShape::linearize([5, 5, 5])), None);
+
+    fs::remove_dir_all(&dir).ok();
+}
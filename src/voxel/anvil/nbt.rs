@@ -0,0 +1,63 @@
+//! The parts of the Anvil chunk NBT that import and export care about.
+//! Unknown tags are skipped when reading.
+
+use std::collections::BTreeMap;
+
+use fastnbt::LongArray;
+use serde::{Deserialize, Serialize};
+
+/// A 16×16 column, the root compound of a chunk in a region file.
+/// Position tags default to 0 so chunks older than 1.18 (wrapped in `Level`)
+/// still parse far enough to check `data_version`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Column {
+    #[serde(rename = "DataVersion")]
+    pub data_version: i32,
+    #[serde(rename = "xPos", default)]
+    pub x_pos: i32,
+    #[serde(rename = "yPos", default)]
+    pub y_pos: i32,
+    #[serde(rename = "zPos", default)]
+    pub z_pos: i32,
+    #[serde(rename = "Status", default)]
+    pub status: String,
+    /// `false` makes the game recompute lighting on load
+    #[serde(rename = "isLightOn", default)]
+    pub is_light_on: bool,
+    #[serde(default)]
+    pub sections: Vec<Section>,
+}
+
+impl Column {
+    /// Chunks that are still being generated hold partial terrain.
+    pub fn is_full(&self) -> bool {
+        matches!(self.status.as_str(), "full" | "minecraft:full")
+    }
+}
+
+/// 16×16×16 blocks.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Section {
+    #[serde(rename = "Y")]
+    pub y: i8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_states: Option<PalettedStates<PaletteEntry>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub biomes: Option<PalettedStates<String>>,
+}
+
+/// `data` is absent when the palette has a single entry.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PalettedStates<T> {
+    pub palette: Vec<T>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<LongArray>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaletteEntry {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Properties", default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<BTreeMap<String, String>>,
+}
@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+/// Minecraft chunk columns per region side.
+pub const REGION_COLUMNS: usize = 32;
+
+const SECTOR: usize = 4096;
+const COMPRESSION_GZIP: u8 = 1;
+const COMPRESSION_ZLIB: u8 = 2;
+const COMPRESSION_NONE: u8 = 3;
+/// Set on the compression byte when the chunk lives in a separate `.mcc` file.
+const EXTERNAL_FLAG: u8 = 128;
+
+/// A `r.<x>.<z>.mca` file: an 8 KiB header of chunk locations and timestamps,
+/// followed by compressed chunk NBT in 4 KiB sectors.
+pub struct AnvilRegion {
+    file: File,
+    /// `offset in sectors << 8 | length in sectors`, 0 if the column was never generated
+    locations: [u32; REGION_COLUMNS * REGION_COLUMNS],
+}
+
+impl AnvilRegion {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("open region {}", path.display()))?;
+        let mut locations = [0; REGION_COLUMNS * REGION_COLUMNS];
+        // the game leaves empty region files around
+        if file.metadata()?.len() != 0 {
+            let mut header = vec![0; SECTOR];
+            file.read_exact(&mut header)
+                .with_context(|| format!("read region header {}", path.display()))?;
+            for (location, bytes) in locations.iter_mut().zip(header.chunks_exact(4)) {
+                *location = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+        }
+        Ok(AnvilRegion { file, locations })
+    }
+
+    /// `r.<x>.<z>.mca` -> region x, z
+    pub fn parse_file_name(path: &Path) -> anyhow::Result<(i32, i32)> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("invalid region path {}", path.display()))?;
+        let coords = name
+            .strip_prefix("r.")
+            .and_then(|name| name.strip_suffix(".mca"))
+            .and_then(|coords| coords.split_once('.'))
+            .and_then(|(x, z)| Some((x.parse().ok()?, z.parse().ok()?)));
+        coords.with_context(|| format!("`{name}` is not named r.<x>.<z>.mca"))
+    }
+
+    /// Uncompressed NBT of the column at `x`, `z` inside the region.
+    pub fn read_chunk(&mut self, x: usize, z: usize) -> anyhow::Result<Option<Vec<u8>>> {
+        let location = self.locations[x + z * REGION_COLUMNS];
+        if location == 0 {
+            return Ok(None);
+        }
+        self.file
+            .seek(SeekFrom::Start((location >> 8) as u64 * SECTOR as u64))?;
+        let mut header = [0; 5];
+        self.file.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        anyhow::ensure!(len >= 1, "chunk {x} {z} has length 0");
+        let mut compressed = vec![0; len - 1];
+        self.file.read_exact(&mut compressed)?;
+
+        let mut bytes = Vec::with_capacity(compressed.len() * 4);
+        match header[4] {
+            COMPRESSION_GZIP => {
+                GzDecoder::new(&*compressed).read_to_end(&mut bytes)?;
+            }
+            COMPRESSION_ZLIB => {
+                ZlibDecoder::new(&*compressed).read_to_end(&mut bytes)?;
+            }
+            COMPRESSION_NONE => bytes = compressed,
+            compression if compression & EXTERNAL_FLAG != 0 => {
+                anyhow::bail!("chunk {x} {z} is stored in an external .mcc file, not supported")
+            }
+            compression => anyhow::bail!("chunk {x} {z} uses unsupported compression {compression}"),
+        }
+        Ok(Some(bytes))
+    }
+
+    /// Write a whole region file. `chunks` are (x, z inside the region, uncompressed NBT).
+    pub fn write(path: &Path, chunks: &[(usize, usize, Vec<u8>)]) -> anyhow::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as u32);
+        let mut header = vec![0; SECTOR * 2];
+        let mut body = Vec::new();
+
+        for (x, z, nbt) in chunks {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(nbt)?;
+            let compressed = encoder.finish()?;
+
+            let start = body.len();
+            body.extend_from_slice(&(compressed.len() as u32 + 1).to_be_bytes());
+            body.push(COMPRESSION_ZLIB);
+            body.extend_from_slice(&compressed);
+            body.resize(body.len().next_multiple_of(SECTOR), 0);
+
+            let sectors = (body.len() - start) / SECTOR;
+            anyhow::ensure!(sectors <= 255, "chunk {x} {z} is larger than 1 MiB");
+            let offset = 2 + start / SECTOR;
+            let i = (x + z * REGION_COLUMNS) * 4;
+            header[i..i + 4].copy_from_slice(&((offset as u32) << 8 | sectors as u32).to_be_bytes());
+            header[SECTOR + i..SECTOR + i + 4].copy_from_slice(&timestamp.to_be_bytes());
+        }
+
+        let mut file =
+            File::create(path).with_context(|| format!("create region {}", path.display()))?;
+        file.write_all(&header)?;
+        file.write_all(&body)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::{Arc, Once};
+use std::time::{Duration, Instant};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::tasks::block_on;
+use bevy::tasks::futures_lite::future::poll_once;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+
+use super::chunk::ChunkData;
+use super::config::VoxelConfig;
+use super::saver::{ChunkSaver, SaveRequest};
+use super::storage::WorldStorage;
+use super::world::{LoadedChunks, VoxelWorld};
+
+/// Copy every dirty loaded chunk and clear its dirty flag and applied edits.
+/// Both are restored if the copy fails to encode or commit.
+fn take_dirty_chunks(world: &VoxelWorld) -> Vec<ChunkData> {
+    let mut dirty = Vec::new();
+    world.loaded_chunks.retain(|_, chunk| {
+        if chunk.dirty {
+            chunk.dirty = false;
+            if world.unhashed.remove(&chunk.pos).is_some() {
+                chunk.generate_hash();
+            }
+            chunk.compact_palette();
+            dirty.push(chunk.clone());
+            // handed to the saver with the copy
+            chunk.applied_edits.clear();
+        }
+        true
+    });
+    dirty
+}
+
+/// Encode dirty chunks in parallel and queue them on the saver.
+/// Returns the number of chunks queued.
+pub(super) fn save_dirty_chunks(world: &VoxelWorld, saver: &ChunkSaver) -> usize {
+    let requests = take_dirty_chunks(world)
+        .par_iter()
+        .filter_map(|chunk| match chunk.encode() {
+            Ok(bytes) => Some(SaveRequest {
+                pos: chunk.pos,
+                bytes,
+                applied_edits: chunk.applied_edits.clone(),
+            }),
+            Err(err) => {
+                tracing::error!("encode chunk {} failed: {:?}", chunk.pos, err);
+                // tried again next time
+                world.loaded_chunks.update(&chunk.pos, |_, loaded| {
+                    loaded.dirty = true;
+                    loaded
+                        .applied_edits
+                        .extend(chunk.applied_edits.iter().cloned());
+                });
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    let count = requests.len();
+    for request in requests {
+        saver.save(request);
+    }
+    count
+}
+
+/// Save dirty chunks every `VoxelConfig::autosave_interval`.
+pub fn autosave_chunks(
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+    config: Res<VoxelConfig>,
+    world: Res<VoxelWorld>,
+    saver: Res<ChunkSaver>,
+) {
+    let timer = timer
+        .get_or_insert_with(|| Timer::new(config.autosave_interval, TimerMode::Repeating));
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let _span = tracing::info_span!("profiling::{autosave}").entered();
+    let count = save_dirty_chunks(&world, &saver);
+    if count != 0 {
+        tracing::info!("autosaved {} chunks", count);
+    }
+}
+
+/// Save every dirty chunk and wait until the saver has committed them,
+/// including chunks that are still being unloaded.
+pub fn save_all_chunks(world: Res<VoxelWorld>, saver: Res<ChunkSaver>) {
+    let _span = tracing::info_span!("profiling::{save all chunks}").entered();
+    let count = save_dirty_chunks(&world, &saver);
+    wait_for_saver(&world, &saver);
+    tracing::info!("saved {} chunks", count);
+}
+
+/// Block until the saver committed everything queued and every unloading chunk.
+fn wait_for_saver(world: &VoxelWorld, saver: &ChunkSaver) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        saver.flush();
+        if world.saving_chunks.is_empty() {
+            break;
+        }
+        if Instant::now() > deadline {
+            tracing::warn!(
+                "gave up waiting for {} chunks to be saved",
+                world.saving_chunks.len()
+            );
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Closing the window does not leave `AppState::InGame`, so catch `AppExit` too.
+pub fn save_on_exit(
+    mut exit: EventReader<AppExit>,
+    world: Option<Res<VoxelWorld>>,
+    saver: Option<Res<ChunkSaver>>,
+) {
+    if exit.is_empty() {
+        return;
+    }
+    exit.clear();
+    if let (Some(world), Some(saver)) = (world, saver) {
+        save_all_chunks(world, saver);
+    }
+}
+
+/// What the panic hook saves, replaced by every `install_panic_hook`.
+static PANIC_SAVE: Mutex<Option<PanicSave>> = parking_lot::const_mutex(None);
+
+struct PanicSave {
+    chunks: Arc<LoadedChunks>,
+    positions: Arc<scc::HashSet<IVec3, ahash::RandomState>>,
+    storage: WorldStorage,
+}
+
+/// On panic, write dirty chunks straight to the storage, bypassing the saver.
+/// The hook is installed once, setting up another world only swaps what it saves.
+pub fn install_panic_hook(world: &VoxelWorld, storage: &WorldStorage) {
+    static INSTALL: Once = Once::new();
+
+    *PANIC_SAVE.lock() = Some(PanicSave {
+        chunks: world.loaded_chunks.clone(),
+        positions: world.loaded_positions.clone(),
+        storage: storage.clone(),
+    });
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            save_on_panic();
+        }));
+    });
+}
+
+/// Best effort: the panic may have happened while a chunk was locked, by this very
+/// thread even, so a chunk that cannot be read right away is skipped, not waited for.
+fn save_on_panic() {
+    // taken, so a second panic does not save again
+    let Some(save) = PANIC_SAVE.try_lock().and_then(|mut save| save.take()) else {
+        return;
+    };
+
+    let mut positions = Vec::new();
+    save.positions.scan(|pos| positions.push(*pos));
+    let mut skipped = 0;
+    let mut encoded = Vec::new();
+    for pos in positions {
+        let read = save
+            .chunks
+            .read_async(&pos, |_, chunk| chunk.dirty.then(|| chunk.encode()));
+        let Some(read) = block_on(poll_once(read)) else {
+            skipped += 1;
+            continue;
+        };
+        match read.flatten() {
+            Some(Ok(bytes)) => encoded.push((pos, bytes)),
+            Some(Err(err)) => tracing::error!("panic: encode chunk {} failed: {:?}", pos, err),
+            // unloaded or clean
+            None => {}
+        }
+    }
+
+    let batch = encoded
+        .iter()
+        .map(|(pos, bytes)| (*pos, &**bytes))
+        .collect::<Vec<_>>();
+    match save.storage.save(&batch) {
+        Ok(()) => tracing::info!(
+            "panic: saved {} modified chunks, skipped {} locked chunks",
+            batch.len(),
+            skipped
+        ),
+        Err(err) => tracing::error!("panic: saving modified chunks failed: {:?}", err),
+    }
+}
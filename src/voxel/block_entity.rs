@@ -0,0 +1,102 @@
+//! Structured data attached to a single voxel, such as chest contents, sign text or spawner settings.
+//! Each chunk keeps the entities of its blocks next to the voxels and saves them with it.
+
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+
+use anyhow::Context;
+use bevy::math::IVec3;
+use bevy::prelude::Res;
+use serde::{Deserialize, Serialize};
+
+use super::chunk::get_chunk_voxel_position;
+use super::voxel_block::BlockId;
+use super::world::{ChunkPositions, LoadedChunks, VoxelWorld};
+
+/// Anything a Lua table can hold, minus functions and userdata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+#[serde(untagged)]
+pub enum BlockValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<BlockValue>),
+    Map(BTreeMap<String, BlockValue>),
+}
+
+impl Hash for BlockValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        discriminant(self).hash(state);
+        match self {
+            BlockValue::Bool(value) => value.hash(state),
+            BlockValue::Int(value) => value.hash(state),
+            BlockValue::Float(value) => value.to_bits().hash(state),
+            BlockValue::String(value) => value.hash(state),
+            BlockValue::List(values) => values.hash(state),
+            BlockValue::Map(values) => values.hash(state),
+        }
+    }
+}
+
+/// Data of the block at one position. Replacing the voxel with another block drops it,
+/// changing only the state of the same block keeps it.
+#[derive(Debug, Clone, PartialEq, Hash, bincode::Encode, bincode::Decode)]
+pub struct BlockEntity {
+    /// the block it was created for
+    pub block: BlockId,
+    pub data: BlockValue,
+}
+
+/// Padded voxel index -> entity. Ordered, so encoding and hashing do not depend on insertion order.
+pub type BlockEntities = BTreeMap<u32, BlockEntity>;
+
+/// Entity of the block at `pos`, `None` if there is none or its chunk is not loaded.
+pub fn get(chunks: &LoadedChunks, pos: IVec3) -> Option<BlockEntity> {
+    let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+    chunks
+        .read(&chunk_pos, |_, chunk| {
+            chunk.block_entity(voxel_pos).cloned()
+        })
+        .flatten()
+}
+
+/// Attach `data` to the block at `pos`, returning the entity it replaced.
+/// Fails if the chunk is not loaded or the voxel is air.
+/// The chunk is added to `unhashed` for `rehash_chunks`.
+pub fn set(
+    chunks: &LoadedChunks,
+    unhashed: &ChunkPositions,
+    pos: IVec3,
+    data: BlockValue,
+) -> anyhow::Result<Option<BlockEntity>> {
+    let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+    let old = chunks
+        .update(&chunk_pos, |_, chunk| {
+            chunk.set_block_entity(voxel_pos, data)
+        })
+        .with_context(|| format!("chunk {} of block {} is not loaded", chunk_pos, pos))??;
+    unhashed.insert(chunk_pos).ok();
+    Ok(old)
+}
+
+pub fn remove(chunks: &LoadedChunks, unhashed: &ChunkPositions, pos: IVec3) -> Option<BlockEntity> {
+    let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+    let old = chunks
+        .update(&chunk_pos, |_, chunk| chunk.remove_block_entity(voxel_pos))
+        .flatten()?;
+    unhashed.insert(chunk_pos).ok();
+    Some(old)
+}
+
+/// Rehash the chunks whose block entities changed, once per frame however many
+/// changes a script made, like `flush_voxel_write_buffer` does for blocks.
+pub fn rehash_chunks(world: Res<VoxelWorld>) {
+    world.unhashed.retain(|chunk_pos| {
+        world
+            .loaded_chunks
+            .update(chunk_pos, |_, chunk| chunk.generate_hash());
+        false
+    });
+}
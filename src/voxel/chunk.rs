@@ -1,5 +1,5 @@
 use std::collections::VecDeque;
-use std::hash::{BuildHasher, Hash, Hasher};
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 use ahash::{AHashMap, HashSet};
@@ -11,32 +11,43 @@ use bevy::tasks::futures_lite::future::poll_once;
 use bevy::tasks::{block_on, AsyncComputeTaskPool};
 use ndshape::{ConstShape, ConstShape3u32};
 use parking_lot::RwLock;
-use redb::ReadableTable;
 
 use crate::core::registry::Registry;
 use crate::voxel::chunk_task::GenMeshTaskData;
 
+use super::block_entity::{BlockEntities, BlockEntity, BlockValue};
 use super::chunk_task::{BuildChunkTask, BuildChunkTaskInner, GenMeshTask};
 use super::config::VoxelConfig;
+use super::edit;
+use super::heightmap::{HeightmapKind, Heightmaps};
+use super::history::{BlockChange, EditGroup, EditHistory, HistoryStep, UnloadedBlocks};
 use super::material::VoxelMaterialHandle;
 use super::mesh::{MeshCache, MeshRef};
 use super::modifier::VoxelModifier;
-use super::palette::Palette;
-use super::storage::{WorldDatabase, CHUNKS};
+use super::origin::FloatingOrigin;
+use super::padding::{padding_neighbors, sync_padding};
+use super::palette::{Palette, PaletteError};
+use super::saver::{ChunkSaver, SaveRequest};
+use super::storage::WorldStorage;
 use super::textures::TextureMap;
-use super::voxel_block::{BlockId, VoxelBlock};
+use super::voxel_block::{BlockId, BlockState, VoxelBlock, AIR};
 use super::world::{VoxelWorld, WorldRoot};
 use super::VoxelWorldCamera;
 
+mod container;
 mod data;
+mod migration;
+
+pub use container::PalettedContainer;
+pub use migration::CHUNK_FORMAT_VERSION;
 
 pub const CHUNK_SIZE: u32 = 32;
 // with 1-voxel boundary padding. but....why?
-const PADDED_CHUNK_SIZE: u32 = CHUNK_SIZE + 2;
+pub const PADDED_CHUNK_SIZE: u32 = CHUNK_SIZE + 2;
 pub type PaddedChunkShape = ConstShape3u32<PADDED_CHUNK_SIZE, PADDED_CHUNK_SIZE, PADDED_CHUNK_SIZE>;
 
 //pub type VoxelArray = [VoxelBlock; PaddedChunkShape::SIZE as usize];
-pub type VoxelArray = Vec<VoxelBlock>;
+pub type VoxelArray = PalettedContainer;
 
 /* #[derive(Resource, Deref, DerefMut, Default)]
 pub struct ChunkLoadBuffer(#[deref] Vec<(IVec3, ChunkData)>); */
@@ -50,6 +61,94 @@ impl Default for ChunkLoadQueue {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkErrorKind {
+    /// the storage could not be read, the chunk stays unloaded and is read again when
+    /// it is next requested
+    Read,
+    /// the stored bytes did not decode, they were quarantined and the chunk regenerated
+    Corrupted,
+    /// the chunk could not be encoded or written. A loaded chunk is saved again later,
+    /// the changes of an unloaded one are lost
+    Save,
+}
+
+/// A chunk that could not be read, decoded or saved.
+#[derive(Event, Debug, Clone)]
+pub struct ChunkError {
+    pub pos: IVec3,
+    pub kind: ChunkErrorKind,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeCause {
+    /// `VoxelModifier::set`
+    Edit,
+    /// `VoxelModifier::apply`
+    Bulk,
+    Undo,
+    Redo,
+    /// an edit made while the chunk was unloaded, applied when it loaded
+    Deferred,
+}
+
+impl ChangeCause {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeCause::Edit => "edit",
+            ChangeCause::Bulk => "bulk",
+            ChangeCause::Undo => "undo",
+            ChangeCause::Redo => "redo",
+            ChangeCause::Deferred => "deferred",
+        }
+    }
+}
+
+/// A block that was placed, removed or changed state in a loaded chunk.
+/// Edits of unloaded chunks are reported once the chunk loads, as `ChangeCause::Deferred`.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct BlockChanged {
+    pub pos: IVec3,
+    /// air included
+    pub old: BlockState,
+    pub new: BlockState,
+    pub cause: ChangeCause,
+}
+
+/// Errors from async tasks and the saver thread, turned into `ChunkError` events.
+#[derive(Resource, Deref, DerefMut)]
+pub struct ChunkErrorQueue(#[deref] (kanal::Sender<ChunkError>, kanal::Receiver<ChunkError>));
+
+impl Default for ChunkErrorQueue {
+    fn default() -> Self {
+        ChunkErrorQueue(kanal::unbounded())
+    }
+}
+
+/// Log the error and queue a `ChunkError` event.
+pub fn report_chunk_error(
+    errors: &kanal::Sender<ChunkError>,
+    pos: IVec3,
+    kind: ChunkErrorKind,
+    error: &anyhow::Error,
+) {
+    tracing::error!("chunk {} {:?} error: {:?}", pos, kind, error);
+    errors
+        .send(ChunkError {
+            pos,
+            kind,
+            error: format!("{error:#}"),
+        })
+        .ok();
+}
+
+pub fn emit_chunk_errors(queue: Res<ChunkErrorQueue>, mut events: EventWriter<ChunkError>) {
+    while let Ok(Some(error)) = queue.1.try_recv() {
+        events.send(error);
+    }
+}
+
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct ChunkUpdateBuffer(#[deref] Vec<(IVec3, ChunkData)>);
 
@@ -59,9 +158,12 @@ pub struct ChunkUnloadBuffer(#[deref] Vec<IVec3>);
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct MeshCacheBuffer(#[deref] Vec<(u64, MeshRef)>);
 
+/// Edits parked for unloaded chunks, chunk pos -> block pos -> block.
+pub type ParkedEdits = AHashMap<IVec3, AHashMap<IVec3, BlockState>>;
+
 // modified but in unloaded chunk
 #[derive(Resource, Clone, Deref, DerefMut, Default)]
-pub struct ModifiedVoxels(#[deref] Arc<RwLock<AHashMap<IVec3, BlockId>>>);
+pub struct ModifiedVoxels(#[deref] Arc<RwLock<ParkedEdits>>);
 
 #[derive(Component)]
 #[component(storage = "SparseSet")]
@@ -71,9 +173,6 @@ pub struct NeedRemesh;
 #[component(storage = "SparseSet")]
 pub struct NeedUnload;
 
-#[derive(Component)]
-pub struct Modified();
-
 #[derive(Component)]
 pub struct Chunk {
     pub position: IVec3,
@@ -85,10 +184,18 @@ pub struct ChunkData {
     pub pos: IVec3,
     pub voxels: VoxelArray,
     pub solid_count: u32,
-    pub uniform: bool,
     pub hash: u64,
     pub entity: Entity,
     pub palette: Palette,
+    pub block_entities: BlockEntities,
+    /// `None` until `BuildChunkTaskInner::build` computes them, like for chunks saved
+    /// before there were heightmaps
+    pub heightmaps: Option<Heightmaps>,
+    /// modified since it was last saved
+    pub dirty: bool,
+    /// stored pending edits this chunk applied when it was built, dropped from the
+    /// storage together with the next save of the chunk
+    pub applied_edits: Vec<(IVec3, BlockState)>,
 }
 
 impl ChunkData {
@@ -97,11 +204,14 @@ impl ChunkData {
             pos,
             entity,
             //voxels: [const { VoxelBlock::Air }; PaddedChunkShape::SIZE as usize],
-            voxels: vec![VoxelBlock::Air; PaddedChunkShape::SIZE as usize],
+            voxels: PalettedContainer::new(),
             solid_count: 0,
-            uniform: false,
             hash: 0,
             palette: Palette::default(),
+            block_entities: BlockEntities::new(),
+            heightmaps: None,
+            dirty: false,
+            applied_edits: Vec::new(),
         }
     }
 
@@ -109,32 +219,171 @@ impl ChunkData {
         ChunkData::new(IVec3::ZERO, Entity::PLACEHOLDER)
     }
 
+    /// Hashes the block states the voxels resolve to, not their palette indices, so
+    /// compacting or reordering the palette leaves the hash alone.
     pub fn generate_hash(&mut self) {
-        let mut hasher = ahash::RandomState::new().build_hasher();
-        for block in &self.voxels {
-            block.hash(&mut hasher);
+        let states = (0..self.palette.len())
+            .map(|idx| {
+                let mut hasher = ahash::AHasher::default();
+                self.palette.block_state(idx as u16).hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect::<Vec<_>>();
+        let mut hasher = ahash::AHasher::default();
+        for block in self.voxels.iter() {
+            match block {
+                VoxelBlock::Air => None,
+                VoxelBlock::Solid(idx) => Some(states.get(idx as usize)),
+            }
+            .hash(&mut hasher);
         }
+        self.hash_block_entities(&mut hasher);
         self.hash = hasher.finish();
     }
 
+    /// Skipped when there are none, so chunks without block entities keep their hash.
+    fn hash_block_entities(&self, hasher: &mut impl Hasher) {
+        if !self.block_entities.is_empty() {
+            self.block_entities.hash(hasher);
+        }
+    }
+
     #[inline]
     /// `None` == Air
-    pub fn get_block_id(&self, index: u32) -> Option<&BlockId> {
-        let idx = match &self.voxels[index as usize] {
+    pub fn get_block_state(&self, index: u32) -> Option<&BlockState> {
+        let idx = match self.voxels.get(index as usize) {
             VoxelBlock::Air => return None,
-            VoxelBlock::Solid(idx) => *idx,
+            VoxelBlock::Solid(idx) => idx,
         };
-        self.palette.block_id(idx)
+        self.palette.block_state(idx)
     }
 
-    /* #[inline]
-    pub fn get_block_with_pos(&self, pos: UVec3) -> &VoxelBlock {
-        self.get_block(PaddedChunkShape::linearize(pos.to_array()))
-    } */
+    #[inline]
+    /// `None` == Air
+    pub fn get_block_id(&self, index: u32) -> Option<&BlockId> {
+        self.get_block_state(index).map(BlockState::id)
+    }
+
+    #[inline]
+    /// `pos` is in padded coordinates, `None` == Air
+    pub fn block_at(&self, pos: UVec3) -> Option<&BlockState> {
+        self.get_block_state(PaddedChunkShape::linearize(pos.to_array()))
+    }
+
+    /// The entity of the replaced block is dropped unless `state` is a state of the same block.
+    pub fn set_block(&mut self, pos: UVec3, state: &BlockState) -> Result<(), PaletteError> {
+        let voxel = self.voxel_block(state)?;
+        let index = PaddedChunkShape::linearize(pos.to_array()) as usize;
+        // `is_empty` decides whether the chunk gets a mesh at all
+        match (self.voxels.get(index).is_air(), voxel.is_air()) {
+            (true, false) => self.solid_count += 1,
+            (false, true) => self.solid_count = self.solid_count.saturating_sub(1),
+            _ => {}
+        }
+        self.voxels.set(index, voxel);
+        self.sync_block_entity(pos, state.id(), None);
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// `set_block`, then give the block the entity `registry` declares for it
+    /// and update the heightmaps.
+    pub fn place_block(
+        &mut self,
+        pos: UVec3,
+        state: &BlockState,
+        registry: &Registry,
+    ) -> Result<(), PaletteError> {
+        self.set_block(pos, state)?;
+        self.sync_block_entity(pos, state.id(), Some(registry));
+        let inner = pos.cmpge(UVec3::ONE).all() && pos.cmple(UVec3::splat(CHUNK_SIZE)).all();
+        if let (true, Some(mut heightmaps)) = (inner, self.heightmaps.take()) {
+            heightmaps.update(self, registry, pos, state);
+            self.heightmaps = Some(heightmaps);
+        }
+        Ok(())
+    }
+
+    /// Padded y of the highest block of `kind` in the column at padded `x`, `z`,
+    /// `None` if there is none or the heightmaps are not computed yet.
+    pub fn height(&self, kind: HeightmapKind, x: u32, z: u32) -> Option<u32> {
+        self.heightmaps.as_ref()?.get(kind, x, z)
+    }
+
+    /// The voxel at `pos` is now block `id`: drop an entity created for another block,
+    /// then create the default one `registry` declares for `id` if there is none.
+    pub fn sync_block_entity(&mut self, pos: UVec3, id: &BlockId, registry: Option<&Registry>) {
+        let index = PaddedChunkShape::linearize(pos.to_array());
+        if let Some(entity) = self.block_entities.get(&index) {
+            if &entity.block == id {
+                return;
+            }
+            self.block_entities.remove(&index);
+        }
+        if let Some(entity) = registry.and_then(|registry| registry.new_block_entity(id)) {
+            self.block_entities.insert(index, entity);
+        }
+    }
+
+    pub fn block_entity(&self, pos: UVec3) -> Option<&BlockEntity> {
+        self.block_entities
+            .get(&PaddedChunkShape::linearize(pos.to_array()))
+    }
+
+    /// Marks the chunk dirty. Like after `set_block`, call `generate_hash` once done
+    /// or unloading will think the chunk is unchanged.
+    pub fn block_entity_mut(&mut self, pos: UVec3) -> Option<&mut BlockEntity> {
+        let entity = self
+            .block_entities
+            .get_mut(&PaddedChunkShape::linearize(pos.to_array()))?;
+        self.dirty = true;
+        Some(entity)
+    }
+
+    /// Attach `data` to the block at `pos`, returning the entity it replaced. `Err` if the voxel is air.
+    pub fn set_block_entity(
+        &mut self,
+        pos: UVec3,
+        data: BlockValue,
+    ) -> anyhow::Result<Option<BlockEntity>> {
+        let index = PaddedChunkShape::linearize(pos.to_array());
+        let block = self
+            .get_block_id(index)
+            .with_context(|| format!("no block at {} in chunk {}", pos, self.pos))?
+            .clone();
+        self.dirty = true;
+        Ok(self.block_entities.insert(index, BlockEntity { block, data }))
+    }
 
-    pub fn set_block(&mut self, pos: UVec3, block_id: &BlockId) {
-        let voxel = self.palette.voxel_block(&block_id);
-        self.voxels[PaddedChunkShape::linearize(pos.to_array()) as usize] = voxel;
+    pub fn remove_block_entity(&mut self, pos: UVec3) -> Option<BlockEntity> {
+        let entity = self
+            .block_entities
+            .remove(&PaddedChunkShape::linearize(pos.to_array()))?;
+        self.dirty = true;
+        Some(entity)
+    }
+
+    /// `state` in this chunk's palette, added if missing. When the new entry would
+    /// widen the voxel container, dead entries are compacted away first.
+    pub fn voxel_block(&mut self, state: &BlockState) -> Result<VoxelBlock, PaletteError> {
+        if let Some(voxel) = self.palette.get(state) {
+            return Ok(voxel);
+        }
+        if self.palette.len() >= self.voxels.capacity() {
+            self.compact_palette();
+        }
+        self.palette.voxel_block(state)
+    }
+
+    /// Drop palette entries no voxel uses any more, remapping `VoxelBlock::Solid` indices.
+    /// Returns whether anything was dropped.
+    pub fn compact_palette(&mut self) -> bool {
+        let used = self.voxels.used_indices(self.palette.len());
+        let Some(remap) = self.palette.compact(&used) else {
+            return false;
+        };
+        self.voxels.remap(&remap);
+        true
     }
 
     #[inline]
@@ -147,44 +396,85 @@ impl ChunkData {
         self.solid_count == 0
     }
 
+    /// No visible faces: empty, or uniform including the padding, so every face
+    /// touches a voxel of the same block.
+    #[inline]
+    pub fn is_meshless(&self) -> bool {
+        self.is_empty() || self.voxels.as_uniform().is_some()
+    }
+
     /// `ChunkData` -> `bincode::encode` -> `zstd::encode` -> bytes
-    /// write 8 bytes hash to header.
+    /// write 8 bytes hash and 2 bytes format version to header.
     pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
         let inner = data::Inner {
             pos: self.pos,
             palette: &self.palette,
             voxels: &self.voxels,
+            block_entities: &self.block_entities,
+            heightmaps: &self.heightmaps,
         };
         let mut buffer = Vec::with_capacity(65536);
+        buffer.extend_from_slice(&self.hash.to_le_bytes());
+        buffer.extend_from_slice(&CHUNK_FORMAT_VERSION.to_le_bytes());
         let mut encoder = zstd::Encoder::new(&mut buffer, 0).with_context(|| "zstd")?;
-        
+
         bincode::encode_into_std_write(inner, &mut encoder, bincode::config::standard())
             .with_context(|| "bincode")?;
         encoder.finish()?;
-        buffer.splice(0..0, self.hash.to_le_bytes());
         Ok(buffer)
     }
 
-    /// bytes -> `zstd::decode` -> `bincode::decode` -> `ChunkData`
+    /// bytes -> `zstd::decode` -> `migration::migrate` -> `bincode::decode` -> `ChunkData`
     pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
-        let mut buffer = Vec::with_capacity(bytes.len() * 2);
-        // skip first 8 bytes as hash
-        let mut decoder = zstd::Decoder::new(&bytes[8..])?;
-        std::io::copy(&mut decoder, &mut buffer)?;
-
-        let (data, _) = bincode::borrow_decode_from_slice(&buffer, bincode::config::standard())?;
+        let (version, buffer) = Self::decompress(bytes)?;
+        let buffer = migration::migrate(version, buffer)?;
+        let (mut data, _): (Self, _) =
+            bincode::borrow_decode_from_slice(&buffer, bincode::config::standard())?;
+        data.hash = Self::read_header(bytes)?;
 
         Ok(data)
     }
 
+    /// Returns the format version and the decompressed (not yet migrated) payload.
+    pub fn decompress(bytes: &[u8]) -> anyhow::Result<(u16, Vec<u8>)> {
+        let (version, compressed) = Self::split_header(bytes)?;
+        let mut buffer = Vec::with_capacity(bytes.len() * 2);
+        let mut decoder = zstd::Decoder::new(compressed)?;
+        std::io::copy(&mut decoder, &mut buffer)?;
+        Ok((version, buffer))
+    }
+
     #[inline]
     pub fn read_header(bytes: &[u8]) -> anyhow::Result<u64> {
         Ok(u64::from_le_bytes(
-            bytes[..8]
-                .try_into()
+            bytes
+                .get(..8)
+                .and_then(|hash| hash.try_into().ok())
                 .with_context(|| "read chunk hash but data length < 8")?,
         ))
     }
+
+    #[inline]
+    pub fn read_version(bytes: &[u8]) -> anyhow::Result<u16> {
+        Self::split_header(bytes).map(|(version, _)| version)
+    }
+
+    /// Returns the format version and the compressed payload.
+    /// Chunks written before versioning have the zstd frame right after the hash,
+    /// they are version 0.
+    fn split_header(bytes: &[u8]) -> anyhow::Result<(u16, &[u8])> {
+        const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+        let rest = bytes
+            .get(8..)
+            .with_context(|| "read chunk header but data length < 8")?;
+        if rest.starts_with(&ZSTD_MAGIC) {
+            return Ok((0, rest));
+        }
+        let version = rest
+            .get(..2)
+            .with_context(|| "read chunk version but data length < 10")?;
+        Ok((u16::from_le_bytes([version[0], version[1]]), &rest[2..]))
+    }
 }
 
 /* impl Clone for ChunkData {
@@ -211,10 +501,10 @@ pub fn load_visit_chunks(
     load_queue: ResMut<ChunkLoadQueue>,
     config: Res<VoxelConfig>,
     world: Res<VoxelWorld>,
+    origin: Res<FloatingOrigin>,
     camera: Query<(&Camera, &GlobalTransform), With<VoxelWorldCamera>>,
 ) {
     let (camera, cam_gtf) = camera.single();
-    let cam_pos = cam_gtf.translation().as_ivec3();
 
     let spawning_distance = config.spawning_distance;
 
@@ -240,7 +530,7 @@ pub fn load_visit_chunks(
         let mut t = 0f32;
 
         while t < (spawning_distance * CHUNK_SIZE) as f32 {
-            let chunk_pos = current.as_ivec3() / CHUNK_SIZE as i32;
+            let chunk_pos = origin.world_chunk(current);
             if let Some(chunk) = world.loaded_chunks.get(&chunk_pos) {
                 if chunk.is_full() {
                     // If we hit a full chunk, we can stop the ray early
@@ -254,7 +544,7 @@ pub fn load_visit_chunks(
         }
 
         // We also queue the chunks closest to the camera to make sure they will always spawn early
-        let chunk_at_camera = cam_pos / CHUNK_SIZE as i32;
+        let chunk_at_camera = origin.world_chunk(cam_gtf.translation());
         for x in -1..=1 {
             for y in -1..=1 {
                 for z in -1..=1 {
@@ -336,12 +626,12 @@ pub fn mark_unload_chunks(
     mut unload_buffer: ResMut<ChunkUnloadBuffer>,
     config: Res<VoxelConfig>,
     world: Res<VoxelWorld>,
+    origin: Res<FloatingOrigin>,
     camera_gtf: Query<&GlobalTransform, With<VoxelWorldCamera>>,
     chunks: Query<&Chunk, (Without<GenMeshTask>, Without<NeedRemesh>)>,
 ) {
     let camera_gtf = camera_gtf.single();
-    let cam_pos = camera_gtf.translation().as_ivec3();
-    let cam_at_chunk = cam_pos / CHUNK_SIZE as i32;
+    let cam_at_chunk = origin.world_chunk(camera_gtf.translation());
 
     for chunk in chunks.iter() {
         if chunk.position.distance_squared(cam_at_chunk) > (config.spawning_distance as i32 ^ 2) - 1
@@ -359,11 +649,17 @@ pub fn mark_unload_chunks(
 pub fn unload_chunks(
     mut world: ResMut<VoxelWorld>,
     mut chunk_unload_buffer: ResMut<ChunkUnloadBuffer>,
-    storage: Res<WorldDatabase>,
+    storage: Res<WorldStorage>,
+    saver: Res<ChunkSaver>,
+    errors: Res<ChunkErrorQueue>,
 ) {
     if chunk_unload_buffer.is_empty() {
         return;
     }
+    // the saver is behind, keep the chunks loaded until it catches up
+    if saver.is_full() {
+        return;
+    }
 
     // load buffer (write) -> world.chunks
     /* for (pos, data) in chunk_load_buffer.drain(..) {
@@ -390,34 +686,60 @@ pub fn unload_chunks(
     let pool = AsyncComputeTaskPool::get();
     let mut need_rebuild_aabb = false;
     for pos in chunk_unload_buffer.drain(..) {
-        let (_, chunk) = world
+        let (_, mut chunk) = world
             .loaded_chunks
             .remove(&pos)
             .expect("remove chunk but it not in the world");
+        world.loaded_positions.remove(&pos);
         world.saving_chunks.insert(pos).ok();
+        let unhashed = world.unhashed.remove(&pos).is_some();
 
         let storage = storage.clone();
+        let saver = saver.clone();
         let saving_chunk = world.saving_chunks.clone();
+        let errors = errors.0.clone();
         pool.spawn(async move {
-            let _span = tracing::info_span!("profiling::{save chunk}").entered();
-            storage
-                .write(CHUNKS, |_, mut table| {
-                    let key = chunk.pos.to_array();
-                    // if hash of the chunk to be saved == existing chunk,
-                    // then skip. (This means that the chunk has not changed)
-                    if let Some(data) = table.get(&key)? {
-                        let hash = ChunkData::read_header(data.value())?;
-                        if hash == chunk.hash {
-                            return Ok(());
+            let bytes = {
+                let _span = tracing::info_span!("profiling::{save chunk}").entered();
+                if unhashed {
+                    chunk.generate_hash();
+                }
+                chunk.compact_palette();
+                // a clean chunk whose hash == existing chunk is skipped, it has not changed.
+                // A dirty one is always saved, whatever its hash says
+                let unchanged = !chunk.dirty
+                    && match storage.load_hash(chunk.pos) {
+                        Ok(hash) => hash == Some(chunk.hash),
+                        Err(err) => {
+                            tracing::warn!(
+                                "read hash of chunk {} failed, saving it anyway: {:?}",
+                                chunk.pos,
+                                err
+                            );
+                            false
                         }
+                    };
+                if unchanged {
+                    saving_chunk.remove(&chunk.pos);
+                    return;
+                }
+                match chunk.encode() {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        report_chunk_error(&errors, chunk.pos, ChunkErrorKind::Save, &err);
+                        saving_chunk.remove(&chunk.pos);
+                        return;
                     }
-                    let data = chunk.encode().with_context(|| "encode chunk data failed")?;
-                    table.insert(key, &*data)?;
-                    Ok(())
+                }
+            };
+            // removed from `saving_chunks` once the batch commits
+            saver
+                .save_async(SaveRequest {
+                    pos: chunk.pos,
+                    bytes,
+                    applied_edits: chunk.applied_edits,
                 })
-                .and_then(|v| v)
-                .expect("save chunk data failed");
-            saving_chunk.remove(&chunk.pos);
+                .await;
         })
         .detach();
 
@@ -455,30 +777,202 @@ pub fn flush_voxel_write_buffer(
     mut commands: Commands,
     world: Res<VoxelWorld>,
     modifier: Res<VoxelModifier>,
+    registry: Res<Registry>,
     load_queue: Res<ChunkLoadQueue>,
     modified: Res<ModifiedVoxels>,
+    storage: Res<WorldStorage>,
+    saver: Res<ChunkSaver>,
+    mut history: ResMut<EditHistory>,
+    mut events: EventWriter<BlockChanged>,
 ) {
-    if modifier.queue.1.is_empty() {
+    if modifier.queue.1.is_empty() && modifier.bulk.1.is_empty() && modifier.history.1.is_empty() {
         return;
     }
 
-    while let Some((block_pos, block_id)) = modifier.queue.1.try_recv().unwrap() {
-        let (chunk_pos, block_pos_in_chunk) = get_chunk_voxel_position(block_pos);
-        //modified.write().insert(block_pos, block);
+    let mut writer = BlockWriter {
+        world: &world,
+        registry: &registry,
+        load_queue: &load_queue,
+        modified: &modified,
+        unloaded: UnloadedBlocks::new(&storage, world.generator.as_ref()),
+        touched: HashSet::default(),
+        parked: Vec::new(),
+        changes: Vec::new(),
+    };
+
+    // the single edits of a frame are undone together
+    let mut group = EditGroup::default();
+    while let Some((block_pos, state)) = modifier.queue.1.try_recv().unwrap() {
+        let before = writer.set(&mut commands, block_pos, state.clone(), ChangeCause::Edit);
+        if before != state {
+            group.changes.push(BlockChange {
+                pos: block_pos,
+                before,
+                after: state,
+            });
+        }
+    }
+    history.push(group);
 
-        let exist = world
+    // one pass per chunk, however many of its blocks the edit covers
+    while let Some(edit) = modifier.bulk.1.try_recv().unwrap() {
+        if let Err(err) = edit.check() {
+            tracing::warn!("bulk edit dropped: {}", err);
+            continue;
+        }
+        let mut applied = edit::apply(&edit, &world.loaded_chunks, &registry);
+        for (_, entity) in applied.changed {
+            commands.entity(entity).try_insert(NeedRemesh);
+        }
+        writer
+            .changes
+            .extend(applied.changes.iter().map(|change| BlockChanged {
+                pos: change.pos,
+                old: change.before.clone(),
+                new: change.after.clone(),
+                cause: ChangeCause::Bulk,
+            }));
+        let mut queued = HashSet::default();
+        writer.unloaded.prefetch(
+            &modified,
+            applied.parked.iter().map(|(chunk_pos, _, _)| *chunk_pos),
+        );
+        for (chunk_pos, block_pos, state) in &applied.parked {
+            if queued.insert(*chunk_pos) {
+                load_queue.0 .0.send(*chunk_pos).unwrap();
+            }
+            let before = writer.unloaded.get(&modified, *block_pos);
+            if before != *state {
+                applied.changes.push(BlockChange {
+                    pos: *block_pos,
+                    before,
+                    after: state.clone(),
+                });
+            }
+        }
+        // parked once every block they replace is known, the lock is never held for a read
+        let mut modified = modified.write();
+        for (chunk_pos, block_pos, state) in applied.parked {
+            modified
+                .entry(chunk_pos)
+                .or_default()
+                .insert(block_pos, state.clone());
+            writer.unloaded.park(chunk_pos, block_pos, state.clone());
+            writer.parked.push((chunk_pos, block_pos, state));
+        }
+        history.push(EditGroup {
+            changes: applied.changes,
+        });
+    }
+
+    // after the edits of the same frame, so a script can undo what it just did
+    while let Some(step) = modifier.history.1.try_recv().unwrap() {
+        let (blocks, cause) = match step {
+            HistoryStep::Undo => (history.undo(), ChangeCause::Undo),
+            HistoryStep::Redo => (history.redo(), ChangeCause::Redo),
+        };
+        let Some(blocks) = blocks else {
+            tracing::info!("nothing to {:?}", step);
+            continue;
+        };
+        for (block_pos, state) in blocks {
+            writer.set(&mut commands, block_pos, state, cause);
+        }
+    }
+
+    // persisted too, so edits to far away chunks survive restarts
+    if !writer.parked.is_empty() {
+        saver.save_pending_edits(writer.parked);
+    }
+
+    events.send_batch(writer.changes);
+
+    // the hash keys the mesh cache and lets saving skip unchanged chunks
+    for chunk_pos in writer.touched {
+        world
             .loaded_chunks
-            .update(&chunk_pos, |_, data| {
-                data.set_block(block_pos_in_chunk, &block_id);
-                commands.entity(data.entity).try_insert(NeedRemesh);
-            })
-            .is_some();
-        if !exist {
-            // if chunk not loaded, queue it for loading
-            load_queue.0 .0.send(chunk_pos).unwrap();
-            // add to ModifiedVoxels, automatically applied when chunks are loaded
-            modified.write().insert(block_pos, block_id);
+            .update(&chunk_pos, |_, data| data.generate_hash());
+    }
+}
+
+/// Writes single blocks for `flush_voxel_write_buffer`, edits and undone blocks alike.
+struct BlockWriter<'a> {
+    world: &'a VoxelWorld,
+    registry: &'a Registry,
+    load_queue: &'a ChunkLoadQueue,
+    modified: &'a ModifiedVoxels,
+    unloaded: UnloadedBlocks<'a>,
+    /// loaded chunks to rehash
+    touched: HashSet<IVec3>,
+    /// edits of unloaded chunks to persist
+    parked: Vec<(IVec3, IVec3, BlockState)>,
+    /// blocks of loaded chunks that changed
+    changes: Vec<BlockChanged>,
+}
+
+impl BlockWriter<'_> {
+    /// Returns the block `state` replaced, air included.
+    fn set(
+        &mut self,
+        commands: &mut Commands,
+        block_pos: IVec3,
+        state: BlockState,
+        cause: ChangeCause,
+    ) -> BlockState {
+        let (chunk_pos, block_pos_in_chunk) = get_chunk_voxel_position(block_pos);
+        let before = self.world.loaded_chunks.update(&chunk_pos, |_, data| {
+            let before = data.block_at(block_pos_in_chunk).cloned();
+            if let Err(err) = data.place_block(block_pos_in_chunk, &state, self.registry) {
+                tracing::error!("set block in chunk {} failed: {}", chunk_pos, err);
+            }
+            commands.entity(data.entity).try_insert(NeedRemesh);
+            before.unwrap_or_else(|| BlockState::from(AIR.clone()))
+        });
+        if let Some(before) = &before {
+            self.touched.insert(chunk_pos);
+            if *before != state {
+                self.changes.push(BlockChanged {
+                    pos: block_pos,
+                    old: before.clone(),
+                    new: state.clone(),
+                    cause,
+                });
+            }
         }
+
+        // blocks on faces, edges and corners are copied into the padding of neighbors,
+        // unloaded ones catch up in `sync_padding` when they load
+        for (neighbor, padded_pos) in padding_neighbors(block_pos) {
+            let updated = self
+                .world
+                .loaded_chunks
+                .update(&neighbor, |_, data| {
+                    if let Err(err) = data.set_block(padded_pos, &state) {
+                        tracing::error!("set padding in chunk {} failed: {}", neighbor, err);
+                    }
+                    commands.entity(data.entity).try_insert(NeedRemesh);
+                })
+                .is_some();
+            if updated {
+                self.touched.insert(neighbor);
+            }
+        }
+
+        if let Some(before) = before {
+            return before;
+        }
+        // if chunk not loaded, queue it for loading
+        self.load_queue.0 .0.send(chunk_pos).unwrap();
+        // add to ModifiedVoxels, automatically applied when chunks are loaded
+        let before = self.unloaded.get(self.modified, block_pos);
+        self.unloaded.park(chunk_pos, block_pos, state.clone());
+        self.parked.push((chunk_pos, block_pos, state.clone()));
+        self.modified
+            .write()
+            .entry(chunk_pos)
+            .or_default()
+            .insert(block_pos, state);
+        before
     }
 }
 
@@ -512,7 +1006,7 @@ pub fn remesh_dirty_chunks(
         let task = pool.spawn(async move {
             //task_data.generate();
 
-            if task_data.chunk_data.is_empty() {
+            if task_data.chunk_data.is_meshless() {
                 return task_data;
             }
 
@@ -544,7 +1038,7 @@ pub fn spawn_mesh(
     for (chunk, mut task, transform) in &mut tasks {
         if let Some(task_data) = block_on(poll_once(&mut task.0)) {
             debug_assert_eq!(chunk.entity, task_data.chunk_data.entity);
-            if !task_data.chunk_data.is_empty() {
+            if !task_data.chunk_data.is_meshless() {
                 let mesh_ref = if let Some(mesh) = mesh_cache.get(task_data.chunk_data.hash) {
                     mesh.clone()
                 } else {
@@ -586,13 +1080,12 @@ pub fn spawn_mesh(
 /// (block pos in world) -> (chunk pos, block pos in chunk)
 #[inline]
 pub fn get_chunk_voxel_position(position: IVec3) -> (IVec3, UVec3) {
-    let chunk_position = IVec3 {
-        x: (position.x as f32 / CHUNK_SIZE as f32).floor() as i32,
-        y: (position.y as f32 / CHUNK_SIZE as f32).floor() as i32,
-        z: (position.z as f32 / CHUNK_SIZE as f32).floor() as i32,
-    };
-
-    let voxel_position = (position - chunk_position * CHUNK_SIZE as i32).as_uvec3() + 1;
+    // rounds toward negative infinity, so -1 is in chunk -1
+    let chunk_position = position.div_euclid(IVec3::splat(CHUNK_SIZE as i32));
+    let voxel_position = position
+        .rem_euclid(IVec3::splat(CHUNK_SIZE as i32))
+        .as_uvec3()
+        + 1;
 
     (chunk_position, voxel_position)
 }
@@ -601,9 +1094,12 @@ pub fn get_chunk_voxel_position(position: IVec3) -> (IVec3, UVec3) {
 pub fn load_chunks(
     mut commands: Commands,
     mut world: ResMut<VoxelWorld>,
-    storage: Res<WorldDatabase>,
+    storage: Res<WorldStorage>,
     load_queue: Res<ChunkLoadQueue>,
     modified: Res<ModifiedVoxels>,
+    registry: Res<Registry>,
+    errors: Res<ChunkErrorQueue>,
+    origin: Res<FloatingOrigin>,
 ) {
     if load_queue.1.is_empty() {
         return;
@@ -623,6 +1119,8 @@ pub fn load_chunks(
         let generator = world.generator.clone();
         let modified = modified.clone();
         let storage = Some(storage.clone());
+        let registry = registry.clone();
+        let errors = errors.0.clone();
         let task = pool.spawn(async move {
             let inner = BuildChunkTaskInner {
                 chunk_pos: pos,
@@ -630,6 +1128,8 @@ pub fn load_chunks(
                 modified_voxels: modified,
                 generator,
                 storage,
+                registry,
+                errors,
             };
             inner.build()
         });
@@ -638,7 +1138,7 @@ pub fn load_chunks(
             .entity(chunk.entity)
             .try_insert((
                 chunk,
-                Transform::from_translation(pos.as_vec3() * CHUNK_SIZE as f32 - 1f32),
+                Transform::from_translation(origin.chunk_translation(pos)),
             ))
             .insert(BuildChunkTask(task));
         // add chunk entity to world entity
@@ -658,19 +1158,62 @@ pub fn load_chunks(
 /// GenChunkTask done -> add chunkdata to world.loaded_chunks
 pub fn load_chunks_done(
     mut commands: Commands,
-    mut tasks: Query<&mut BuildChunkTask>,
+    mut tasks: Query<(&Chunk, &mut BuildChunkTask)>,
     world: Res<VoxelWorld>,
+    modified: Res<ModifiedVoxels>,
+    registry: Res<Registry>,
+    mut events: EventWriter<BlockChanged>,
 ) {
     tasks
         .iter_mut()
-        .filter_map(|mut task| block_on(poll_once(&mut task.0)))
-        .for_each(|data| {
-            let entity = data.entity;
-            world.loading_chunks.remove(&data.pos);
-            world.loaded_chunks.upsert(data.pos, data);
+        .filter_map(|(chunk, mut task)| Some((chunk, block_on(poll_once(&mut task.0))?)))
+        .for_each(|(chunk, built)| {
+            let Some((mut data, mut changes)) = built else {
+                // not readable, requested again by `load_visit_chunks` like any unloaded chunk
+                world.loading_chunks.remove(&chunk.position);
+                commands.entity(chunk.entity).despawn_recursive();
+                return;
+            };
+            let (pos, entity) = (data.pos, data.entity);
+            // parked while the chunk was still building, after the build took its edits
+            let late_edits = modified.write().remove(&pos).unwrap_or_default();
+            let rehash = !late_edits.is_empty();
+            for (block_pos, state) in late_edits {
+                let (_, voxel_pos) = get_chunk_voxel_position(block_pos);
+                let old = data
+                    .block_at(voxel_pos)
+                    .cloned()
+                    .unwrap_or_else(|| BlockState::from(AIR.clone()));
+                if let Err(err) = data.place_block(voxel_pos, &state, &registry) {
+                    tracing::error!("set block in chunk {} failed: {}", pos, err);
+                    continue;
+                }
+                if old != state {
+                    changes.push(BlockChanged {
+                        pos: block_pos,
+                        old,
+                        new: state.clone(),
+                        cause: ChangeCause::Deferred,
+                    });
+                }
+                // persisted by the saver, dropped from the storage once the chunk is saved
+                data.applied_edits.push((block_pos, state));
+            }
+            if rehash {
+                data.generate_hash();
+            }
+            world.loading_chunks.remove(&pos);
+            world.loaded_chunks.upsert(pos, data);
+            world.loaded_positions.insert(pos).ok();
             commands
                 .entity(entity)
                 .insert(NeedRemesh)
                 .remove::<BuildChunkTask>();
+            // once the chunk can be read, like edits of loaded chunks
+            events.send_batch(changes);
+            // neighbors may have been edited while this chunk was unloaded, or the other way around
+            for (_, neighbor) in sync_padding(&world.loaded_chunks, pos) {
+                commands.entity(neighbor).try_insert(NeedRemesh);
+            }
         });
 }
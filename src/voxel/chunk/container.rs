@@ -0,0 +1,400 @@
+use ndshape::ConstShape;
+
+use crate::voxel::voxel_block::VoxelBlock;
+
+use super::PaddedChunkShape;
+
+const LEN: usize = PaddedChunkShape::SIZE as usize;
+const MAX_BITS: u32 = 16;
+
+/// Palette index of every voxel in a padded chunk. Index 0 is air, as in `VoxelBlock`.
+///
+/// Chunks made of a single block (sky, deep stone) store just that index and
+/// expand on the first write of a different one. Otherwise the indices are packed
+/// into `u64` words with as many bits per entry as the largest index needs (1 to 16).
+/// Entries never span two words, like Minecraft's section storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalettedContainer(Storage);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Storage {
+    Uniform(u16),
+    Packed(Packed),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Packed {
+    bits: u32,
+    /// entries per word, cached because `get` divides by it
+    per_word: u32,
+    data: Vec<u64>,
+}
+
+impl PalettedContainer {
+    pub const LEN: usize = LEN;
+
+    /// All air.
+    pub fn new() -> Self {
+        PalettedContainer(Storage::Uniform(0))
+    }
+
+    /// Every voxel is `voxel`.
+    pub fn uniform(voxel: VoxelBlock) -> Self {
+        PalettedContainer(Storage::Uniform(to_raw(voxel)))
+    }
+
+    /// `indices` as `VoxelBlock` encodes them, one per voxel.
+    pub fn from_indices(indices: &[u16]) -> Self {
+        assert_eq!(indices.len(), LEN, "wrong number of voxels");
+        let max = indices.iter().copied().max().unwrap_or_default();
+        let mut packed = Packed::with_bits(bits_for(max));
+        for (i, idx) in indices.iter().enumerate() {
+            packed.set(i, *idx);
+        }
+        PalettedContainer(Storage::Packed(packed))
+    }
+
+    /// The voxel every entry has, `None` if the container is expanded.
+    #[inline]
+    pub fn as_uniform(&self) -> Option<VoxelBlock> {
+        match &self.0 {
+            Storage::Uniform(raw) => Some(from_raw(*raw)),
+            Storage::Packed(_) => None,
+        }
+    }
+
+    /// Bits per entry, 0 while uniform.
+    #[inline]
+    pub fn bits(&self) -> u32 {
+        match &self.0 {
+            Storage::Uniform(_) => 0,
+            Storage::Packed(packed) => packed.bits,
+        }
+    }
+
+    /// Palette indices that fit without widening.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        match &self.0 {
+            // expanding picks the width for the indices involved anyway
+            Storage::Uniform(raw) => 1 << bits_for(*raw),
+            Storage::Packed(packed) => 1 << packed.bits,
+        }
+    }
+
+    /// Bytes allocated for the packed entries.
+    #[inline]
+    pub fn heap_size(&self) -> usize {
+        match &self.0 {
+            Storage::Uniform(_) => 0,
+            Storage::Packed(packed) => packed.data.capacity() * std::mem::size_of::<u64>(),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> VoxelBlock {
+        match &self.0 {
+            Storage::Uniform(raw) => from_raw(*raw),
+            Storage::Packed(packed) => from_raw(packed.get(i)),
+        }
+    }
+
+    /// Expands a uniform container, or widens every entry, if `voxel` needs it.
+    #[inline]
+    pub fn set(&mut self, i: usize, voxel: VoxelBlock) {
+        let raw = to_raw(voxel);
+        match &mut self.0 {
+            Storage::Uniform(current) if *current == raw => {}
+            Storage::Uniform(current) => {
+                let mut packed = Packed::filled(bits_for(raw.max(*current)), *current);
+                packed.set(i, raw);
+                self.0 = Storage::Packed(packed);
+            }
+            Storage::Packed(packed) => {
+                if raw as u64 > packed.mask() {
+                    packed.repack(bits_for(raw));
+                }
+                packed.set(i, raw);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = VoxelBlock> + '_ {
+        self.iter_raw().map(from_raw)
+    }
+
+    /// One `VoxelBlock` per voxel, the layout `block_mesh` reads.
+    pub fn unpack(&self) -> Vec<VoxelBlock> {
+        match &self.0 {
+            Storage::Uniform(raw) => vec![from_raw(*raw); LEN],
+            Storage::Packed(_) => self.iter().collect(),
+        }
+    }
+
+    /// Turn back into a uniform container if every entry is the same.
+    /// Returns whether it is uniform now.
+    pub fn collapse(&mut self) -> bool {
+        let Storage::Packed(packed) = &self.0 else {
+            return true;
+        };
+        let first = packed.get(0);
+        if packed.iter().any(|raw| raw != first) {
+            return false;
+        }
+        self.0 = Storage::Uniform(first);
+        true
+    }
+
+    /// `used[idx]` is whether any voxel has palette index `idx`, for a palette of `len` entries.
+    pub fn used_indices(&self, len: usize) -> Vec<bool> {
+        let mut used = vec![false; len];
+        let mut mark = |raw: u16| {
+            if let Some(used) = used.get_mut(raw as usize) {
+                *used = true;
+            }
+        };
+        match &self.0 {
+            Storage::Uniform(raw) => mark(*raw),
+            Storage::Packed(packed) => packed.iter().for_each(mark),
+        }
+        used
+    }
+
+    /// Replace every palette index `idx` with `remap[idx]`, narrowing
+    /// the entries, or collapsing them, when the new indices allow it.
+    pub fn remap(&mut self, remap: &[u16]) {
+        // indices past the palette do not name a block, keep them as air
+        let map = |raw: u16| remap.get(raw as usize).copied().unwrap_or_default();
+        match &mut self.0 {
+            Storage::Uniform(raw) => *raw = map(*raw),
+            Storage::Packed(packed) => {
+                let max = remap.iter().copied().max().unwrap_or_default();
+                let mut remapped = Packed::with_bits(bits_for(max));
+                for (i, raw) in packed.iter().enumerate() {
+                    remapped.set(i, map(raw));
+                }
+                *packed = remapped;
+                self.collapse();
+            }
+        }
+    }
+
+    fn iter_raw(&self) -> impl Iterator<Item = u16> + '_ {
+        // one iterator type for both: a uniform container is no words and a tail
+        let (packed, tail, tail_len) = match &self.0 {
+            Storage::Uniform(raw) => (None, *raw, LEN),
+            Storage::Packed(packed) => (Some(packed), 0, 0),
+        };
+        packed
+            .into_iter()
+            .flat_map(Packed::iter)
+            .chain(std::iter::repeat(tail).take(tail_len))
+    }
+}
+
+impl Default for PalettedContainer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Packed {
+    fn with_bits(bits: u32) -> Self {
+        Packed {
+            bits,
+            per_word: 64 / bits,
+            data: vec![0; words_for(bits)],
+        }
+    }
+
+    /// Every entry set to `raw`.
+    fn filled(bits: u32, raw: u16) -> Self {
+        let mut packed = Self::with_bits(bits);
+        let word = (0..packed.per_word).fold(0, |word, j| word | (raw as u64) << (j * bits));
+        packed.data.fill(word);
+        packed
+    }
+
+    #[inline]
+    fn mask(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+
+    #[inline]
+    fn locate(&self, i: usize) -> (usize, u32) {
+        debug_assert!(i < LEN, "voxel index {i} out of bounds");
+        let per_word = self.per_word as usize;
+        (i / per_word, (i % per_word) as u32 * self.bits)
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> u16 {
+        let (word, shift) = self.locate(i);
+        ((self.data[word] >> shift) & self.mask()) as u16
+    }
+
+    #[inline]
+    fn set(&mut self, i: usize, raw: u16) {
+        let (word, shift) = self.locate(i);
+        let mask = self.mask() << shift;
+        self.data[word] = (self.data[word] & !mask) | ((raw as u64) << shift);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let (bits, per_word, mask) = (self.bits, self.per_word, self.mask());
+        self.data
+            .iter()
+            .flat_map(move |word| (0..per_word).map(move |j| ((word >> (j * bits)) & mask) as u16))
+            .take(LEN)
+    }
+
+    fn repack(&mut self, bits: u32) {
+        let mut repacked = Self::with_bits(bits);
+        for (i, raw) in self.iter().enumerate() {
+            repacked.set(i, raw);
+        }
+        *self = repacked;
+    }
+}
+
+#[inline]
+fn words_for(bits: u32) -> usize {
+    LEN.div_ceil((64 / bits) as usize)
+}
+
+#[inline]
+fn bits_for(max: u16) -> u32 {
+    (u16::BITS - max.leading_zeros()).max(1)
+}
+
+#[inline]
+fn to_raw(voxel: VoxelBlock) -> u16 {
+    match voxel {
+        VoxelBlock::Air => 0,
+        VoxelBlock::Solid(idx) => idx,
+    }
+}
+
+#[inline]
+fn from_raw(raw: u16) -> VoxelBlock {
+    if raw == 0 {
+        VoxelBlock::Air
+    } else {
+        VoxelBlock::Solid(raw)
+    }
+}
+
+/// bits per entry, then the words as little endian bytes.
+/// A uniform container is 0 bits per entry, then its index.
+impl bincode::Encode for PalettedContainer {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        match &self.0 {
+            Storage::Uniform(raw) => {
+                0u8.encode(encoder)?;
+                raw.encode(encoder)
+            }
+            Storage::Packed(packed) => {
+                (packed.bits as u8).encode(encoder)?;
+                let bytes = packed
+                    .data
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .collect::<Vec<u8>>();
+                bytes.encode(encoder)
+            }
+        }
+    }
+}
+
+impl bincode::Decode for PalettedContainer {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let bits = u8::decode(decoder)? as u32;
+        if bits == 0 {
+            return Ok(PalettedContainer(Storage::Uniform(u16::decode(decoder)?)));
+        }
+        let bytes = Vec::<u8>::decode(decoder)?;
+        if bits > MAX_BITS || bytes.len() != words_for(bits) * 8 {
+            return Err(bincode::error::DecodeError::OtherString(format!(
+                "invalid voxel container: {} bits per entry, {} bytes",
+                bits,
+                bytes.len()
+            )));
+        }
+        let data = bytes
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        Ok(PalettedContainer(Storage::Packed(Packed {
+            bits,
+            per_word: 64 / bits,
+            data,
+        })))
+    }
+}
+
+bincode::impl_borrow_decode!(PalettedContainer);
+
+#[test]
+fn test_grow_keeps_entries() {
+    let mut container = PalettedContainer::new();
+    assert_eq!(container.bits(), 0);
+    container.set(0, VoxelBlock::Solid(1));
+    container.set(LEN - 1, VoxelBlock::Solid(1));
+    assert_eq!(container.bits(), 1);
+
+    // every width up to 16 bits
+    for (n, i) in (2..=u16::MAX).step_by(97).zip((1..LEN - 1).step_by(5)) {
+        container.set(i, VoxelBlock::Solid(n));
+        assert_eq!(container.get(i), VoxelBlock::Solid(n));
+    }
+    assert_eq!(container.bits(), 16);
+    assert_eq!(container.get(0), VoxelBlock::Solid(1));
+    assert_eq!(container.get(LEN - 1), VoxelBlock::Solid(1));
+    assert_eq!(container.get(2), VoxelBlock::Air);
+
+    let unpacked = container.unpack();
+    assert_eq!(unpacked.len(), LEN);
+    for (i, voxel) in unpacked.iter().enumerate() {
+        assert_eq!(*voxel, container.get(i));
+    }
+
+    let bytes = bincode::encode_to_vec(&container, bincode::config::standard()).unwrap();
+    let (decoded, _): (PalettedContainer, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    assert_eq!(decoded, container);
+}
+
+#[test]
+fn test_uniform_expand_collapse() {
+    let stone = VoxelBlock::Solid(3);
+    let mut container = PalettedContainer::uniform(stone);
+    assert_eq!(container.heap_size(), 0);
+    container.set(17, stone);
+    assert_eq!(container.as_uniform(), Some(stone));
+
+    // the first different write expands, keeping every other voxel
+    container.set(17, VoxelBlock::Air);
+    assert_eq!(container.as_uniform(), None);
+    assert_eq!(container.bits(), 2);
+    assert_eq!(container.get(17), VoxelBlock::Air);
+    assert!(container
+        .iter()
+        .enumerate()
+        .all(|(i, voxel)| (i == 17) == voxel.is_air()));
+
+    assert!(!container.collapse());
+    container.set(17, stone);
+    assert!(container.collapse());
+    assert_eq!(container, PalettedContainer::uniform(stone));
+
+    let bytes = bincode::encode_to_vec(&container, bincode::config::standard()).unwrap();
+    assert!(bytes.len() <= 4);
+    let (decoded, _): (PalettedContainer, _) =
+        bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    assert_eq!(decoded, container);
+}
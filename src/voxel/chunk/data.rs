@@ -2,16 +2,18 @@ use bevy::math::IVec3;
 use bevy::prelude::Entity;
 use bincode::Encode;
 
+use crate::voxel::block_entity::BlockEntities;
+use crate::voxel::heightmap::Heightmaps;
 use crate::voxel::palette::Palette;
-use crate::voxel::voxel_block::VoxelBlock;
-
-use super::ChunkData;
+use super::{ChunkData, PalettedContainer};
 
 #[derive(Debug, PartialEq)]
 pub struct Inner<'a> {
     pub pos: IVec3,
     pub palette: &'a Palette,
-    pub voxels: &'a [VoxelBlock],
+    pub voxels: &'a PalettedContainer,
+    pub block_entities: &'a BlockEntities,
+    pub heightmaps: &'a Option<Heightmaps>,
 }
 
 impl<'a> Encode for Inner<'a> {
@@ -22,6 +24,8 @@ impl<'a> Encode for Inner<'a> {
         self.pos.to_array().encode(encoder)?;
         self.palette.encode(encoder)?;
         self.voxels.encode(encoder)?;
+        self.block_entities.encode(encoder)?;
+        self.heightmaps.encode(encoder)?;
         Ok(())
     }
 }
@@ -34,15 +38,21 @@ impl<'a> bincode::BorrowDecode<'a> for ChunkData {
         let pos = IVec3::from_array(<_>::borrow_decode(decoder)?);
         let palette = <_>::borrow_decode(decoder)?;
         let voxels = <_>::borrow_decode(decoder)?;
+        let block_entities = <_>::borrow_decode(decoder)?;
+        // anything unexpected is computed again when the chunk is built
+        let heightmaps = Option::<Heightmaps>::borrow_decode(decoder)?.filter(Heightmaps::is_valid);
 
         Ok(ChunkData {
             pos,
             voxels,
             solid_count: 0,
-            uniform: false,
             hash: 0,
             entity: Entity::PLACEHOLDER,
             palette,
+            block_entities,
+            heightmaps,
+            dirty: false,
+            applied_edits: Vec::new(),
         })
     }
 }
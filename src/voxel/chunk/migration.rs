@@ -0,0 +1,452 @@
+use anyhow::Context;
+
+use crate::voxel::block_entity::BlockEntities;
+use crate::voxel::heightmap::Heightmaps;
+
+use super::PalettedContainer;
+
+/// Version written into the header of every newly encoded chunk.
+pub const CHUNK_FORMAT_VERSION: u16 = 5;
+
+/// Upgrades a decompressed payload by exactly one version.
+type Migration = fn(Vec<u8>) -> anyhow::Result<Vec<u8>>;
+
+/// `MIGRATIONS[n]` upgrades a version `n` payload to version `n + 1`.
+/// Bumping `CHUNK_FORMAT_VERSION` without adding a step here fails to compile.
+const MIGRATIONS: [Migration; CHUNK_FORMAT_VERSION as usize] =
+    [v0_to_v1, v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5];
+
+/// Upgrade a decompressed payload written with `version` to `CHUNK_FORMAT_VERSION`.
+pub fn migrate(mut version: u16, mut payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        version <= CHUNK_FORMAT_VERSION,
+        "chunk format version {} is newer than supported version {}",
+        version,
+        CHUNK_FORMAT_VERSION
+    );
+    while version < CHUNK_FORMAT_VERSION {
+        payload = MIGRATIONS[version as usize](payload)
+            .with_context(|| format!("migrate chunk from version {version}"))?;
+        version += 1;
+    }
+    Ok(payload)
+}
+
+/// Version 0 only lacked the version tag in the header, the payload is unchanged.
+fn v0_to_v1(payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    Ok(payload)
+}
+
+/// Version 1 stored every voxel as a varint `u16` palette index,
+/// version 2 bit-packs them into a `PalettedContainer`.
+fn v1_to_v2(payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let config = bincode::config::standard();
+    let ((pos, palette, voxels), _): (([i32; 3], Vec<String>, Vec<u16>), _) =
+        bincode::decode_from_slice(&payload, config).with_context(|| "decode version 1")?;
+    anyhow::ensure!(
+        voxels.len() == PalettedContainer::LEN,
+        "chunk has {} voxels, expected {}",
+        voxels.len(),
+        PalettedContainer::LEN
+    );
+    let voxels = PalettedContainer::from_indices(&voxels);
+    Ok(bincode::encode_to_vec((pos, palette, voxels), config)?)
+}
+
+/// Version 3 added uniform containers (0 bits per entry), version 2 payloads never use them.
+fn v2_to_v3(payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    Ok(payload)
+}
+
+/// Version 4 appended the block entities of the chunk, older chunks have none.
+fn v3_to_v4(mut payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    bincode::encode_into_std_write(
+        BlockEntities::new(),
+        &mut payload,
+        bincode::config::standard(),
+    )?;
+    Ok(payload)
+}
+
+/// Version 5 appended the heightmaps, which older chunks get once they are built.
+fn v4_to_v5(mut payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    bincode::encode_into_std_write(
+        Option::<Heightmaps>::None,
+        &mut payload,
+        bincode::config::standard(),
+    )?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+fn encode_v0(pos: [i32; 3], palette: &[&'static str], voxels: &[u16], hash: u64) -> Vec<u8> {
+    let payload = bincode::encode_to_vec((pos, palette, voxels), bincode::config::standard())
+        .unwrap();
+    let mut bytes = zstd::encode_all(&*payload, 0).unwrap();
+    bytes.splice(0..0, hash.to_le_bytes());
+    bytes
+}
+
+/// Hash and version header, then the zstd frame of `payload`.
+#[cfg(test)]
+fn encode_versioned(version: u16, payload: &[u8], hash: u64) -> Vec<u8> {
+    let mut bytes = hash.to_le_bytes().to_vec();
+    bytes.extend(version.to_le_bytes());
+    bytes.extend(zstd::encode_all(payload, 0).unwrap());
+    bytes
+}
+
+#[cfg(test)]
+fn encode_v2(pos: [i32; 3], palette: &[&'static str], voxels: &[u16], hash: u64) -> Vec<u8> {
+    let voxels = PalettedContainer::from_indices(voxels);
+    let payload = bincode::encode_to_vec((pos, palette, voxels), bincode::config::standard())
+        .unwrap();
+    encode_versioned(2, &payload, hash)
+}
+
+/// Like version 2, with uniform voxels collapsed.
+#[cfg(test)]
+fn encode_v3(pos: [i32; 3], palette: &[&'static str], voxels: &[u16], hash: u64) -> Vec<u8> {
+    let mut voxels = PalettedContainer::from_indices(voxels);
+    voxels.collapse();
+    let payload = bincode::encode_to_vec((pos, palette, voxels), bincode::config::standard())
+        .unwrap();
+    encode_versioned(3, &payload, hash)
+}
+
+/// Like version 3, followed by the block entities.
+#[cfg(test)]
+fn encode_v4(
+    pos: [i32; 3],
+    palette: &[&'static str],
+    voxels: &[u16],
+    block_entities: &BlockEntities,
+    hash: u64,
+) -> Vec<u8> {
+    let mut voxels = PalettedContainer::from_indices(voxels);
+    voxels.collapse();
+    let payload = bincode::encode_to_vec(
+        (pos, palette, voxels, block_entities),
+        bincode::config::standard(),
+    )
+    .unwrap();
+    encode_versioned(4, &payload, hash)
+}
+
+#[test]
+fn test_decode_v0() {
+    use bevy::math::IVec3;
+    use ndshape::ConstShape;
+
+    use super::{ChunkData, PaddedChunkShape};
+    use crate::voxel::voxel_block::{BlockId, VoxelBlock};
+
+    let mut voxels = vec![0u16; PaddedChunkShape::SIZE as usize];
+    voxels[0] = 1;
+    voxels[42] = 1;
+    let bytes = encode_v0([1, -2, 3], &["core::air", "core::grass"], &voxels, 114514);
+
+    assert_eq!(ChunkData::read_version(&bytes).unwrap(), 0);
+    assert_eq!(ChunkData::read_header(&bytes).unwrap(), 114514);
+
+    let chunk = ChunkData::decode(&bytes).unwrap();
+    assert_eq!(chunk.pos, IVec3::new(1, -2, 3));
+    assert_eq!(chunk.voxels.get(0), VoxelBlock::Solid(1));
+    assert_eq!(chunk.voxels.get(1), VoxelBlock::Air);
+    assert_eq!(chunk.voxels.get(42), VoxelBlock::Solid(1));
+    assert_eq!(chunk.palette.block_id(1), Some(&BlockId::new("core::grass")));
+    assert_eq!(chunk.heightmaps, None);
+}
+
+#[test]
+fn test_decode_v1() {
+    use ndshape::ConstShape;
+
+    use super::{ChunkData, PaddedChunkShape};
+    use crate::voxel::voxel_block::VoxelBlock;
+
+    let mut voxels = vec![0u16; PaddedChunkShape::SIZE as usize];
+    voxels[7] = 2;
+    voxels[PaddedChunkShape::SIZE as usize - 1] = 1;
+    let mut bytes = encode_v0(
+        [0, 0, 0],
+        &["core::air", "core::grass", "core::stone"],
+        &voxels,
+        1,
+    );
+    bytes.splice(8..8, 1u16.to_le_bytes());
+    assert_eq!(ChunkData::read_version(&bytes).unwrap(), 1);
+
+    let chunk = ChunkData::decode(&bytes).unwrap();
+    assert_eq!(chunk.voxels.bits(), 2);
+    assert_eq!(chunk.voxels.get(7), VoxelBlock::Solid(2));
+    assert_eq!(chunk.voxels.get(8), VoxelBlock::Air);
+    assert_eq!(
+        chunk.voxels.get(PaddedChunkShape::SIZE as usize - 1),
+        VoxelBlock::Solid(1)
+    );
+}
+
+#[test]
+fn test_decode_v2() {
+    use bevy::math::IVec3;
+    use ndshape::ConstShape;
+
+    use super::{ChunkData, PaddedChunkShape};
+    use crate::voxel::voxel_block::{BlockId, VoxelBlock};
+
+    let mut voxels = vec![0u16; PaddedChunkShape::SIZE as usize];
+    voxels[3] = 1;
+    voxels[1000] = 2;
+    let palette = ["core::air", "core::grass", "core::stone"];
+    let bytes = encode_v2([5, 0, -5], &palette, &voxels, 77);
+    assert_eq!(ChunkData::read_version(&bytes).unwrap(), 2);
+    assert_eq!(ChunkData::read_header(&bytes).unwrap(), 77);
+
+    let chunk = ChunkData::decode(&bytes).unwrap();
+    assert_eq!(chunk.pos, IVec3::new(5, 0, -5));
+    assert_eq!(chunk.voxels.bits(), 2);
+    assert_eq!(chunk.voxels.get(3), VoxelBlock::Solid(1));
+    assert_eq!(chunk.voxels.get(1000), VoxelBlock::Solid(2));
+    assert_eq!(chunk.voxels.get(1001), VoxelBlock::Air);
+    assert_eq!(chunk.palette.block_id(2), Some(&BlockId::new("core::stone")));
+    assert!(chunk.block_entities.is_empty());
+    assert_eq!(chunk.heightmaps, None);
+}
+
+#[test]
+fn test_decode_v3() {
+    use ndshape::ConstShape;
+
+    use super::{ChunkData, PaddedChunkShape};
+    use crate::voxel::voxel_block::{BlockId, VoxelBlock};
+
+    let voxels = vec![1u16; PaddedChunkShape::SIZE as usize];
+    let bytes = encode_v3([0, -1, 0], &["core::air", "core::stone"], &voxels, 3);
+    assert_eq!(ChunkData::read_version(&bytes).unwrap(), 3);
+
+    let chunk = ChunkData::decode(&bytes).unwrap();
+    assert_eq!(chunk.voxels.as_uniform(), Some(VoxelBlock::Solid(1)));
+    assert_eq!(chunk.palette.block_id(1), Some(&BlockId::new("core::stone")));
+    assert!(chunk.block_entities.is_empty());
+    assert_eq!(chunk.heightmaps, None);
+}
+
+#[test]
+fn test_decode_v4() {
+    use bevy::math::UVec3;
+    use ndshape::ConstShape;
+
+    use super::{ChunkData, PaddedChunkShape};
+    use crate::voxel::block_entity::{BlockEntity, BlockValue};
+    use crate::voxel::voxel_block::{BlockId, VoxelBlock};
+
+    let index = PaddedChunkShape::linearize([4, 5, 6]);
+    let mut voxels = vec![0u16; PaddedChunkShape::SIZE as usize];
+    voxels[index as usize] = 1;
+    let block_entities = BlockEntities::from([(
+        index,
+        BlockEntity {
+            block: BlockId::new("core::chest"),
+            data: BlockValue::String("loot".to_owned()),
+        },
+    )]);
+    let bytes = encode_v4(
+        [2, 2, 2],
+        &["core::air", "core::chest"],
+        &voxels,
+        &block_entities,
+        9,
+    );
+    assert_eq!(ChunkData::read_version(&bytes).unwrap(), 4);
+
+    let chunk = ChunkData::decode(&bytes).unwrap();
+    assert_eq!(chunk.voxels.get(index as usize), VoxelBlock::Solid(1));
+    assert_eq!(chunk.block_entities, block_entities);
+    assert_eq!(
+        chunk.block_entity(UVec3::new(4, 5, 6)).unwrap().data,
+        BlockValue::String("loot".to_owned())
+    );
+    // computed once the chunk is built
+    assert_eq!(chunk.heightmaps, None);
+}
+
+#[test]
+fn test_decode_truncated() {
+    use super::ChunkData;
+
+    assert!(ChunkData::read_header(&[1, 2, 3]).is_err());
+    assert!(ChunkData::decode(&[1, 2, 3]).is_err());
+    assert!(ChunkData::decode(&[]).is_err());
+}
+
+#[test]
+fn test_roundtrip_current() {
+    use bevy::math::{IVec3, UVec3};
+    use bevy::prelude::Entity;
+
+    use super::ChunkData;
+    use crate::core::registry::Registry;
+    use crate::voxel::voxel_block::BlockState;
+
+    let mut chunk = ChunkData::new(IVec3::new(-4, 0, 7), Entity::PLACEHOLDER);
+    let log = BlockState::parse("core::log[axis=x]").unwrap();
+    chunk.set_block(UVec3::new(1, 2, 3), &log).unwrap();
+    chunk.heightmaps = Some(Heightmaps::compute(&chunk, &Registry::new()));
+    chunk.generate_hash();
+
+    let bytes = chunk.encode().unwrap();
+    assert_eq!(ChunkData::read_version(&bytes).unwrap(), CHUNK_FORMAT_VERSION);
+    assert_eq!(ChunkData::read_header(&bytes).unwrap(), chunk.hash);
+
+    let decoded = ChunkData::decode(&bytes).unwrap();
+    assert_eq!(decoded.pos, chunk.pos);
+    assert_eq!(decoded.voxels, chunk.voxels);
+    assert_eq!(decoded.palette, chunk.palette);
+    assert_eq!(decoded.palette.block_state(1), Some(&log));
+    assert_eq!(decoded.heightmaps, chunk.heightmaps);
+}
+
+#[test]
+fn test_compact_palette_roundtrip() {
+    use bevy::math::{IVec3, UVec3};
+    use bevy::prelude::Entity;
+    use ndshape::ConstShape;
+
+    use super::{ChunkData, PaddedChunkShape};
+    use crate::voxel::voxel_block::BlockState;
+
+    let (grass, dirt, stone) = (
+        BlockState::parse("core::grass").unwrap(),
+        BlockState::parse("core::dirt").unwrap(),
+        BlockState::parse("core::stone").unwrap(),
+    );
+    let mut chunk = ChunkData::new(IVec3::ZERO, Entity::PLACEHOLDER);
+    chunk.set_block(UVec3::new(1, 1, 1), &grass).unwrap();
+    chunk.set_block(UVec3::new(2, 2, 2), &dirt).unwrap();
+    chunk.set_block(UVec3::new(3, 3, 3), &stone).unwrap();
+    // grass is no longer used anywhere
+    chunk.set_block(UVec3::new(1, 1, 1), &stone).unwrap();
+    assert_eq!(chunk.palette.len(), 4);
+    chunk.generate_hash();
+    let hash = chunk.hash;
+
+    assert!(chunk.compact_palette());
+    assert!(!chunk.compact_palette());
+    assert_eq!(chunk.palette.len(), 3);
+    assert_eq!(chunk.palette.get(&grass), None);
+    // same blocks, same hash, whatever their palette indices
+    chunk.generate_hash();
+    assert_eq!(chunk.hash, hash);
+    let mut fresh = ChunkData::new(IVec3::ZERO, Entity::PLACEHOLDER);
+    fresh.set_block(UVec3::new(2, 2, 2), &dirt).unwrap();
+    fresh.set_block(UVec3::new(1, 1, 1), &stone).unwrap();
+    fresh.set_block(UVec3::new(3, 3, 3), &stone).unwrap();
+    fresh.generate_hash();
+    assert_eq!(fresh.hash, hash);
+
+    let decoded = ChunkData::decode(&chunk.encode().unwrap()).unwrap();
+    assert_eq!(decoded.palette, chunk.palette);
+    let block_at =
+        |pos: UVec3| decoded.get_block_state(PaddedChunkShape::linearize(pos.to_array()));
+    assert_eq!(block_at(UVec3::new(1, 1, 1)), Some(&stone));
+    assert_eq!(block_at(UVec3::new(2, 2, 2)), Some(&dirt));
+    assert_eq!(block_at(UVec3::new(3, 3, 3)), Some(&stone));
+    assert_eq!(block_at(UVec3::new(4, 4, 4)), None);
+}
+
+#[test]
+fn test_palette_growth_compacts() {
+    use bevy::math::{IVec3, UVec3};
+    use bevy::prelude::Entity;
+    use ndshape::ConstShape;
+
+    use super::{ChunkData, PaddedChunkShape};
+    use crate::voxel::voxel_block::{BlockId, BlockState};
+
+    // a long edit history on one voxel must not keep every block type it ever had
+    let mut chunk = ChunkData::new(IVec3::ZERO, Entity::PLACEHOLDER);
+    let pos = UVec3::new(7, 8, 9);
+    for i in 0..1000 {
+        let state = BlockState::from(BlockId::new(format!("test::{i}")));
+        chunk.set_block(pos, &state).unwrap();
+        assert!(chunk.palette.len() <= 4);
+        assert!(chunk.voxels.bits() <= 2);
+    }
+
+    let decoded = ChunkData::decode(&chunk.encode().unwrap()).unwrap();
+    assert_eq!(
+        decoded.get_block_id(PaddedChunkShape::linearize(pos.to_array())),
+        Some(&BlockId::new("test::999"))
+    );
+}
+
+#[test]
+fn test_uniform_roundtrip() {
+    use bevy::math::{IVec3, UVec3};
+    use bevy::prelude::Entity;
+
+    use super::ChunkData;
+    use crate::voxel::voxel_block::{BlockState, VoxelBlock};
+
+    let mut chunk = ChunkData::new(IVec3::new(0, 5, 0), Entity::PLACEHOLDER);
+    chunk.generate_hash();
+    let bytes = chunk.encode().unwrap();
+    assert!(bytes.len() < 64, "empty chunk encoded to {} bytes", bytes.len());
+    let decoded = ChunkData::decode(&bytes).unwrap();
+    assert_eq!(decoded.voxels.as_uniform(), Some(VoxelBlock::Air));
+    assert_eq!(decoded.hash, chunk.hash);
+
+    // digging the only block back out leaves a dead entry, compacting collapses the chunk
+    let pos = UVec3::new(3, 3, 3);
+    chunk.set_block(pos, &BlockState::parse("core::stone").unwrap()).unwrap();
+    assert_eq!(chunk.voxels.as_uniform(), None);
+    chunk.set_block(pos, &BlockState::parse("core::air").unwrap()).unwrap();
+    assert!(chunk.compact_palette());
+    assert_eq!(chunk.voxels.as_uniform(), Some(VoxelBlock::Air));
+    assert_eq!(chunk.voxels.heap_size(), 0);
+}
+
+#[test]
+fn test_block_entity_roundtrip() {
+    use std::collections::BTreeMap;
+
+    use bevy::math::{IVec3, UVec3};
+    use bevy::prelude::Entity;
+
+    use super::ChunkData;
+    use crate::voxel::block_entity::BlockValue;
+    use crate::voxel::voxel_block::BlockState;
+
+    let mut chunk = ChunkData::new(IVec3::new(2, 0, -1), Entity::PLACEHOLDER);
+    let pos = UVec3::new(5, 6, 7);
+    assert!(chunk.set_block_entity(pos, BlockValue::Bool(true)).is_err());
+
+    let furnace = BlockState::parse("core::furnace[facing=north]").unwrap();
+    chunk.set_block(pos, &furnace).unwrap();
+    chunk.generate_hash();
+    let hash = chunk.hash;
+    let data = BlockValue::Map(BTreeMap::from([
+        ("burn_time".to_owned(), BlockValue::Int(200)),
+        ("label".to_owned(), BlockValue::String("smelter".to_owned())),
+    ]));
+    chunk.set_block_entity(pos, data.clone()).unwrap();
+    chunk.generate_hash();
+    assert_ne!(chunk.hash, hash);
+
+    let mut decoded = ChunkData::decode(&chunk.encode().unwrap()).unwrap();
+    assert_eq!(decoded.block_entities, chunk.block_entities);
+    assert_eq!(decoded.block_entity(pos).unwrap().data, data);
+
+    // another state of the same block keeps the entity, another block drops it
+    let lit = BlockState::parse("core::furnace[facing=north,lit=true]").unwrap();
+    decoded.set_block(pos, &lit).unwrap();
+    assert!(decoded.block_entity(pos).is_some());
+    decoded.set_block(pos, &BlockState::parse("core::stone").unwrap()).unwrap();
+    assert!(decoded.block_entity(pos).is_none());
+}
+
+#[test]
+fn test_reject_future_version() {
+    assert!(migrate(CHUNK_FORMAT_VERSION + 1, Vec::new()).is_err());
+}
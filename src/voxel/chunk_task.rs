@@ -1,20 +1,21 @@
-use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
-use ahash::AHashSet;
-use anyhow::Context;
-use bevy::math::IVec3;
+use ahash::{AHashMap, AHashSet};
+use bevy::math::{IVec3, UVec3};
 use bevy::prelude::{Component, Entity, Mesh};
 use bevy::tasks::Task;
 use ndshape::ConstShape as _;
 
 use crate::core::registry::Registry;
 
+use super::chunk::{report_chunk_error, BlockChanged, ChangeCause, ChunkError, ChunkErrorKind};
 use super::generator::Generator;
+use super::heightmap::Heightmaps;
 use super::mesh::generate_chunk_mesh;
-use super::storage::{WorldDatabase, CHUNKS};
+use super::storage::WorldStorage;
 use super::textures::TextureMap;
-use super::voxel_block::VoxelBlock;
+use super::utils::ivec3_range;
+use super::voxel_block::{BlockState, VoxelBlock, AIR};
 use super::{ChunkData, ModifiedVoxels, PaddedChunkShape, CHUNK_SIZE};
 
 #[derive(Component)]
@@ -28,7 +29,7 @@ pub struct GenMeshTaskData {
 
 impl GenMeshTaskData {
     pub fn generate_mesh(&mut self, registry: Registry, texture_map: TextureMap) {
-        if self.mesh.is_none() && self.chunk_data.solid_count != 0 {
+        if self.mesh.is_none() && !self.chunk_data.is_meshless() {
             self.mesh = Some(generate_chunk_mesh(
                 &mut self.chunk_data,
                 registry,
@@ -39,47 +40,81 @@ impl GenMeshTaskData {
 }
 
 #[derive(Component)]
-pub struct BuildChunkTask(pub Task<ChunkData>);
+pub struct BuildChunkTask(pub Task<Option<(ChunkData, Vec<BlockChanged>)>>);
+
+/// Reads of a chunk before its build gives up, a flaky disk often answers the second time.
+const READ_ATTEMPTS: usize = 3;
 
 pub struct BuildChunkTaskInner {
     pub chunk_pos: IVec3,
     pub chunk_entity: Entity,
     pub modified_voxels: ModifiedVoxels,
     pub generator: Arc<dyn Generator>,
-    pub storage: Option<WorldDatabase>,
+    pub storage: Option<WorldStorage>,
+    /// block entities of edited blocks and heightmaps
+    pub registry: Registry,
+    pub errors: kanal::Sender<ChunkError>,
 }
 
 impl BuildChunkTaskInner {
-    pub fn build(self) -> ChunkData {
+    /// `Ok(None)` if the chunk is not stored or could not be decoded, it is generated then.
+    /// Undecodable bytes are quarantined so the regenerated chunk does not replace them.
+    /// `Err` if the storage could not be read: the chunk may well be stored, so it must not
+    /// be generated and saved over.
+    fn load(&self) -> anyhow::Result<Option<ChunkData>> {
+        let Some(storage) = self.storage.as_ref() else {
+            return Ok(None);
+        };
+        let _span = tracing::info_span!("profiling::{read chunk from storage}").entered();
+        let Some(bytes) = retry(|| storage.load(self.chunk_pos))? else {
+            return Ok(None);
+        };
+
+        let err = match ChunkData::decode(&bytes) {
+            Ok(chunk_data) if chunk_data.pos == self.chunk_pos => return Ok(Some(chunk_data)),
+            Ok(chunk_data) => anyhow::anyhow!("stored chunk says it is at {}", chunk_data.pos),
+            Err(err) => err.context("decoding chunk data failed"),
+        };
+        if let Err(quarantine_err) = storage.quarantine(self.chunk_pos, &bytes) {
+            tracing::error!(
+                "quarantine chunk {} failed: {:?}",
+                self.chunk_pos,
+                quarantine_err
+            );
+        }
+        report_chunk_error(&self.errors, self.chunk_pos, ChunkErrorKind::Corrupted, &err);
+        Ok(None)
+    }
+
+    /// The chunk and the deferred edits it applied, to be reported once it is loaded.
+    /// `None` if the storage could not be read, the chunk stays unloaded until it is
+    /// requested again.
+    pub fn build(self) -> Option<(ChunkData, Vec<BlockChanged>)> {
         let mut filled_count = 0;
         let mut new_chunk = false;
-        let mut hasher = ahash::AHasher::default();
-        let mut modified_voxels = self.modified_voxels.write();
         let mut material_count = AHashSet::new();
+        let mut placed = Vec::new();
+        let mut changes = Vec::new();
 
-        let mut chunk_data = if let Some(mut chunk_data) = self
-            .storage
-            .as_ref()
-            .map(|storage| {
-                let _span = tracing::info_span!("profiling::{read chunk from database}").entered();
-                storage
-                    .read(CHUNKS, |_, table| {
-                        if let Some(bytes) = table.get(self.chunk_pos.to_array())? {
-                            ChunkData::decode(bytes.value())
-                                .map(Some)
-                                .with_context(|| "decoding chunk data failed")
-                        } else {
-                            Ok(None)
-                        }
-                    })
-                    .and_then(|v| v)
-                    .expect("reading chunk data failed")
-            })
-            .flatten()
-        {
-            // read from database
-            assert_eq!(self.chunk_pos, chunk_data.pos);
+        let loaded = self.load().and_then(|stored| {
+            // edits made while this chunk was unloaded, persisted across restarts
+            let pending_edits = match self.storage.as_ref() {
+                Some(storage) => retry(|| storage.load_pending_edits(self.chunk_pos))?,
+                None => Vec::new(),
+            };
+            Ok((stored, pending_edits))
+        });
+        let (stored, stored_edits) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                report_chunk_error(&self.errors, self.chunk_pos, ChunkErrorKind::Read, &err);
+                return None;
+            }
+        };
+        let mut pending_edits = stored_edits.iter().cloned().collect::<AHashMap<_, _>>();
 
+        let mut chunk_data = if let Some(mut chunk_data) = stored {
+            // read from storage
             //tracing::trace!("Load chunk:{} from database", self.chunk_pos);
 
             chunk_data.entity = self.chunk_entity;
@@ -87,10 +122,51 @@ impl BuildChunkTaskInner {
         } else {
             // generate new chunk from generator
             new_chunk = true;
-            ChunkData::new(self.chunk_pos, self.chunk_entity)
+            let mut chunk_data = ChunkData::new(self.chunk_pos, self.chunk_entity);
+            let _span = tracing::info_span!("profiling::{generate block}").entered();
+            if let Err(err) = self.generator.generate_chunk(&mut chunk_data) {
+                tracing::error!("generate chunk {} failed: {}", self.chunk_pos, err);
+            }
+            chunk_data
         };
 
-        let _span = new_chunk.then(|| tracing::info_span!("profiling::{generate block}").entered());
+        // edits parked for this chunk are applied now, the ones of its neighbors only show
+        // in its padding until they load. Read and generated before taking the lock, and
+        // released right away, so edits never wait for the disk or the build
+        let (mut parked_edits, padding_edits) = {
+            let mut modified_voxels = self.modified_voxels.write();
+            let parked_edits = modified_voxels.remove(&self.chunk_pos).unwrap_or_default();
+            let min = self.chunk_pos * CHUNK_SIZE as i32 - IVec3::ONE;
+            let max = min + IVec3::splat(CHUNK_SIZE as i32 + 1);
+            let neighbors = ivec3_range(self.chunk_pos - IVec3::ONE, self.chunk_pos + IVec3::ONE);
+            let padding_edits = neighbors
+                .filter_map(|neighbor| modified_voxels.get(&neighbor))
+                .flatten()
+                .filter(|(pos, _)| pos.cmpge(min).all() && pos.cmple(max).all())
+                .map(|(pos, state)| (*pos, state.clone()))
+                .collect::<AHashMap<_, _>>();
+            (parked_edits, padding_edits)
+        };
+        let edited =
+            !parked_edits.is_empty() || !pending_edits.is_empty() || !padding_edits.is_empty();
+        // persisted by the saver too, maybe only after the pending edits were read above
+        let mut applied_edits = stored_edits;
+        applied_edits.extend(parked_edits.clone());
+
+        // a stored uniform chunk without edits is ready as is, its hash came with it
+        if !new_chunk && !edited {
+            if let Some(voxel) = chunk_data.voxels.as_uniform() {
+                chunk_data.solid_count = if voxel.is_air() {
+                    0
+                } else {
+                    PaddedChunkShape::SIZE
+                };
+                if chunk_data.heightmaps.is_none() {
+                    chunk_data.heightmaps = Some(Heightmaps::compute(&chunk_data, &self.registry));
+                }
+                return Some((chunk_data, changes));
+            }
+        }
 
         // for each all blocks in the chunk
         for i in 0..PaddedChunkShape::SIZE {
@@ -101,19 +177,43 @@ impl BuildChunkTaskInner {
                 z: pos[2] as i32 + (self.chunk_pos.z * CHUNK_SIZE as i32) - 1,
             };
 
-            // apply modified voxels
-            let modified = modified_voxels.remove(&block_pos);
-            let voxel = if let Some(id) = modified {
-                chunk_data.palette.voxel_block(&id)
-            } else if new_chunk {
-                chunk_data
-                    .palette
-                    .voxel_block(&self.generator.generate(block_pos))
+            // apply parked and pending edits, show the ones of neighbors in the padding
+            let modified = parked_edits
+                .remove(&block_pos)
+                .or_else(|| pending_edits.remove(&block_pos))
+                .or_else(|| padding_edits.get(&block_pos).cloned());
+            let id = if let Some(id) = modified {
+                chunk_data.dirty = true;
+                // the padding belongs to the neighbors, so do their block entities and changes
+                let inner = UVec3::from_array(pos);
+                if inner.cmpge(UVec3::ONE).all() && inner.cmple(UVec3::splat(CHUNK_SIZE)).all() {
+                    placed.push((inner, id.id().clone()));
+                    // a new chunk is generated already
+                    let old = chunk_data
+                        .block_at(inner)
+                        .cloned()
+                        .unwrap_or_else(|| BlockState::from(AIR.clone()));
+                    if old != id {
+                        changes.push(BlockChanged {
+                            pos: block_pos,
+                            old,
+                            new: id.clone(),
+                            cause: ChangeCause::Deferred,
+                        });
+                    }
+                }
+                Some(id)
             } else {
-                chunk_data.voxels[i as usize]
+                None
+            };
+            let voxel = match id.map(|id| chunk_data.voxel_block(&id)) {
+                Some(Ok(voxel)) => voxel,
+                Some(Err(err)) => {
+                    tracing::error!("build chunk {} failed: {}", self.chunk_pos, err);
+                    chunk_data.voxels.get(i as usize)
+                }
+                None => chunk_data.voxels.get(i as usize),
             };
-
-            voxel.hash(&mut hasher);
 
             if let VoxelBlock::Solid(id) = &voxel {
                 material_count.insert(id.clone());
@@ -121,22 +221,91 @@ impl BuildChunkTaskInner {
             if !voxel.is_air() {
                 filled_count += 1;
             }
-            if new_chunk {
-                chunk_data.voxels[i as usize] = voxel;
+            if chunk_data.dirty {
+                chunk_data.voxels.set(i as usize, voxel);
             }
         }
 
+        // the chunk is dirty now, its edits are dropped from the storage once it is saved
+        chunk_data.applied_edits = applied_edits;
+
+        for (pos, id) in placed {
+            chunk_data.sync_block_entity(pos, &id, Some(&self.registry));
+        }
+
         chunk_data.solid_count = filled_count;
-        chunk_data.hash = hasher.finish();
+        chunk_data.generate_hash();
         if filled_count == 0 {
             // empty chunk, all is air
+            chunk_data.voxels.collapse();
         } else if chunk_data.is_full() && material_count.len() == 1 {
-            chunk_data.uniform = true;
+            chunk_data.voxels.collapse();
         } else {
             // mixed
         }
-        chunk_data
+        // stored heightmaps are still right unless a block changed
+        if new_chunk || chunk_data.dirty || chunk_data.heightmaps.is_none() {
+            chunk_data.heightmaps = Some(Heightmaps::compute(&chunk_data, &self.registry));
+        }
+        Some((chunk_data, changes))
+    }
+}
+
+/// `READ_ATTEMPTS` tries of `read`, the last error if none succeeds.
+fn retry<T>(mut read: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let mut result = read();
+    for _ in 1..READ_ATTEMPTS {
+        if result.is_ok() {
+            break;
+        }
+        result = read();
     }
+    result
 }
 
 pub struct SaveChunkTask(pub Task<()>);
+
+#[test]
+fn test_parked_edit_dropped_once_saved() {
+    use super::generator::flat::FlatGenerator;
+    use super::storage::MemoryStorage;
+
+    let storage = WorldStorage(Arc::new(MemoryStorage::new()));
+    let modified_voxels = ModifiedVoxels::default();
+    let (chunk_pos, block_pos) = (IVec3::ZERO, IVec3::new(1, 2, 3));
+    let stone = BlockState::parse("core::stone").unwrap();
+    modified_voxels
+        .write()
+        .entry(chunk_pos)
+        .or_default()
+        .insert(block_pos, stone.clone());
+
+    let (errors, _) = kanal::unbounded();
+    let (chunk, _) = BuildChunkTaskInner {
+        chunk_pos,
+        chunk_entity: Entity::PLACEHOLDER,
+        modified_voxels,
+        generator: Arc::new(FlatGenerator::new()),
+        storage: Some(storage.clone()),
+        registry: Registry::new(),
+        errors,
+    }
+    .build()
+    .unwrap();
+    assert_eq!(chunk.applied_edits, vec![(block_pos, stone.clone())]);
+
+    // the saver commits the parked edit only after the build read the pending edits
+    storage
+        .save_pending_edits(&[(chunk_pos, block_pos, stone.clone())])
+        .unwrap();
+    let bytes = chunk.encode().unwrap();
+    let applied_edits = chunk
+        .applied_edits
+        .iter()
+        .map(|(pos, state)| (chunk_pos, *pos, state.clone()))
+        .collect::<Vec<_>>();
+    storage
+        .save_applied(&[(chunk_pos, &bytes[..])], &applied_edits)
+        .unwrap();
+    assert!(storage.load_pending_edits(chunk_pos).unwrap().is_empty());
+}
@@ -1,11 +1,34 @@
+use std::time::Duration;
+
 use bevy::prelude::Resource;
 
+use super::generator::noise::NoiseConfig;
+use super::generator::GeneratorKind;
+
 #[derive(Resource, Debug, Clone)]
 pub struct VoxelConfig {
     pub spawning_distance: u32,
     pub spawning_rays: u32,
     pub spawning_ray_margin: u32,
     pub max_spawn_per_frame: u32,
+    /// chunks committed per save transaction
+    pub save_batch_size: u32,
+    /// encoded bytes committed per save transaction
+    pub save_batch_bytes: u32,
+    /// how long the saver waits to fill a batch
+    pub save_batch_delay: Duration,
+    /// chunks that may wait for the saver before unloading backs off
+    pub save_queue_capacity: u32,
+    /// how often modified loaded chunks are saved
+    pub autosave_interval: Duration,
+    /// edit groups kept for undo
+    pub history_limit: u32,
+    /// chunks the camera may move from the floating origin before it is moved
+    pub origin_rebase_distance: u32,
+    /// seed of a newly created world, opened worlds keep the one in their metadata
+    pub seed: u32,
+    /// generator of a newly created world, with its parameters
+    pub generator: GeneratorKind,
 }
 
 impl Default for VoxelConfig {
@@ -15,6 +38,15 @@ impl Default for VoxelConfig {
             spawning_rays: 96,
             spawning_ray_margin: 24,
             max_spawn_per_frame: 8192,
+            save_batch_size: 256,
+            save_batch_bytes: 16 * 1024 * 1024,
+            save_batch_delay: Duration::from_millis(500),
+            save_queue_capacity: 1024,
+            autosave_interval: Duration::from_secs(60),
+            history_limit: 64,
+            origin_rebase_distance: 8,
+            seed: 1234,
+            generator: GeneratorKind::Noise(NoiseConfig::default()),
         }
     }
 }
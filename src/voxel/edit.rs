@@ -0,0 +1,710 @@
+//! Edits that cover a whole region: filling shapes, replacing one block with another and
+//! pasting a clipboard. Each is applied one chunk at a time, so a chunk is locked, rehashed
+//! and remeshed once per edit rather than once per block.
+
+use std::sync::Arc;
+
+use ahash::AHashMap;
+use bevy::math::{I64Vec3, IVec3, UVec3};
+use bevy::prelude::Entity;
+use mlua::UserData;
+use thiserror::Error;
+
+use crate::core::registry::Registry;
+
+use super::chunk::{get_chunk_voxel_position, CHUNK_SIZE};
+use super::history::BlockChange;
+use super::padding::padding_neighbors;
+use super::query::{self, QueryError};
+use super::utils::ivec3_range;
+use super::voxel_block::{BlockState, AIR};
+use super::world::LoadedChunks;
+
+/// Most blocks one edit may cover, every one of them is visited when it is applied.
+pub const MAX_EDIT_VOLUME: u64 = 1 << 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum EditError {
+    #[error("edit covers {0} blocks, at most {MAX_EDIT_VOLUME} are allowed")]
+    TooLarge(u64),
+    #[error("edit from {0} to {1} reaches past the edge of the world")]
+    OutOfRange(I64Vec3, I64Vec3),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// both corners inclusive, in any order
+    Box {
+        min: IVec3,
+        max: IVec3,
+    },
+    Sphere {
+        center: IVec3,
+        radius: u32,
+    },
+    /// upright, `base` is the center of its lowest layer
+    Cylinder {
+        base: IVec3,
+        radius: u32,
+        height: u32,
+    },
+}
+
+impl Shape {
+    /// Smallest box holding the shape, both corners inclusive.
+    /// Only meaningful once `check` passed, out of range corners are clamped.
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        let (min, max) = self.wide_bounds();
+        (clamp_ivec3(min), clamp_ivec3(max))
+    }
+
+    /// `bounds` without overflow, whatever the radius and height.
+    fn wide_bounds(&self) -> (I64Vec3, I64Vec3) {
+        match *self {
+            Shape::Box { min, max } => (min.min(max).as_i64vec3(), min.max(max).as_i64vec3()),
+            Shape::Sphere { center, radius } => {
+                let center = center.as_i64vec3();
+                let radius = I64Vec3::splat(radius.into());
+                (center - radius, center + radius)
+            }
+            Shape::Cylinder {
+                base,
+                radius,
+                height,
+            } => {
+                let base = base.as_i64vec3();
+                let radius = i64::from(radius);
+                (
+                    base - I64Vec3::new(radius, 0, radius),
+                    base + I64Vec3::new(radius, i64::from(height) - 1, radius),
+                )
+            }
+        }
+    }
+
+    /// Blocks in `bounds`, saturating.
+    pub fn volume(&self) -> u64 {
+        let (min, max) = self.wide_bounds();
+        let size = (max - min + I64Vec3::ONE).max(I64Vec3::ZERO).as_u64vec3();
+        size.x.saturating_mul(size.y).saturating_mul(size.z)
+    }
+
+    /// `Err` if the shape covers more than `MAX_EDIT_VOLUME` blocks or does not fit the
+    /// world's coordinates. Bulk edits are checked before they are applied.
+    pub fn check(&self) -> Result<(), EditError> {
+        check_bounds(self.wide_bounds())?;
+        match self.volume() {
+            volume if volume > MAX_EDIT_VOLUME => Err(EditError::TooLarge(volume)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn contains(&self, pos: IVec3) -> bool {
+        // half a block more than the radius, so the poles are not single blocks.
+        // In `f64`, squaring even an `i64` offset may overflow
+        let in_radius = |offset: I64Vec3, radius: u32| {
+            offset.as_dvec3().length_squared() <= (f64::from(radius) + 0.5).powi(2)
+        };
+        match *self {
+            Shape::Box { .. } => {
+                let (min, max) = self.bounds();
+                pos.cmpge(min).all() && pos.cmple(max).all()
+            }
+            Shape::Sphere { center, radius } => {
+                in_radius(pos.as_i64vec3() - center.as_i64vec3(), radius)
+            }
+            Shape::Cylinder {
+                base,
+                radius,
+                height,
+            } => {
+                let offset = pos.as_i64vec3() - base.as_i64vec3();
+                (0..i64::from(height)).contains(&offset.y)
+                    && in_radius(I64Vec3::new(offset.x, 0, offset.z), radius)
+            }
+        }
+    }
+
+    /// Inside, with at least one face neighbor outside.
+    pub fn on_surface(&self, pos: IVec3) -> bool {
+        const FACES: [IVec3; 6] = [
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ];
+        self.contains(pos) && FACES.iter().any(|face| !self.contains(pos + *face))
+    }
+}
+
+/// Leaves a block of margin at both ends, so the face neighbors of every block in
+/// bounds still fit an `IVec3`.
+fn check_bounds((min, max): (I64Vec3, I64Vec3)) -> Result<(), EditError> {
+    let fits = min.cmpgt(I64Vec3::splat(i32::MIN.into())).all()
+        && max.cmplt(I64Vec3::splat(i32::MAX.into())).all();
+    if fits {
+        Ok(())
+    } else {
+        Err(EditError::OutOfRange(min, max))
+    }
+}
+
+fn clamp_ivec3(pos: I64Vec3) -> IVec3 {
+    pos.clamp(
+        I64Vec3::splat(i32::MIN.into()),
+        I64Vec3::splat(i32::MAX.into()),
+    )
+    .as_ivec3()
+}
+
+/// Quarter turns around the y axis, clockwise seen from above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    pub fn from_quarter_turns(turns: i32) -> Self {
+        match turns.rem_euclid(4) {
+            0 => Rotation::None,
+            1 => Rotation::Clockwise90,
+            2 => Rotation::Clockwise180,
+            _ => Rotation::Clockwise270,
+        }
+    }
+
+    #[inline]
+    fn quarter_turns(self) -> u32 {
+        self as u32
+    }
+
+    /// `offset` in a box of `size` turned once clockwise: north (-z) becomes east (+x).
+    fn turn(offset: UVec3, size: UVec3) -> UVec3 {
+        UVec3::new(size.z - 1 - offset.z, offset.y, offset.x)
+    }
+
+    /// Size of a box of `size` after the rotation.
+    fn size(self, size: UVec3) -> UVec3 {
+        if self.quarter_turns() % 2 == 1 {
+            UVec3::new(size.z, size.y, size.x)
+        } else {
+            size
+        }
+    }
+}
+
+/// Flips across the x or the z axis, before rotating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mirror {
+    #[default]
+    None,
+    /// east <-> west
+    X,
+    /// north <-> south
+    Z,
+}
+
+const HORIZONTAL: [&str; 4] = ["north", "east", "south", "west"];
+
+/// `state` as seen after mirroring, then rotating the blocks around it.
+/// Only `facing` and `axis` follow, other properties are kept as they are.
+pub fn transform_state(state: &BlockState, rotation: Rotation, mirror: Mirror) -> BlockState {
+    let mut state = state.clone();
+    if let Some(facing) = state.property("facing") {
+        if let Some(idx) = HORIZONTAL.iter().position(|dir| *dir == facing) {
+            let idx = match (mirror, idx) {
+                (Mirror::X, 1 | 3) | (Mirror::Z, 0 | 2) => (idx + 2) % 4,
+                _ => idx,
+            };
+            let idx = (idx + rotation.quarter_turns() as usize) % 4;
+            state = state.with("facing", HORIZONTAL[idx]);
+        }
+    }
+    if rotation.quarter_turns() % 2 == 1 {
+        match state.property("axis") {
+            Some("x") => state = state.with("axis", "z"),
+            Some("z") => state = state.with("axis", "x"),
+            _ => {}
+        }
+    }
+    state
+}
+
+/// Blocks copied from a box, pasted relative to its lowest corner.
+/// Block entities are not copied. Cloning shares the blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clipboard {
+    size: UVec3,
+    /// `None` == Air
+    blocks: Arc<[Option<BlockState>]>,
+}
+
+impl Clipboard {
+    /// Copy the box from `min` to `max`, both inclusive. Every chunk it touches has to be loaded.
+    pub fn copy(chunks: &LoadedChunks, min: IVec3, max: IVec3) -> Result<Self, QueryError> {
+        let (min, max) = (min.min(max), min.max(max));
+        let size = (max - min + IVec3::ONE).as_uvec3();
+        let mut blocks = vec![None; (size.x * size.y * size.z) as usize];
+        query::for_each_block(chunks, min, max, |pos, state| {
+            blocks[Self::index(size, (pos - min).as_uvec3())] = state.cloned();
+        })?;
+        Ok(Clipboard {
+            size,
+            blocks: blocks.into(),
+        })
+    }
+
+    /// `f` gets every offset from the lowest corner, `None` == Air.
+    pub fn from_fn(size: UVec3, mut f: impl FnMut(UVec3) -> Option<BlockState>) -> Self {
+        let mut blocks = vec![None; (size.x * size.y * size.z) as usize];
+        for offset in ivec3_range(IVec3::ZERO, size.as_ivec3() - IVec3::ONE) {
+            blocks[Self::index(size, offset.as_uvec3())] = f(offset.as_uvec3());
+        }
+        Clipboard {
+            size,
+            blocks: blocks.into(),
+        }
+    }
+
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    /// Mirrored, then rotated around its center, still pasted from its lowest corner.
+    pub fn transformed(&self, rotation: Rotation, mirror: Mirror) -> Self {
+        let size = rotation.size(self.size);
+        Clipboard::from_fn(size, |offset| {
+            // back to where the block came from: the remaining quarter turns, then the mirror
+            let (mut source, mut turned) = (offset, size);
+            for _ in 0..(4 - rotation.quarter_turns()) % 4 {
+                source = Rotation::turn(source, turned);
+                turned = UVec3::new(turned.z, turned.y, turned.x);
+            }
+            match mirror {
+                Mirror::None => {}
+                Mirror::X => source.x = self.size.x - 1 - source.x,
+                Mirror::Z => source.z = self.size.z - 1 - source.z,
+            }
+            self.blocks[Self::index(self.size, source)]
+                .as_ref()
+                .map(|state| transform_state(state, rotation, mirror))
+        })
+    }
+
+    /// The block at `offset` from the lowest corner, `None` if `offset` is outside.
+    pub fn get(&self, offset: IVec3) -> Option<Option<&BlockState>> {
+        if offset.cmplt(IVec3::ZERO).any() || offset.as_uvec3().cmpge(self.size).any() {
+            return None;
+        }
+        Some(self.blocks[Self::index(self.size, offset.as_uvec3())].as_ref())
+    }
+
+    #[inline]
+    fn index(size: UVec3, offset: UVec3) -> usize {
+        ((offset.x * size.y + offset.y) * size.z + offset.z) as usize
+    }
+}
+
+impl UserData for Clipboard {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("size", |_, this, ()| {
+            Ok((this.size.x, this.size.y, this.size.z))
+        });
+        // quarter turns clockwise seen from above, negative turns counterclockwise
+        methods.add_method("rotated", |_, this, turns: i32| {
+            Ok(this.transformed(Rotation::from_quarter_turns(turns), Mirror::None))
+        });
+        methods.add_method("mirrored", |_, this, axis: String| {
+            let mirror = match axis.as_str() {
+                "x" => Mirror::X,
+                "z" => Mirror::Z,
+                _ => {
+                    return Err(mlua::Error::runtime(format!(
+                        "mirror axis must be `x` or `z`, got `{axis}`"
+                    )))
+                }
+            };
+            Ok(this.transformed(Rotation::None, mirror))
+        });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BulkEdit {
+    /// every block of `shape`, or only its surface if `hollow`
+    Fill {
+        shape: Shape,
+        state: BlockState,
+        hollow: bool,
+    },
+    /// `from` without properties matches every state of its block
+    Replace {
+        shape: Shape,
+        from: BlockState,
+        to: BlockState,
+    },
+    /// air included
+    Paste { origin: IVec3, clipboard: Clipboard },
+}
+
+impl BulkEdit {
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        match self {
+            BulkEdit::Fill { shape, .. } | BulkEdit::Replace { shape, .. } => shape.bounds(),
+            BulkEdit::Paste { origin, clipboard } => {
+                (*origin, *origin + clipboard.size().as_ivec3() - IVec3::ONE)
+            }
+        }
+    }
+
+    /// See `Shape::check`, a paste only has to fit the world's coordinates.
+    pub fn check(&self) -> Result<(), EditError> {
+        match self {
+            BulkEdit::Fill { shape, .. } | BulkEdit::Replace { shape, .. } => shape.check(),
+            BulkEdit::Paste { origin, clipboard } => {
+                let origin = origin.as_i64vec3();
+                check_bounds((
+                    origin,
+                    origin + clipboard.size().as_i64vec3() - I64Vec3::ONE,
+                ))
+            }
+        }
+    }
+
+    /// Has to read the blocks it edits, so it cannot be parked for unloaded chunks.
+    fn reads_blocks(&self) -> bool {
+        matches!(self, BulkEdit::Replace { .. })
+    }
+
+    /// What the block at `pos` becomes, `None` to leave it as is. `current` is `None` for air.
+    fn block_at(&self, pos: IVec3, current: Option<&BlockState>) -> Option<BlockState> {
+        match self {
+            BulkEdit::Fill {
+                shape,
+                state,
+                hollow,
+            } => {
+                let inside = if *hollow {
+                    shape.on_surface(pos)
+                } else {
+                    shape.contains(pos)
+                };
+                inside.then(|| state.clone())
+            }
+            BulkEdit::Replace { shape, from, to } => {
+                let matches = match current {
+                    None => from.is_air(),
+                    Some(current) if from.properties().next().is_none() => {
+                        current.id() == from.id()
+                    }
+                    Some(current) => current == from,
+                };
+                (matches && shape.contains(pos)).then(|| to.clone())
+            }
+            BulkEdit::Paste { origin, clipboard } => clipboard.get(pos - *origin).map(|state| {
+                state
+                    .cloned()
+                    .unwrap_or_else(|| BlockState::from(AIR.clone()))
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Applied {
+    /// loaded chunks whose blocks or padding changed, already rehashed
+    pub changed: Vec<(IVec3, Entity)>,
+    /// blocks of loaded chunks, with what they replaced
+    pub changes: Vec<BlockChange>,
+    /// (chunk pos, block pos, state) of blocks in chunks that are not loaded
+    pub parked: Vec<(IVec3, IVec3, BlockState)>,
+}
+
+/// Apply `edit` to the loaded chunks it covers, each updated in a single pass, and copy
+/// changed border blocks into the padding of loaded neighbors. Blocks of unloaded chunks
+/// are returned to be parked like single edits, except for `Replace`, which skips them.
+pub fn apply(edit: &BulkEdit, chunks: &LoadedChunks, registry: &Registry) -> Applied {
+    let mut applied = Applied::default();
+    let (min, max) = edit.bounds();
+    let (min_chunk, _) = get_chunk_voxel_position(min);
+    let (max_chunk, _) = get_chunk_voxel_position(max);
+    let mut padding = AHashMap::<IVec3, Vec<(UVec3, BlockState)>>::new();
+
+    for chunk_pos in ivec3_range(min_chunk, max_chunk) {
+        let origin = chunk_pos * CHUNK_SIZE as i32;
+        let lo = min.max(origin);
+        let hi = max.min(origin + IVec3::splat(CHUNK_SIZE as i32 - 1));
+        let updated = chunks.update(&chunk_pos, |_, chunk| {
+            let mut changed = false;
+            for pos in ivec3_range(lo, hi) {
+                let voxel_pos = (pos - origin).as_uvec3() + 1;
+                let current = chunk.block_at(voxel_pos);
+                let Some(state) = edit.block_at(pos, current) else {
+                    continue;
+                };
+                if current == (!state.is_air()).then_some(&state) {
+                    continue;
+                }
+                let before = current
+                    .cloned()
+                    .unwrap_or_else(|| BlockState::from(AIR.clone()));
+                if let Err(err) = chunk.place_block(voxel_pos, &state, registry) {
+                    tracing::error!("bulk edit in chunk {} failed: {}", chunk_pos, err);
+                    continue;
+                }
+                changed = true;
+                for (neighbor, padded_pos) in padding_neighbors(pos) {
+                    padding
+                        .entry(neighbor)
+                        .or_default()
+                        .push((padded_pos, state.clone()));
+                }
+                applied.changes.push(BlockChange {
+                    pos,
+                    before,
+                    after: state,
+                });
+            }
+            changed.then_some(chunk.entity)
+        });
+        match updated {
+            Some(Some(entity)) => applied.changed.push((chunk_pos, entity)),
+            Some(None) => {}
+            None if edit.reads_blocks() => {
+                tracing::warn!("chunk {} is not loaded, bulk edit skipped it", chunk_pos);
+            }
+            None => {
+                for pos in ivec3_range(lo, hi) {
+                    if let Some(state) = edit.block_at(pos, None) {
+                        for (neighbor, padded_pos) in padding_neighbors(pos) {
+                            padding
+                                .entry(neighbor)
+                                .or_default()
+                                .push((padded_pos, state.clone()));
+                        }
+                        applied.parked.push((chunk_pos, pos, state));
+                    }
+                }
+            }
+        }
+    }
+
+    // after every chunk's own blocks, so the copies are never overwritten by stale ones
+    for (neighbor, blocks) in padding {
+        let updated = chunks.update(&neighbor, |_, chunk| {
+            let mut changed = false;
+            for (padded_pos, state) in blocks {
+                if let Err(err) = chunk.set_block(padded_pos, &state) {
+                    tracing::error!("set padding in chunk {} failed: {}", neighbor, err);
+                    continue;
+                }
+                changed = true;
+            }
+            changed.then_some(chunk.entity)
+        });
+        if let Some(Some(entity)) = updated {
+            if !applied.changed.iter().any(|(pos, _)| *pos == neighbor) {
+                applied.changed.push((neighbor, entity));
+            }
+        }
+    }
+
+    for (pos, _) in &applied.changed {
+        chunks.update(pos, |_, chunk| chunk.generate_hash());
+    }
+    applied
+}
+
+#[test]
+fn test_shapes() {
+    let sphere = Shape::Sphere {
+        center: IVec3::ZERO,
+        radius: 2,
+    };
+    assert!(sphere.contains(IVec3::new(0, 2, 0)));
+    assert!(!sphere.contains(IVec3::new(2, 2, 0)));
+    assert!(sphere.on_surface(IVec3::new(0, 2, 0)));
+    assert!(!sphere.on_surface(IVec3::ZERO));
+
+    let cylinder = Shape::Cylinder {
+        base: IVec3::new(0, 10, 0),
+        radius: 1,
+        height: 3,
+    };
+    assert_eq!(
+        cylinder.bounds(),
+        (IVec3::new(-1, 10, -1), IVec3::new(1, 12, 1))
+    );
+    assert!(cylinder.contains(IVec3::new(1, 12, 0)));
+    assert!(!cylinder.contains(IVec3::new(0, 13, 0)));
+
+    let hollow = BulkEdit::Fill {
+        shape: Shape::Box {
+            min: IVec3::splat(2),
+            max: IVec3::ZERO,
+        },
+        state: BlockState::parse("core::stone").unwrap(),
+        hollow: true,
+    };
+    let filled = ivec3_range(IVec3::ZERO, IVec3::splat(2))
+        .filter(|pos| hollow.block_at(*pos, None).is_some())
+        .count();
+    assert_eq!(filled, 26);
+}
+
+#[test]
+fn test_shape_check() {
+    let sphere = Shape::Sphere {
+        center: IVec3::ZERO,
+        radius: 100,
+    };
+    assert_eq!(sphere.check(), Ok(()));
+    // would overflow `i32` squared
+    let huge = Shape::Sphere {
+        center: IVec3::ZERO,
+        radius: u32::MAX,
+    };
+    assert!(matches!(huge.check(), Err(EditError::OutOfRange(..))));
+    assert!(!huge.contains(IVec3::new(i32::MAX, i32::MAX, i32::MAX)));
+    assert!(huge.contains(IVec3::new(100_000, 0, 0)));
+
+    let tall = Shape::Cylinder {
+        base: IVec3::ZERO,
+        radius: 0,
+        height: MAX_EDIT_VOLUME as u32 + 1,
+    };
+    assert_eq!(tall.check(), Err(EditError::TooLarge(MAX_EDIT_VOLUME + 1)));
+    let flat = Shape::Cylinder {
+        base: IVec3::ZERO,
+        radius: 5,
+        height: 0,
+    };
+    assert_eq!(flat.volume(), 0);
+
+    let edge = Shape::Box {
+        min: IVec3::splat(i32::MAX - 1),
+        max: IVec3::splat(i32::MAX),
+    };
+    assert!(matches!(edge.check(), Err(EditError::OutOfRange(..))));
+}
+
+#[test]
+fn test_apply_across_chunks() {
+    use super::chunk::ChunkData;
+
+    let chunks = LoadedChunks::default();
+    for chunk_pos in [IVec3::ZERO, IVec3::new(-1, 0, 0)] {
+        chunks
+            .insert(chunk_pos, ChunkData::new(chunk_pos, Entity::from_raw(10)))
+            .ok();
+    }
+    let registry = Registry::new();
+    let stone = BlockState::parse("core::stone").unwrap();
+    let log = BlockState::parse("core::log[axis=x]").unwrap();
+
+    // spans two loaded chunks and an unloaded one above them
+    let fill = BulkEdit::Fill {
+        shape: Shape::Box {
+            min: IVec3::new(-2, 30, 0),
+            max: IVec3::new(1, 32, 0),
+        },
+        state: stone.clone(),
+        hollow: false,
+    };
+    let applied = apply(&fill, &chunks, &registry);
+    assert_eq!(applied.changed.len(), 2);
+    assert_eq!(applied.changes.len(), 8);
+    assert!(applied.changes.iter().all(|change| change.before.is_air()));
+    assert_eq!(applied.parked.len(), 4);
+    assert!(applied
+        .parked
+        .iter()
+        .all(|(chunk_pos, pos, _)| pos.y == 32 && chunk_pos.y == 1));
+    assert_eq!(
+        query::get_block(&chunks, IVec3::new(-2, 31, 0)),
+        Ok(Some(stone.clone()))
+    );
+    // the neighbor's copy of a border block follows
+    let padding = chunks
+        .read(&IVec3::ZERO, |_, chunk| {
+            chunk.block_at(UVec3::new(0, 32, 1)).cloned()
+        })
+        .unwrap();
+    assert_eq!(padding, Some(stone.clone()));
+
+    let replace = BulkEdit::Replace {
+        shape: Shape::Box {
+            min: IVec3::new(-40, 0, -40),
+            max: IVec3::new(40, 31, 40),
+        },
+        from: BlockState::parse("core::stone").unwrap(),
+        to: log.clone(),
+    };
+    let applied = apply(&replace, &chunks, &registry);
+    assert!(applied.parked.is_empty());
+    assert_eq!(
+        query::get_block(&chunks, IVec3::new(1, 30, 0)),
+        Ok(Some(log.clone()))
+    );
+
+    let clipboard = Clipboard::copy(&chunks, IVec3::new(-2, 30, 0), IVec3::new(1, 31, 0)).unwrap();
+    assert_eq!(clipboard.size(), UVec3::new(4, 2, 1));
+    assert_eq!(clipboard.get(IVec3::new(3, 1, 0)), Some(Some(&log)));
+    assert_eq!(clipboard.get(IVec3::new(4, 0, 0)), None);
+    let paste = BulkEdit::Paste {
+        origin: IVec3::new(5, 0, 5),
+        clipboard,
+    };
+    apply(&paste, &chunks, &registry);
+    assert_eq!(
+        query::get_block(&chunks, IVec3::new(8, 1, 5)),
+        Ok(Some(log))
+    );
+    assert_eq!(query::get_block(&chunks, IVec3::new(9, 1, 5)), Ok(None));
+}
+
+#[test]
+fn test_transform_clipboard() {
+    let log = BlockState::parse("core::log[axis=x]").unwrap();
+    let furnace = BlockState::parse("core::furnace[facing=north,lit=false]").unwrap();
+    let clipboard = Clipboard::from_fn(UVec3::new(2, 1, 3), |offset| match offset.to_array() {
+        [0, 0, 0] => Some(log.clone()),
+        [1, 0, 2] => Some(furnace.clone()),
+        _ => None,
+    });
+
+    let turned = clipboard.transformed(Rotation::Clockwise90, Mirror::None);
+    assert_eq!(turned.size(), UVec3::new(3, 1, 2));
+    assert_eq!(
+        turned.get(IVec3::new(2, 0, 0)),
+        Some(Some(&BlockState::parse("core::log[axis=z]").unwrap()))
+    );
+    assert_eq!(
+        turned.get(IVec3::new(0, 0, 1)),
+        Some(Some(
+            &BlockState::parse("core::furnace[facing=east,lit=false]").unwrap()
+        ))
+    );
+    assert_eq!(
+        turned.transformed(Rotation::Clockwise270, Mirror::None),
+        clipboard
+    );
+
+    let mirrored = clipboard.transformed(Rotation::None, Mirror::Z);
+    assert_eq!(mirrored.get(IVec3::new(0, 0, 2)), Some(Some(&log)));
+    assert_eq!(
+        mirrored.get(IVec3::new(1, 0, 0)),
+        Some(Some(
+            &BlockState::parse("core::furnace[facing=south,lit=false]").unwrap()
+        ))
+    );
+    // turning twice is a point reflection, not a mirror
+    let half = clipboard.transformed(Rotation::Clockwise180, Mirror::None);
+    assert_eq!(half.get(IVec3::new(1, 0, 2)), Some(Some(&log)));
+}
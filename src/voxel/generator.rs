@@ -1,10 +1,90 @@
-use bevy::math::IVec3;
+use std::sync::Arc;
 
-use super::voxel_block::BlockId;
+use bevy::math::{IVec3, UVec3};
+use ndshape::ConstShape as _;
+
+use super::chunk::{ChunkData, PaddedChunkShape, CHUNK_SIZE};
+use super::palette::PaletteError;
+use super::voxel_block::{BlockId, BlockState};
+use self::flat::FlatGenerator;
+use self::noise::{NoiseConfig, NoiseGenerator};
 
 pub mod flat;
 pub mod noise;
 
 pub trait Generator: Sync + Send {
     fn generate(&self, pos: IVec3) -> BlockId;
-}
\ No newline at end of file
+
+    /// Fill every voxel of `chunk`, padding included, which is all air at `chunk.pos`.
+    /// Has to agree with `generate`, which still answers for single blocks.
+    /// The default asks `generate` voxel by voxel, generators that can should sample
+    /// per column and write palette indices directly.
+    fn generate_chunk(&self, chunk: &mut ChunkData) -> Result<(), PaletteError> {
+        let origin = padded_origin(chunk.pos);
+        for i in 0..PaddedChunkShape::SIZE {
+            let pos = origin + UVec3::from_array(PaddedChunkShape::delinearize(i)).as_ivec3();
+            let voxel = chunk.voxel_block(&BlockState::from(self.generate(pos)))?;
+            chunk.voxels.set(i as usize, voxel);
+        }
+        Ok(())
+    }
+}
+
+/// World position of the padded voxel (0, 0, 0) of chunk `chunk_pos`.
+#[inline]
+pub fn padded_origin(chunk_pos: IVec3) -> IVec3 {
+    chunk_pos * CHUNK_SIZE as i32 - IVec3::ONE
+}
+
+/// Which generator a world was created with and its parameters, stored in its metadata.
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub enum GeneratorKind {
+    Flat,
+    Noise(NoiseConfig),
+}
+
+impl GeneratorKind {
+    pub fn build(&self, seed: u32) -> Arc<dyn Generator> {
+        match self {
+            GeneratorKind::Flat => Arc::new(FlatGenerator::new()),
+            GeneratorKind::Noise(config) => Arc::new(NoiseGenerator::new(seed, config)),
+        }
+    }
+}
+
+#[test]
+fn test_generate_chunk_matches_voxels() {
+    use bevy::prelude::Entity;
+
+    /// Only `generate`, so `generate_chunk` is the default.
+    struct PerVoxel<'a>(&'a dyn Generator);
+
+    impl Generator for PerVoxel<'_> {
+        fn generate(&self, pos: IVec3) -> BlockId {
+            self.0.generate(pos)
+        }
+    }
+
+    let generators: [(Arc<dyn Generator>, &[IVec3]); 2] = [
+        (
+            GeneratorKind::Flat.build(0),
+            &[IVec3::ZERO, IVec3::NEG_Y, IVec3::new(3, 1, -2)],
+        ),
+        (
+            GeneratorKind::Noise(NoiseConfig::default()).build(1234),
+            &[IVec3::ZERO, IVec3::new(-5, 0, 12), IVec3::new(40, -1, -40)],
+        ),
+    ];
+    for (generator, chunks) in generators {
+        for &chunk_pos in chunks {
+            let mut fast = ChunkData::new(chunk_pos, Entity::PLACEHOLDER);
+            generator.generate_chunk(&mut fast).unwrap();
+            let mut slow = ChunkData::new(chunk_pos, Entity::PLACEHOLDER);
+            PerVoxel(&*generator).generate_chunk(&mut slow).unwrap();
+            for i in 0..PaddedChunkShape::SIZE {
+                let pos = UVec3::from_array(PaddedChunkShape::delinearize(i));
+                assert_eq!(fast.block_at(pos), slow.block_at(pos), "{chunk_pos} {pos}");
+            }
+        }
+    }
+}
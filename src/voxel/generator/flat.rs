@@ -1,8 +1,11 @@
 use bevy::prelude::IVec3;
+use ndshape::ConstShape as _;
 
-use crate::voxel::voxel_block::BlockId;
+use crate::voxel::chunk::{ChunkData, PaddedChunkShape, PADDED_CHUNK_SIZE};
+use crate::voxel::palette::PaletteError;
+use crate::voxel::voxel_block::{BlockId, BlockState};
 
-use super::Generator;
+use super::{padded_origin, Generator};
 
 pub struct FlatGenerator;
 
@@ -14,6 +17,22 @@ impl Generator for FlatGenerator {
             "core::air"
         })
     }
+
+    /// Only the chunks whose padding reaches y 0 get any blocks.
+    fn generate_chunk(&self, chunk: &mut ChunkData) -> Result<(), PaletteError> {
+        let y = -padded_origin(chunk.pos).y;
+        if !(0..PADDED_CHUNK_SIZE as i32).contains(&y) {
+            return Ok(());
+        }
+        let grass = chunk.voxel_block(&BlockState::from(BlockId::new("core::grass")))?;
+        for z in 0..PADDED_CHUNK_SIZE {
+            for x in 0..PADDED_CHUNK_SIZE {
+                let i = PaddedChunkShape::linearize([x, y as u32, z]);
+                chunk.voxels.set(i as usize, grass);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl FlatGenerator {
@@ -1,38 +1,95 @@
 use bevy::prelude::IVec3;
+use ndshape::ConstShape as _;
 use noise::{HybridMulti, NoiseFn, Perlin};
+use parking_lot::Mutex;
 
-use crate::voxel::voxel_block::{BlockId, AIR};
+use crate::voxel::chunk::{ChunkData, PaddedChunkShape, PADDED_CHUNK_SIZE};
+use crate::voxel::palette::PaletteError;
+use crate::voxel::voxel_block::{BlockId, BlockState, AIR};
 
-use super::Generator;
+use super::{padded_origin, Generator};
+
+/// Columns whose height is remembered, a few chunk columns worth.
+const CACHE_SLOTS: usize = 1 << 14;
+
+/// Terrain parameters of `NoiseGenerator`. Stored in the world metadata next to the seed,
+/// so a world keeps its terrain when the defaults change.
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub struct NoiseConfig {
+    pub octaves: u32,
+    pub frequency: f64,
+    pub lacunarity: f64,
+    pub persistence: f64,
+    /// blocks per noise unit along x and z
+    pub scale: f64,
+    /// terrain height in blocks at a noise value of 1
+    pub amplitude: f64,
+}
+
+impl Default for NoiseConfig {
+    /// What every world was generated with before the parameters were stored.
+    fn default() -> Self {
+        NoiseConfig {
+            octaves: 5,
+            frequency: 1.1,
+            lacunarity: 2.8,
+            persistence: 0.4,
+            scale: 1000.0,
+            amplitude: 50.0,
+        }
+    }
+}
 
 pub struct NoiseGenerator {
     noise: HybridMulti<Perlin>,
-    cache: papaya::HashMap<(i32, i32), f64, ahash::RandomState>,
+    config: NoiseConfig,
+    /// direct mapped, a column evicts whatever was in its slot
+    cache: Box<[Mutex<Option<((i32, i32), f64)>>]>,
 }
 
 impl NoiseGenerator {
-    pub fn new() -> Self {
-        let mut noise = HybridMulti::<Perlin>::new(1234);
-        noise.octaves = 5;
-        noise.frequency = 1.1;
-        noise.lacunarity = 2.8;
-        noise.persistence = 0.4;
-        let cache = papaya::HashMap::default();
-
-        NoiseGenerator { noise, cache }
+    pub fn new(seed: u32, config: &NoiseConfig) -> Self {
+        let mut noise = HybridMulti::<Perlin>::new(seed);
+        noise.octaves = config.octaves as usize;
+        noise.frequency = config.frequency;
+        noise.lacunarity = config.lacunarity;
+        noise.persistence = config.persistence;
+        let cache = (0..CACHE_SLOTS).map(|_| Mutex::new(None)).collect();
+
+        NoiseGenerator {
+            noise,
+            config: config.clone(),
+            cache,
+        }
+    }
+
+    /// Terrain height of the column at `x`, `z`.
+    pub fn sample(&self, x: i32, z: i32) -> f64 {
+        let hash = (x as u32).wrapping_mul(0x9e37_79b1) ^ (z as u32).wrapping_mul(0x85eb_ca77);
+        let slot = &self.cache[hash as usize % CACHE_SLOTS];
+        // another thread on the slot is rare, computing beats waiting for it
+        let mut slot = slot.try_lock();
+        if let Some(Some((column, sample))) = slot.as_deref() {
+            if *column == (x, z) {
+                return *sample;
+            }
+        }
+
+        let scale = self.config.scale;
+        let sample = self.noise.get([x as f64 / scale, z as f64 / scale]) * self.config.amplitude;
+        if let Some(slot) = slot.as_deref_mut() {
+            *slot = Some(((x, z), sample));
+        }
+        sample
     }
 }
 
 impl Generator for NoiseGenerator {
     fn generate(&self, pos: IVec3) -> BlockId {
-        let [x, y, z] = pos.as_dvec3().to_array();
-
-        let sample = *self.cache.pin().get_or_insert_with((pos.x, pos.z), || {
-            self.noise.get([x / 1000.0, z / 1000.0]) * 50.0
-        });
+        let sample = self.sample(pos.x, pos.z);
 
         // If y is less than the noise sample, we will set the voxel to solid
-        let is_ground = y < sample;
+        let is_ground = (pos.y as f64) < sample;
 
         if is_ground {
             BlockId::new("core::grass")
@@ -40,4 +97,46 @@ impl Generator for NoiseGenerator {
             AIR.clone()
         }
     }
+
+    /// One sample per column, the ground below it is filled bottom up.
+    fn generate_chunk(&self, chunk: &mut ChunkData) -> Result<(), PaletteError> {
+        let origin = padded_origin(chunk.pos);
+        let grass = chunk.voxel_block(&BlockState::from(BlockId::new("core::grass")))?;
+        for z in 0..PADDED_CHUNK_SIZE {
+            for x in 0..PADDED_CHUNK_SIZE {
+                let sample = self.sample(origin.x + x as i32, origin.z + z as i32);
+                for y in 0..PADDED_CHUNK_SIZE {
+                    if (origin.y + y as i32) as f64 >= sample {
+                        break;
+                    }
+                    let i = PaddedChunkShape::linearize([x, y, z]);
+                    chunk.voxels.set(i as usize, grass);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_noise_seeded() {
+    let config = NoiseConfig::default();
+    let a = NoiseGenerator::new(1234, &config);
+    let b = NoiseGenerator::new(1234, &config);
+    let other = NoiseGenerator::new(4321, &config);
+
+    let columns = (0..4 * CACHE_SLOTS as i32).map(|i| (i * 7 - 5000, i / 3 - 40));
+    let samples = columns
+        .clone()
+        .map(|(x, z)| a.sample(x, z))
+        .collect::<Vec<_>>();
+    // far more columns than slots: evicted samples come back the same
+    for ((x, z), sample) in columns.clone().zip(&samples) {
+        assert_eq!(a.sample(x, z), *sample);
+        assert_eq!(b.sample(x, z), *sample);
+    }
+    assert_eq!(a.cache.len(), CACHE_SLOTS);
+    assert!(columns
+        .zip(&samples)
+        .any(|((x, z), sample)| other.sample(x, z) != *sample));
 }
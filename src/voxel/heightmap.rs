@@ -0,0 +1,297 @@
+//! Highest block of every column of a chunk, for spawn finding, lighting, rain and maps.
+//! Kept per kind of "highest", computed when a chunk is built, updated as its blocks are
+//! placed and saved with it. Columns spanning chunks are answered by `height`, which
+//! walks the loaded chunks down from the top instead of looking at every block.
+
+use std::ops::RangeInclusive;
+
+use ahash::AHashMap;
+use bevy::math::{IVec2, IVec3, UVec3};
+use bincode::{Decode, Encode};
+use ndshape::ConstShape as _;
+
+use crate::core::registry::Registry;
+
+use super::chunk::{get_chunk_voxel_position, ChunkData, PaddedChunkShape, CHUNK_SIZE};
+use super::query::QueryError;
+use super::voxel_block::{BlockState, VoxelBlock};
+use super::world::LoadedChunks;
+
+const COLUMNS: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeightmapKind {
+    /// anything but air
+    WorldSurface,
+    /// anything not `passable`, liquids included
+    MotionBlocking,
+    /// anything neither `passable` nor `liquid`, the floor below water
+    OceanFloor,
+}
+
+impl HeightmapKind {
+    pub const ALL: [HeightmapKind; 3] = [
+        HeightmapKind::WorldSurface,
+        HeightmapKind::MotionBlocking,
+        HeightmapKind::OceanFloor,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HeightmapKind::WorldSurface => "world_surface",
+            HeightmapKind::MotionBlocking => "motion_blocking",
+            HeightmapKind::OceanFloor => "ocean_floor",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        HeightmapKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == name)
+    }
+
+    fn counts(self, flags: BlockFlags) -> bool {
+        match self {
+            HeightmapKind::WorldSurface => true,
+            HeightmapKind::MotionBlocking => !flags.passable,
+            HeightmapKind::OceanFloor => !flags.passable && !flags.liquid,
+        }
+    }
+}
+
+/// What the heightmaps need to know of a non-air block.
+/// Unregistered blocks are solid, like every block before these flags existed.
+#[derive(Debug, Clone, Copy, Default)]
+struct BlockFlags {
+    liquid: bool,
+    passable: bool,
+}
+
+impl BlockFlags {
+    fn of(registry: &Registry, state: &BlockState) -> Self {
+        registry
+            .get_block_with(state.id(), |block| BlockFlags {
+                liquid: block.metadata.liquid,
+                passable: block.metadata.passable,
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Heights of the 32×32 columns of a chunk, kind after kind, z-major. A height is the
+/// padded y of the highest block of the kind, 0 if the column has none inside the chunk.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Heightmaps {
+    heights: Vec<u8>,
+}
+
+impl Heightmaps {
+    pub fn compute(chunk: &ChunkData, registry: &Registry) -> Self {
+        let mut heightmaps = Heightmaps {
+            heights: vec![0; COLUMNS * HeightmapKind::ALL.len()],
+        };
+        // a palette index looks the same up every time
+        let mut flags = AHashMap::new();
+        for z in 1..=CHUNK_SIZE {
+            for x in 1..=CHUNK_SIZE {
+                heightmaps.scan_column(chunk, registry, &mut flags, x, z);
+            }
+        }
+        heightmaps
+    }
+
+    /// Stored heightmaps of another chunk size are recomputed.
+    pub fn is_valid(&self) -> bool {
+        self.heights.len() == COLUMNS * HeightmapKind::ALL.len()
+            && self
+                .heights
+                .iter()
+                .all(|&height| height as u32 <= CHUNK_SIZE)
+    }
+
+    /// Padded y of the highest block of `kind` in the column at padded `x`, `z`.
+    pub fn get(&self, kind: HeightmapKind, x: u32, z: u32) -> Option<u32> {
+        match self.heights[Self::index(kind, x, z)] {
+            0 => None,
+            height => Some(height as u32),
+        }
+    }
+
+    /// The block at padded `pos` of `chunk` is now `state`. Only a column whose highest
+    /// block got replaced by one that does not count is scanned again, below `pos`.
+    pub fn update(
+        &mut self,
+        chunk: &ChunkData,
+        registry: &Registry,
+        pos: UVec3,
+        state: &BlockState,
+    ) {
+        let flags = (!state.is_air()).then(|| BlockFlags::of(registry, state));
+        let y = pos.y as u8;
+        for kind in HeightmapKind::ALL {
+            let index = Self::index(kind, pos.x, pos.z);
+            let height = self.heights[index];
+            match flags {
+                Some(flags) if kind.counts(flags) => self.heights[index] = height.max(y),
+                _ if height == y => {
+                    self.heights[index] = Self::scan(chunk, pos.x, pos.z, pos.y - 1, |state| {
+                        kind.counts(BlockFlags::of(registry, state))
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn scan_column(
+        &mut self,
+        chunk: &ChunkData,
+        registry: &Registry,
+        flags: &mut AHashMap<u16, BlockFlags>,
+        x: u32,
+        z: u32,
+    ) {
+        for kind in HeightmapKind::ALL {
+            let mut counts = |idx: u16| {
+                let flags = *flags.entry(idx).or_insert_with(|| {
+                    chunk
+                        .palette
+                        .block_state(idx)
+                        .map(|state| BlockFlags::of(registry, state))
+                        .unwrap_or_default()
+                });
+                kind.counts(flags)
+            };
+            let height = (1..=CHUNK_SIZE)
+                .rev()
+                .find(|&y| {
+                    let index = PaddedChunkShape::linearize([x, y, z]) as usize;
+                    match chunk.voxels.get(index) {
+                        VoxelBlock::Air => false,
+                        VoxelBlock::Solid(idx) => counts(idx),
+                    }
+                })
+                .unwrap_or(0);
+            self.heights[Self::index(kind, x, z)] = height as u8;
+        }
+    }
+
+    /// Padded y of the highest block from `top` down that `counts`, 0 if none.
+    fn scan(
+        chunk: &ChunkData,
+        x: u32,
+        z: u32,
+        top: u32,
+        counts: impl Fn(&BlockState) -> bool,
+    ) -> u8 {
+        (1..=top)
+            .rev()
+            .find(|&y| chunk.block_at(UVec3::new(x, y, z)).is_some_and(&counts))
+            .unwrap_or(0) as u8
+    }
+
+    #[inline]
+    fn index(kind: HeightmapKind, x: u32, z: u32) -> usize {
+        kind as usize * COLUMNS + ((z - 1) * CHUNK_SIZE + x - 1) as usize
+    }
+}
+
+/// Y of the highest block of `kind` in the column at `column` (x, z) within `y`, `None` if
+/// there is none. `y` is widened to whole chunks, whose heightmaps answer without scanning
+/// blocks. Only fails on an unloaded chunk it has to look into.
+pub fn height(
+    chunks: &LoadedChunks,
+    kind: HeightmapKind,
+    column: IVec2,
+    y: RangeInclusive<i32>,
+) -> Result<Option<i32>, QueryError> {
+    let (bottom, _) = get_chunk_voxel_position(IVec3::new(column.x, *y.start(), column.y));
+    let (top, voxel_pos) = get_chunk_voxel_position(IVec3::new(column.x, *y.end(), column.y));
+    for chunk_y in (bottom.y..=top.y).rev() {
+        let chunk_pos = IVec3::new(top.x, chunk_y, top.z);
+        let found = chunks
+            .read(&chunk_pos, |_, chunk| {
+                chunk.height(kind, voxel_pos.x, voxel_pos.z)
+            })
+            .ok_or(QueryError::NotLoaded(chunk_pos))?;
+        if let Some(voxel_y) = found {
+            return Ok(Some(chunk_y * CHUNK_SIZE as i32 + voxel_y as i32 - 1));
+        }
+    }
+    Ok(None)
+}
+
+#[test]
+fn test_heightmaps() {
+    use bevy::prelude::Entity;
+
+    let registry = Registry::new();
+    let lua = mlua::Lua::new();
+    lua.globals().set("Registry", registry.clone()).unwrap();
+    lua.globals().set("namespace", "core").unwrap();
+    lua.load(
+        r#"
+        Registry:set_block("stone", { textures = { top = "stone.png" } })
+        Registry:set_block("water", { liquid = true, textures = { top = "water.png" } })
+        Registry:set_block("flower", { passable = true, textures = { top = "flower.png" } })
+        "#,
+    )
+    .exec()
+    .unwrap();
+    let air = BlockState::parse("core::air").unwrap();
+    let stone = BlockState::parse("core::stone").unwrap();
+    let water = BlockState::parse("core::water").unwrap();
+    let flower = BlockState::parse("core::flower").unwrap();
+
+    let mut chunk = ChunkData::new(IVec3::ZERO, Entity::PLACEHOLDER);
+    chunk.set_block(UVec3::new(1, 3, 1), &stone).unwrap();
+    chunk.set_block(UVec3::new(1, 4, 1), &water).unwrap();
+    chunk.set_block(UVec3::new(1, 5, 1), &water).unwrap();
+    chunk.set_block(UVec3::new(1, 6, 1), &flower).unwrap();
+    // padding belongs to the chunk above
+    chunk.set_block(UVec3::new(1, 33, 1), &stone).unwrap();
+    let heightmaps = Heightmaps::compute(&chunk, &registry);
+    assert!(heightmaps.is_valid());
+    assert_eq!(heightmaps.get(HeightmapKind::WorldSurface, 1, 1), Some(6));
+    assert_eq!(heightmaps.get(HeightmapKind::MotionBlocking, 1, 1), Some(5));
+    assert_eq!(heightmaps.get(HeightmapKind::OceanFloor, 1, 1), Some(3));
+    assert_eq!(heightmaps.get(HeightmapKind::WorldSurface, 2, 1), None);
+    chunk.heightmaps = Some(heightmaps);
+
+    // placing keeps them up to date, removing the top scans down
+    chunk
+        .place_block(UVec3::new(1, 10, 1), &stone, &registry)
+        .unwrap();
+    assert_eq!(chunk.height(HeightmapKind::OceanFloor, 1, 1), Some(10));
+    chunk
+        .place_block(UVec3::new(1, 10, 1), &air, &registry)
+        .unwrap();
+    chunk
+        .place_block(UVec3::new(1, 5, 1), &flower, &registry)
+        .unwrap();
+    assert_eq!(chunk.height(HeightmapKind::WorldSurface, 1, 1), Some(6));
+    assert_eq!(chunk.height(HeightmapKind::MotionBlocking, 1, 1), Some(4));
+    assert_eq!(chunk.height(HeightmapKind::OceanFloor, 1, 1), Some(3));
+    assert_eq!(
+        chunk.heightmaps,
+        Some(Heightmaps::compute(&chunk, &registry))
+    );
+
+    let chunks = LoadedChunks::default();
+    chunks.insert(IVec3::ZERO, chunk).ok();
+    let mut above = ChunkData::new(IVec3::Y, Entity::PLACEHOLDER);
+    above.heightmaps = Some(Heightmaps::compute(&above, &registry));
+    chunks.insert(IVec3::Y, above).ok();
+    assert_eq!(
+        height(&chunks, HeightmapKind::MotionBlocking, IVec2::ZERO, 0..=40),
+        Ok(Some(3))
+    );
+    assert_eq!(
+        height(&chunks, HeightmapKind::OceanFloor, IVec2::new(1, 0), 0..=40),
+        Ok(None)
+    );
+    assert_eq!(
+        height(&chunks, HeightmapKind::WorldSurface, IVec2::X, -1..=40),
+        Err(QueryError::NotLoaded(IVec3::NEG_Y))
+    );
+}
@@ -0,0 +1,348 @@
+//! Undo and redo of the edits applied through `VoxelModifier`. Every flush records the
+//! block each edit replaced: the single edits of a frame form one group, every bulk edit
+//! its own, so undo reverts a whole fill or paste at once. Block entities are not restored,
+//! an undone block gets the default entity of its block like any other placed block.
+
+use std::collections::VecDeque;
+
+use ahash::{AHashMap, AHashSet};
+use bevy::math::IVec3;
+use bevy::prelude::{ButtonInput, Entity, KeyCode, Res, Resource};
+use rayon::prelude::*;
+
+use super::chunk::{get_chunk_voxel_position, ChunkData, ModifiedVoxels};
+use super::generator::Generator;
+use super::modifier::VoxelModifier;
+use super::storage::WorldStorage;
+use super::voxel_block::{BlockState, AIR};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockChange {
+    pub pos: IVec3,
+    /// air included
+    pub before: BlockState,
+    pub after: BlockState,
+}
+
+/// Changes undone and redone together, in the order they were applied.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EditGroup {
+    pub changes: Vec<BlockChange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryStep {
+    Undo,
+    Redo,
+}
+
+/// Bounded undo stack, the oldest group is dropped once `limit` is reached.
+/// A new group clears the redo stack.
+#[derive(Resource, Debug)]
+pub struct EditHistory {
+    undo: VecDeque<EditGroup>,
+    redo: Vec<EditGroup>,
+    limit: usize,
+}
+
+impl EditHistory {
+    pub fn new(limit: usize) -> Self {
+        EditHistory {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Groups without changes are not recorded.
+    pub fn push(&mut self, group: EditGroup) {
+        if group.changes.is_empty() || self.limit == 0 {
+            return;
+        }
+        self.redo.clear();
+        if self.undo.len() == self.limit {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(group);
+    }
+
+    /// Blocks to write to revert the last group, `None` if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<Vec<(IVec3, BlockState)>> {
+        let group = self.undo.pop_back()?;
+        // backwards, so a block changed twice ends up as it was before the first change
+        let blocks = group
+            .changes
+            .iter()
+            .rev()
+            .map(|change| (change.pos, change.before.clone()))
+            .collect();
+        self.redo.push(group);
+        Some(blocks)
+    }
+
+    /// Blocks to write to apply the last undone group again, `None` if there is nothing to redo.
+    pub fn redo(&mut self) -> Option<Vec<(IVec3, BlockState)>> {
+        let group = self.redo.pop()?;
+        let blocks = group
+            .changes
+            .iter()
+            .map(|change| (change.pos, change.after.clone()))
+            .collect();
+        self.undo.push_back(group);
+        Some(blocks)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// Blocks of unloaded chunks as they will be once loaded, to record what a parked edit
+/// replaces: an edit still parked in `ModifiedVoxels`, then a pending edit, the stored
+/// chunk and at last the generator, like `BuildChunkTaskInner::build` picks them.
+/// Each chunk is resolved once, as a whole, and never while `ModifiedVoxels` is locked.
+pub struct UnloadedBlocks<'a> {
+    storage: &'a WorldStorage,
+    generator: &'a dyn Generator,
+    chunks: AHashMap<IVec3, UnloadedChunk>,
+}
+
+/// An unloaded chunk as its build will make it.
+struct UnloadedChunk {
+    /// parked edits over pending ones
+    edits: AHashMap<IVec3, BlockState>,
+    /// stored, or generated if it is not stored or could not be decoded
+    chunk: ChunkData,
+}
+
+impl<'a> UnloadedBlocks<'a> {
+    pub fn new(storage: &'a WorldStorage, generator: &'a dyn Generator) -> Self {
+        UnloadedBlocks {
+            storage,
+            generator,
+            chunks: AHashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, modified: &ModifiedVoxels, pos: IVec3) -> BlockState {
+        let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+        self.prefetch(modified, [chunk_pos]);
+        let unloaded = &self.chunks[&chunk_pos];
+        match unloaded.edits.get(&pos) {
+            Some(state) => state.clone(),
+            None => unloaded
+                .chunk
+                .block_at(voxel_pos)
+                .cloned()
+                .unwrap_or_else(|| BlockState::from(AIR.clone())),
+        }
+    }
+
+    /// Resolve the chunks not seen yet, in parallel, with a single read of `modified`.
+    /// Called with every chunk of a bulk edit before its blocks are looked up.
+    pub fn prefetch(
+        &mut self,
+        modified: &ModifiedVoxels,
+        chunk_positions: impl IntoIterator<Item = IVec3>,
+    ) {
+        let missing = chunk_positions
+            .into_iter()
+            .filter(|chunk_pos| !self.chunks.contains_key(chunk_pos))
+            .collect::<AHashSet<_>>();
+        if missing.is_empty() {
+            return;
+        }
+        let parked = {
+            let modified = modified.read();
+            missing
+                .into_iter()
+                .map(|chunk_pos| {
+                    (
+                        chunk_pos,
+                        modified.get(&chunk_pos).cloned().unwrap_or_default(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let (storage, generator) = (self.storage, self.generator);
+        let resolved = parked
+            .into_par_iter()
+            .map(|(chunk_pos, parked)| {
+                (
+                    chunk_pos,
+                    resolve_chunk(storage, generator, chunk_pos, parked),
+                )
+            })
+            .collect::<Vec<_>>();
+        self.chunks.extend(resolved);
+    }
+
+    /// An edit parked after its chunk was resolved, what later edits to `pos` replace.
+    pub fn park(&mut self, chunk_pos: IVec3, pos: IVec3, state: BlockState) {
+        if let Some(unloaded) = self.chunks.get_mut(&chunk_pos) {
+            unloaded.edits.insert(pos, state);
+        }
+    }
+}
+
+fn resolve_chunk(
+    storage: &WorldStorage,
+    generator: &dyn Generator,
+    chunk_pos: IVec3,
+    parked: AHashMap<IVec3, BlockState>,
+) -> UnloadedChunk {
+    let mut edits = storage
+        .load_pending_edits(chunk_pos)
+        .unwrap_or_else(|err| {
+            tracing::error!(
+                "load pending edits of chunk {} failed: {:?}",
+                chunk_pos,
+                err
+            );
+            Vec::new()
+        })
+        .into_iter()
+        .collect::<AHashMap<_, _>>();
+    edits.extend(parked);
+    let stored = storage.load(chunk_pos).ok().flatten().and_then(|bytes| {
+        ChunkData::decode(&bytes)
+            .map_err(|err| {
+                tracing::warn!(
+                    "decode chunk {} for edit history failed: {:?}",
+                    chunk_pos,
+                    err
+                )
+            })
+            .ok()
+    });
+    let chunk = stored.unwrap_or_else(|| {
+        let mut chunk = ChunkData::new(chunk_pos, Entity::PLACEHOLDER);
+        if let Err(err) = generator.generate_chunk(&mut chunk) {
+            tracing::error!(
+                "generate chunk {} for edit history failed: {}",
+                chunk_pos,
+                err
+            );
+        }
+        chunk
+    });
+    UnloadedChunk { edits, chunk }
+}
+
+/// Ctrl+Z undoes the last edit, Ctrl+Y or Ctrl+Shift+Z redoes it.
+pub fn history_hotkey(keys: Res<ButtonInput<KeyCode>>, modifier: Res<VoxelModifier>) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::KeyY) || (shift && keys.just_pressed(KeyCode::KeyZ)) {
+        modifier.redo();
+    } else if keys.just_pressed(KeyCode::KeyZ) {
+        modifier.undo();
+    }
+}
+
+#[test]
+fn test_undo_redo() {
+    let air = BlockState::from(AIR.clone());
+    let stone = BlockState::parse("core::stone").unwrap();
+    let dirt = BlockState::parse("core::dirt").unwrap();
+    let change = |pos: IVec3, before: &BlockState, after: &BlockState| BlockChange {
+        pos,
+        before: before.clone(),
+        after: after.clone(),
+    };
+
+    let mut history = EditHistory::new(2);
+    assert_eq!(history.undo(), None);
+    history.push(EditGroup::default());
+    assert!(!history.can_undo());
+
+    // the same block changed twice in one group
+    history.push(EditGroup {
+        changes: vec![
+            change(IVec3::ZERO, &air, &stone),
+            change(IVec3::ZERO, &stone, &dirt),
+        ],
+    });
+    assert_eq!(
+        history.undo(),
+        Some(vec![
+            (IVec3::ZERO, stone.clone()),
+            (IVec3::ZERO, air.clone())
+        ])
+    );
+    assert!(history.can_redo());
+    assert_eq!(
+        history.redo(),
+        Some(vec![
+            (IVec3::ZERO, stone.clone()),
+            (IVec3::ZERO, dirt.clone())
+        ])
+    );
+    assert!(!history.can_redo());
+
+    // a new group clears the redo stack, the oldest group falls off
+    history.undo();
+    history.push(EditGroup {
+        changes: vec![change(IVec3::X, &air, &stone)],
+    });
+    assert!(!history.can_redo());
+    history.push(EditGroup {
+        changes: vec![change(IVec3::Y, &air, &stone)],
+    });
+    history.push(EditGroup {
+        changes: vec![change(IVec3::Z, &air, &stone)],
+    });
+    assert_eq!(history.undo(), Some(vec![(IVec3::Z, air.clone())]));
+    assert_eq!(history.undo(), Some(vec![(IVec3::Y, air)]));
+    assert_eq!(history.undo(), None);
+}
+
+#[test]
+fn test_unloaded_blocks() {
+    use bevy::math::UVec3;
+
+    use super::generator::flat::FlatGenerator;
+    use super::storage::{MemoryStorage, WorldMetadata};
+
+    let storage = WorldStorage::open(MemoryStorage::new(), WorldMetadata::default).unwrap();
+    let generator = FlatGenerator::new();
+    let stone = BlockState::parse("core::stone").unwrap();
+    let dirt = BlockState::parse("core::dirt").unwrap();
+
+    let stored = IVec3::new(3, 0, 0);
+    let mut chunk = ChunkData::new(stored, Entity::PLACEHOLDER);
+    chunk.set_block(UVec3::new(1, 2, 3), &stone).unwrap();
+    chunk.generate_hash();
+    storage
+        .save(&[(stored, &chunk.encode().unwrap()[..])])
+        .unwrap();
+    storage
+        .save_pending_edits(&[(stored, IVec3::new(96, 0, 0), dirt.clone())])
+        .unwrap();
+
+    let modified = ModifiedVoxels::default();
+    modified
+        .write()
+        .entry(stored)
+        .or_default()
+        .insert(IVec3::new(97, 0, 0), stone.clone());
+    let mut blocks = UnloadedBlocks::new(&storage, &generator);
+    assert_eq!(blocks.get(&modified, IVec3::new(97, 0, 0)), stone);
+    assert_eq!(blocks.get(&modified, IVec3::new(96, 0, 0)), dirt);
+    blocks.park(stored, IVec3::new(96, 0, 0), stone.clone());
+    assert_eq!(blocks.get(&modified, IVec3::new(96, 0, 0)), stone);
+    assert_eq!(blocks.get(&modified, IVec3::new(96, 1, 2)), stone);
+    assert!(blocks.get(&modified, IVec3::new(96, 5, 5)).is_air());
+    // never stored, so as the generator makes it
+    let generated = IVec3::new(-40, -3, 7);
+    assert_eq!(
+        blocks.get(&modified, generated),
+        BlockState::from(generator.generate(generated))
+    );
+}
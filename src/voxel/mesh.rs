@@ -51,10 +51,11 @@ pub fn generate_chunk_mesh(
     let _span = tracing::info_span!("profiling::{generate mesh}").entered();
     let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
 
-    let mut buffer = GreedyQuadsBuffer::new(chunk_data.voxels.len());
-    //let voxels = chunk_data.voxels.iter().collect::<Vec<_>>();
+    // block_mesh reads a plain slice
+    let voxels = chunk_data.voxels.unpack();
+    let mut buffer = GreedyQuadsBuffer::new(voxels.len());
     greedy_quads(
-        &chunk_data.voxels,
+        &voxels,
         &PaddedChunkShape {},
         [0; 3],
         [CHUNK_SIZE + 1; 3],
@@ -99,14 +100,14 @@ pub fn generate_chunk_mesh(
             let normal = face.signed_normal();
 
             let voxel_index = PaddedChunkShape::linearize(quad.minimum) as usize;
-            let voxel = &chunk_data.voxels[voxel_index];
+            let voxel = &voxels[voxel_index];
             let block_idx = match voxel {
                 VoxelBlock::Air => unreachable!("air block in mesh"),
                 VoxelBlock::Solid(id) => id,
             };
-            let block_id = chunk_data
+            let state = chunk_data
                 .palette
-                .block_id(*block_idx)
+                .block_state(*block_idx)
                 .expect("not found block in palette");
 
             let face = if normal.x > 0 {
@@ -126,8 +127,8 @@ pub fn generate_chunk_mesh(
             };
 
             let idx = registry
-                .get_block_with(block_id, |block| {
-                    let path = block.metadata.textures.face(face);
+                .get_block_with(state.id(), |block| {
+                    let path = block.textures(state).face(face);
                     *texture_map.get(path).expect("non-existent texture")
                 })
                 .unwrap();
@@ -1,28 +1,48 @@
+use autosave::{autosave_chunks, install_panic_hook, save_all_chunks, save_on_exit};
 use bevy::app::Plugin;
 use bevy::pbr::ExtendedMaterial;
 use bevy::prelude::*;
 use bevy_asset_loader::loading_state::config::{ConfigureLoadingState, LoadingStateConfig};
 use bevy_asset_loader::loading_state::LoadingStateAppExt;
+use block_entity::rehash_chunks;
 use chunk::*;
 use config::VoxelConfig;
-use generator::noise::NoiseGenerator;
+use history::{history_hotkey, EditHistory};
 use material::{VoxelMaterial, VoxelMaterialHandle};
 use mesh::MeshCache;
 use modifier::VoxelModifier;
+use origin::{rebase_origin, FloatingOrigin};
+use saver::ChunkSaver;
+use snapshot::{snapshot_hotkey, take_snapshot, TakeSnapshot};
+use storage::WorldStorage;
 use textures_loader::{load_textures, unload_textures, BlockTextureAssets, VoxelTextures};
-use world::{VoxelWorld, WorldRoot};
+use world::{run_block_changed_callbacks, LuaWorld, VoxelWorld, WorldRoot};
 
+use crate::core::registry::Registry;
+use crate::script::LuaEngine;
 use crate::state::AppState;
 
+pub mod anvil;
+pub mod autosave;
+pub mod block_entity;
 pub mod chunk;
 pub mod chunk_ref;
 pub mod chunk_task;
 pub mod config;
+pub mod edit;
 pub mod generator;
+pub mod heightmap;
+pub mod history;
 pub mod material;
 pub mod mesh;
 pub mod modifier;
+pub mod origin;
+pub mod padding;
 pub mod palette;
+pub mod query;
+pub mod saver;
+pub mod schematic;
+pub mod snapshot;
 pub mod storage;
 pub mod textures;
 pub mod textures_loader;
@@ -40,11 +60,16 @@ impl Plugin for VoxelEnginePlugin {
         app.init_resource::<VoxelConfig>()
             .init_resource::<ModifiedVoxels>()
             .init_resource::<VoxelModifier>()
+            .init_resource::<FloatingOrigin>()
             .init_resource::<ChunkLoadQueue>()
+            .init_resource::<ChunkErrorQueue>()
+            .add_event::<ChunkError>()
+            .add_event::<BlockChanged>()
             .init_resource::<ChunkUpdateBuffer>()
             .init_resource::<ChunkUnloadBuffer>()
             .init_resource::<MeshCacheBuffer>()
             .init_resource::<MeshCache>()
+            .add_event::<TakeSnapshot>()
             .add_plugins(MaterialPlugin::<
                 ExtendedMaterial<StandardMaterial, VoxelMaterial>,
             >::default())
@@ -53,12 +78,36 @@ impl Plugin for VoxelEnginePlugin {
                 PreUpdate,
                 (
                     (load_visit_chunks, load_chunks).chain(),
-                    (remesh_dirty_chunks, load_chunks_done).chain(),
-                    (flush_voxel_write_buffer, (flush_mesh_cache)).chain(),
+                    // a chunk that finished loading takes the edits parked for it, later
+                    // edits have to find it loaded
+                    (remesh_dirty_chunks, load_chunks_done)
+                        .chain()
+                        .before(flush_voxel_write_buffer),
+                    (flush_voxel_write_buffer, rehash_chunks, flush_mesh_cache).chain(),
                 )
                     .run_if(in_state(AppState::InGame)),
             )
-            .add_systems(Update, (spawn_mesh,).run_if(in_state(AppState::InGame)))
+            .add_systems(
+                Update,
+                (
+                    spawn_mesh,
+                    autosave_chunks,
+                    snapshot_hotkey,
+                    history_hotkey,
+                    take_snapshot,
+                    emit_chunk_errors,
+                    run_block_changed_callbacks,
+                )
+                    .run_if(in_state(AppState::InGame)),
+            )
+            .add_systems(
+                PostUpdate,
+                rebase_origin
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(in_state(AppState::InGame)),
+            )
+            .add_systems(OnExit(AppState::InGame), save_all_chunks)
+            .add_systems(Last, save_on_exit)
             .add_systems(
                 FixedUpdate,
                 (mark_unload_chunks, unload_chunks).run_if(in_state(AppState::InGame)),
@@ -76,16 +125,34 @@ fn setup(
     mut commands: Commands,
     mut material_assets: ResMut<Assets<ExtendedMaterial<StandardMaterial, VoxelMaterial>>>,
     voxel_texture: Res<VoxelTextures>,
+    storage: Res<WorldStorage>,
+    config: Res<VoxelConfig>,
+    errors: Res<ChunkErrorQueue>,
+    modifier: Res<VoxelModifier>,
+    registry: Res<Registry>,
+    lua: Res<LuaEngine>,
 ) {
     let root = commands.spawn((
         WorldRoot,
         VisibilityBundle::default(),
         TransformBundle::default(),
     ));
-    let mut world = VoxelWorld::default().with_generator(NoiseGenerator::new());
+    let metadata = storage.world_metadata().expect("reading world metadata failed");
+    let mut world = VoxelWorld::from_metadata(&metadata);
 
     world.root = root.id();
+    install_panic_hook(&world, &storage);
+    lua.globals()
+        .set("World", LuaWorld::new(&world, &modifier, &registry))
+        .unwrap();
+    commands.insert_resource(ChunkSaver::spawn(
+        storage.clone(),
+        &world,
+        errors.0.clone(),
+        &config,
+    ));
     commands.insert_resource(world);
+    commands.insert_resource(EditHistory::new(config.history_limit as usize));
 
     commands.insert_resource(VoxelMaterialHandle(material_assets.add(ExtendedMaterial {
         base: StandardMaterial {
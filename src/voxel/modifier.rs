@@ -1,27 +1,48 @@
 use bevy::math::IVec3;
 use bevy::prelude::Resource;
 
-use super::voxel_block::BlockId;
+use super::edit::BulkEdit;
+use super::history::HistoryStep;
+use super::voxel_block::BlockState;
 
 #[derive(Resource)]
 pub struct VoxelModifier {
     pub queue: (
-        kanal::Sender<(IVec3, BlockId)>,
-        kanal::Receiver<(IVec3, BlockId)>,
+        kanal::Sender<(IVec3, BlockState)>,
+        kanal::Receiver<(IVec3, BlockState)>,
     ),
     //pub queue: Vec<(IVec3, VoxelBlock)>,
+    pub bulk: (kanal::Sender<BulkEdit>, kanal::Receiver<BulkEdit>),
+    pub history: (kanal::Sender<HistoryStep>, kanal::Receiver<HistoryStep>),
 }
 
 impl VoxelModifier {
-    pub fn set(&self, position: IVec3, block: BlockId) {
+    pub fn set(&self, position: IVec3, block: BlockState) {
         self.queue.0.send((position, block));
     }
+
+    /// Applied after the single edits of the same frame. Dropped with a warning if
+    /// `BulkEdit::check` fails.
+    pub fn apply(&self, edit: BulkEdit) {
+        self.bulk.0.send(edit).unwrap();
+    }
+
+    /// Reverts the last recorded group, after the edits of the same frame.
+    pub fn undo(&self) {
+        self.history.0.send(HistoryStep::Undo).unwrap();
+    }
+
+    pub fn redo(&self) {
+        self.history.0.send(HistoryStep::Redo).unwrap();
+    }
 }
 
 impl Default for VoxelModifier {
     fn default() -> Self {
         Self {
             queue: kanal::unbounded(),
+            bulk: kanal::unbounded(),
+            history: kanal::unbounded(),
         }
     }
 }
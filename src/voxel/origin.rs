@@ -0,0 +1,117 @@
+//! Rendering happens relative to a chunk near the camera instead of the world origin, so
+//! `f32` transforms stay small and exact however far the player travels. Block and chunk
+//! positions stay absolute `i32`s, only `Transform`s are relative to the origin.
+
+use bevy::math::{IVec3, Vec3};
+use bevy::prelude::{Query, Res, ResMut, Resource, Transform, With, Without};
+
+use super::chunk::{Chunk, CHUNK_SIZE};
+use super::config::VoxelConfig;
+use super::VoxelWorldCamera;
+
+/// The chunk whose lowest corner is at `Vec3::ZERO` in render space.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FloatingOrigin {
+    pub chunk: IVec3,
+}
+
+impl FloatingOrigin {
+    /// World position of the render space origin.
+    #[inline]
+    pub fn offset(&self) -> IVec3 {
+        self.chunk * CHUNK_SIZE as i32
+    }
+
+    /// The block containing the render space point `pos`.
+    #[inline]
+    pub fn world_block(&self, pos: Vec3) -> IVec3 {
+        self.offset() + pos.floor().as_ivec3()
+    }
+
+    /// The chunk containing the render space point `pos`.
+    #[inline]
+    pub fn world_chunk(&self, pos: Vec3) -> IVec3 {
+        self.world_block(pos)
+            .div_euclid(IVec3::splat(CHUNK_SIZE as i32))
+    }
+
+    /// Render space position of the lowest corner of block `pos`.
+    #[inline]
+    pub fn render_position(&self, pos: IVec3) -> Vec3 {
+        (pos - self.offset()).as_vec3()
+    }
+
+    /// Translation of a chunk entity, whose mesh starts at its padding.
+    #[inline]
+    pub fn chunk_translation(&self, chunk_pos: IVec3) -> Vec3 {
+        self.render_position(chunk_pos * CHUNK_SIZE as i32) - 1.0
+    }
+}
+
+/// Move the origin to the camera's chunk once the camera is `origin_rebase_distance` chunks
+/// away, shifting the camera and every chunk under `WorldRoot` by the same whole chunks.
+/// Runs before transform propagation, so nothing renders at the old origin.
+pub fn rebase_origin(
+    mut origin: ResMut<FloatingOrigin>,
+    config: Res<VoxelConfig>,
+    mut camera: Query<&mut Transform, With<VoxelWorldCamera>>,
+    mut chunks: Query<(&Chunk, &mut Transform), Without<VoxelWorldCamera>>,
+) {
+    let Ok(mut camera) = camera.get_single_mut() else {
+        return;
+    };
+    let camera_chunk = origin.world_chunk(camera.translation);
+    let distance = (camera_chunk - origin.chunk).abs().max_element();
+    if distance < config.origin_rebase_distance as i32 {
+        return;
+    }
+
+    let shift = ((camera_chunk - origin.chunk) * CHUNK_SIZE as i32).as_vec3();
+    origin.chunk = camera_chunk;
+    camera.translation -= shift;
+    for (chunk, mut transform) in &mut chunks {
+        transform.translation = origin.chunk_translation(chunk.position);
+    }
+    tracing::debug!("floating origin moved to chunk {}", origin.chunk);
+}
+
+#[test]
+fn test_floating_origin() {
+    let origin = FloatingOrigin {
+        chunk: IVec3::new(-2, 0, 100_000),
+    };
+    let far = IVec3::new(-64, 5, 100_000 * CHUNK_SIZE as i32);
+    assert_eq!(origin.render_position(far), Vec3::new(0.0, 5.0, 0.0));
+    assert_eq!(
+        origin.world_block(Vec3::new(0.5, 5.9, -0.1)),
+        far - IVec3::Z
+    );
+    assert_eq!(
+        origin.world_chunk(Vec3::new(-0.5, 0.0, 0.0)),
+        IVec3::new(-3, 0, 100_000)
+    );
+    assert_eq!(
+        origin.chunk_translation(IVec3::new(-1, 1, 100_000)),
+        Vec3::new(31.0, 31.0, -1.0)
+    );
+}
+
+#[test]
+fn test_chunk_math_far_and_negative() {
+    use bevy::math::UVec3;
+
+    use super::chunk::get_chunk_voxel_position;
+
+    assert_eq!(
+        get_chunk_voxel_position(IVec3::new(-1, -32, -33)),
+        (IVec3::new(-1, -1, -2), UVec3::new(32, 1, 32))
+    );
+    // past 2^24, where f32 can no longer tell neighboring blocks apart
+    let far = IVec3::new((1 << 24) + 1, 0, -(1 << 30) - 1);
+    let (chunk, voxel) = get_chunk_voxel_position(far);
+    assert_eq!(
+        chunk * CHUNK_SIZE as i32 + voxel.as_ivec3() - IVec3::ONE,
+        far
+    );
+    assert_eq!(voxel, UVec3::new(2, 1, 32));
+}
@@ -0,0 +1,154 @@
+//! Every chunk keeps a copy of the blocks around it in a one-voxel border, so meshing can
+//! cull faces against its neighbors without looking them up. A block on a face, edge or
+//! corner of a chunk is copied into up to 7 neighbors, which all have to follow its edits.
+
+use bevy::math::{IVec3, UVec3};
+use bevy::prelude::Entity;
+
+use super::chunk::{get_chunk_voxel_position, CHUNK_SIZE};
+use super::utils::ivec3_range;
+use super::voxel_block::{BlockState, AIR};
+use super::world::LoadedChunks;
+
+/// Chunks other than its own that hold a copy of block `pos`, with its position in their padding.
+pub fn padding_neighbors(pos: IVec3) -> impl Iterator<Item = (IVec3, UVec3)> {
+    let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+    // -1 on the low face, 1 on the high face, 0 inside
+    let side = |v: u32| match v {
+        1 => -1,
+        CHUNK_SIZE => 1,
+        _ => 0,
+    };
+    let max = IVec3::new(side(voxel_pos.x), side(voxel_pos.y), side(voxel_pos.z));
+    ivec3_range(max.min(IVec3::ZERO), max.max(IVec3::ZERO))
+        .filter(|offset| *offset != IVec3::ZERO)
+        .map(move |offset| {
+            let padded = voxel_pos.as_ivec3() - offset * CHUNK_SIZE as i32;
+            (chunk_pos + offset, padded.as_uvec3())
+        })
+}
+
+/// Copy the blocks of loaded chunk `pos` into the padding of its loaded neighbors and theirs
+/// into its padding, for a chunk that was just loaded next to chunks edited since it was saved.
+/// Returns the chunks that changed with their entities, their hashes are already updated.
+pub fn sync_padding(chunks: &LoadedChunks, pos: IVec3) -> Vec<(IVec3, Entity)> {
+    let mut changed = Vec::new();
+    for offset in ivec3_range(IVec3::NEG_ONE, IVec3::ONE).filter(|offset| *offset != IVec3::ZERO) {
+        let neighbor = pos + offset;
+        if !chunks.contains(&neighbor) {
+            continue;
+        }
+        for (from, to) in [(neighbor, pos), (pos, neighbor)] {
+            if let Some(entity) = copy_padding(chunks, from, to) {
+                if !changed.contains(&(to, entity)) {
+                    changed.push((to, entity));
+                }
+            }
+        }
+    }
+    for (pos, _) in &changed {
+        chunks.update(pos, |_, chunk| chunk.generate_hash());
+    }
+    changed
+}
+
+/// Copy the blocks `from` owns next to `to` into the padding of `to`.
+/// Returns the entity of `to` if any of them differed.
+fn copy_padding(chunks: &LoadedChunks, from: IVec3, to: IVec3) -> Option<Entity> {
+    let offset = to - from;
+    // in `from`'s padded coordinates, the slab of its interior that touches `to`
+    let side = |offset: i32| match offset {
+        -1 => (1, 1),
+        1 => (CHUNK_SIZE, CHUNK_SIZE),
+        _ => (1, CHUNK_SIZE),
+    };
+    let (x, y, z) = (side(offset.x), side(offset.y), side(offset.z));
+    let min = UVec3::new(x.0, y.0, z.0).as_ivec3();
+    let max = UVec3::new(x.1, y.1, z.1).as_ivec3();
+    let blocks = chunks.read(&from, |_, chunk| {
+        ivec3_range(min, max)
+            .map(|pos| (pos, chunk.block_at(pos.as_uvec3()).cloned()))
+            .collect::<Vec<_>>()
+    })?;
+
+    let air = BlockState::from(AIR.clone());
+    chunks
+        .update(&to, |_, chunk| {
+            let mut changed = false;
+            for (pos, state) in blocks {
+                let padded = (pos - offset * CHUNK_SIZE as i32).as_uvec3();
+                if chunk.block_at(padded) == state.as_ref() {
+                    continue;
+                }
+                if let Err(err) = chunk.set_block(padded, state.as_ref().unwrap_or(&air)) {
+                    tracing::error!("sync padding of chunk {} failed: {}", to, err);
+                    continue;
+                }
+                changed = true;
+            }
+            changed.then_some(chunk.entity)
+        })
+        .flatten()
+}
+
+#[test]
+fn test_padding_neighbors() {
+    let chunk = CHUNK_SIZE as i32;
+    assert_eq!(padding_neighbors(IVec3::new(5, 6, 7)).count(), 0);
+    // on the low x face of chunk 0: its copy is at x = 33 in chunk -1
+    assert_eq!(
+        padding_neighbors(IVec3::new(0, 6, 7)).collect::<Vec<_>>(),
+        [(IVec3::new(-1, 0, 0), UVec3::new(33, 7, 8))]
+    );
+    // a corner is in 7 neighbors
+    let corner = padding_neighbors(IVec3::splat(chunk - 1)).collect::<Vec<_>>();
+    assert_eq!(corner.len(), 7);
+    assert!(corner.contains(&(IVec3::ONE, UVec3::ZERO)));
+    assert!(corner.contains(&(
+        IVec3::new(1, 0, 0),
+        UVec3::new(0, chunk as u32, chunk as u32)
+    )));
+}
+
+#[test]
+fn test_sync_padding() {
+    use super::chunk::ChunkData;
+
+    let chunks = LoadedChunks::default();
+    let stone = BlockState::parse("core::stone").unwrap();
+    let mut edited = ChunkData::new(IVec3::ZERO, Entity::PLACEHOLDER);
+    // a face, an edge and a corner block
+    edited.set_block(UVec3::new(32, 5, 5), &stone).unwrap();
+    edited.set_block(UVec3::new(32, 32, 5), &stone).unwrap();
+    edited.set_block(UVec3::new(32, 32, 32), &stone).unwrap();
+    chunks.insert(IVec3::ZERO, edited).ok();
+
+    let loaded = IVec3::new(1, 1, 1);
+    chunks
+        .insert(loaded, ChunkData::new(loaded, Entity::from_raw(7)))
+        .ok();
+    let changed = sync_padding(&chunks, loaded);
+    assert_eq!(changed, [(loaded, Entity::from_raw(7))]);
+    chunks
+        .read(&loaded, |_, chunk| {
+            assert_eq!(chunk.block_at(UVec3::ZERO), Some(&stone));
+            assert_eq!(chunk.block_at(UVec3::new(0, 0, 5)), None);
+        })
+        .unwrap();
+
+    // the face and edge blocks are not next to (1, 1, 1), but are next to (1, 0, 0)
+    let face = IVec3::new(1, 0, 0);
+    chunks
+        .insert(face, ChunkData::new(face, Entity::from_raw(8)))
+        .ok();
+    assert_eq!(sync_padding(&chunks, face), [(face, Entity::from_raw(8))]);
+    chunks
+        .read(&face, |_, chunk| {
+            assert_eq!(chunk.block_at(UVec3::new(0, 5, 5)), Some(&stone));
+            assert_eq!(chunk.block_at(UVec3::new(0, 32, 5)), Some(&stone));
+            assert_eq!(chunk.block_at(UVec3::new(0, 32, 32)), Some(&stone));
+            assert_eq!(chunk.block_at(UVec3::new(0, 6, 5)), None);
+        })
+        .unwrap();
+    assert!(sync_padding(&chunks, face).is_empty());
+}
@@ -1,32 +1,90 @@
 use indexmap::IndexSet;
+use thiserror::Error;
 
-use super::voxel_block::{BlockId, VoxelBlock, AIR};
+use super::voxel_block::{BlockId, BlockState, VoxelBlock, AIR};
 
+/// `VoxelBlock::Solid` indices are `u16`.
+const MAX_LEN: usize = u16::MAX as usize + 1;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PaletteError {
+    #[error("palette already holds {MAX_LEN} entries, can not add {0}")]
+    Overflow(BlockState),
+}
+
+/// Block states of a chunk, `VoxelBlock::Solid` holds an index into it.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Palette {
-    map: IndexSet<BlockId, ahash::RandomState>,
+    map: IndexSet<BlockState, ahash::RandomState>,
 }
 
 impl Palette {
-    fn mapped_idx(&mut self, name: &BlockId) -> u16 {
-        if let Some(id) = self.map.get_index_of(name) {
-            id as u16
+    fn mapped_idx(&mut self, state: &BlockState) -> Result<u16, PaletteError> {
+        if let Some(id) = self.map.get_index_of(state) {
+            return Ok(id as u16);
+        }
+        if self.map.len() >= MAX_LEN {
+            return Err(PaletteError::Overflow(state.clone()));
+        }
+        let (id, _) = self.map.insert_full(state.clone());
+        Ok(id as u16)
+    }
+
+    /// Entries including air.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Never true for a default palette, which starts with air.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Like `voxel_block`, but `None` instead of adding `state`.
+    pub fn get(&self, state: &BlockState) -> Option<VoxelBlock> {
+        if state.is_air() {
+            Some(VoxelBlock::Air)
         } else {
-            let (id, _) = self.map.insert_full(name.clone());
-            id as u16
+            self.map
+                .get_index_of(state)
+                .map(|idx| VoxelBlock::Solid(idx as u16))
         }
     }
 
-    pub fn block_id(&self, idx: u16) -> Option<&BlockId> {
+    pub fn block_state(&self, idx: u16) -> Option<&BlockState> {
         self.map.get_index(idx as usize)
     }
 
-    pub fn voxel_block(&mut self, id: &BlockId) -> VoxelBlock {
-        if id == &*AIR {
-            VoxelBlock::Air
+    pub fn block_id(&self, idx: u16) -> Option<&BlockId> {
+        self.block_state(idx).map(BlockState::id)
+    }
+
+    /// Every state of air is `VoxelBlock::Air`.
+    pub fn voxel_block(&mut self, state: &BlockState) -> Result<VoxelBlock, PaletteError> {
+        if state.is_air() {
+            Ok(VoxelBlock::Air)
         } else {
-            VoxelBlock::Solid(self.mapped_idx(id))
+            self.mapped_idx(state).map(VoxelBlock::Solid)
+        }
+    }
+
+    /// Drop every entry whose index is not `used`. Air stays at index 0.
+    /// Returns old index -> new index, `None` if nothing was dropped.
+    pub fn compact(&mut self, used: &[bool]) -> Option<Vec<u16>> {
+        let keep = |idx: usize| idx == 0 || used.get(idx).copied().unwrap_or(false);
+        if (0..self.map.len()).all(keep) {
+            return None;
         }
+        let mut remap = vec![0; self.map.len()];
+        let mut map = IndexSet::with_hasher(ahash::RandomState::new());
+        for (idx, id) in self.map.iter().enumerate() {
+            if keep(idx) {
+                remap[idx] = map.len() as u16;
+                map.insert(id.clone());
+            }
+        }
+        self.map = map;
+        Some(remap)
     }
 }
 
@@ -35,7 +93,7 @@ impl Default for Palette {
         let mut p = Palette {
             map: Default::default(),
         };
-        p.mapped_idx(&AIR);
+        p.map.insert(BlockState::from(AIR.clone()));
         p
     }
 }
@@ -48,7 +106,7 @@ impl<'a> bincode::Encode for &'a Palette {
         self.map.len().encode(encoder)?;
         self.map
             .iter()
-            .try_for_each(|atom| (&**atom).encode(encoder))
+            .try_for_each(|state| state.encode(encoder))
     }
 }
 
@@ -57,14 +115,44 @@ impl<'a> bincode::BorrowDecode<'a> for Palette {
         decoder: &mut D,
     ) -> Result<Self, bincode::error::DecodeError> {
         let len = usize::borrow_decode(decoder)?;
+        if len > MAX_LEN {
+            return Err(bincode::error::DecodeError::OtherString(format!(
+                "palette has {len} entries, at most {MAX_LEN} fit in a chunk"
+            )));
+        }
         let mut map = IndexSet::with_capacity_and_hasher(len, ahash::RandomState::new());
         for _ in 0..len {
-            let atom = <&str>::borrow_decode(decoder)?;
-            assert!(
-                map.insert(BlockId::new(atom)),
-                "Duplicate block ID in palette"
-            );
+            let state = BlockState::borrow_decode(decoder)?;
+            if map.contains(&state) {
+                return Err(bincode::error::DecodeError::OtherString(format!(
+                    "duplicate block state {state} in palette"
+                )));
+            }
+            map.insert(state);
         }
         Ok(Palette { map })
     }
 }
+
+#[test]
+fn test_overflow() {
+    let mut palette = Palette::default();
+    for i in 1..MAX_LEN {
+        let state = BlockState::from(BlockId::new(format!("test::{i}")));
+        let voxel = palette.voxel_block(&state).unwrap();
+        assert_eq!(voxel, VoxelBlock::Solid(i as u16));
+    }
+    assert_eq!(palette.len(), MAX_LEN);
+
+    // existing entries still resolve, new ones are rejected
+    assert_eq!(
+        palette.voxel_block(&BlockState::from(BlockId::new("test::1"))),
+        Ok(VoxelBlock::Solid(1))
+    );
+    let full = BlockState::from(BlockId::new("test::full"));
+    assert_eq!(
+        palette.voxel_block(&full),
+        Err(PaletteError::Overflow(full.clone()))
+    );
+    assert_eq!(palette.get(&full), None);
+}
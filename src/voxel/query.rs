@@ -0,0 +1,161 @@
+//! Block lookups by world position, across chunk boundaries and without the padding arithmetic.
+//! An unloaded chunk is reported as `QueryError::NotLoaded` rather than read as air.
+
+use std::ops::RangeInclusive;
+
+use bevy::math::{IVec2, IVec3, UVec3};
+use thiserror::Error;
+
+use super::chunk::{get_chunk_voxel_position, CHUNK_SIZE};
+use super::utils::ivec3_range;
+use super::voxel_block::BlockState;
+use super::world::LoadedChunks;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum QueryError {
+    #[error("chunk {0} is not loaded")]
+    NotLoaded(IVec3),
+}
+
+/// `None` == Air
+pub fn get_block(chunks: &LoadedChunks, pos: IVec3) -> Result<Option<BlockState>, QueryError> {
+    let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+    chunks
+        .read(&chunk_pos, |_, chunk| chunk.block_at(voxel_pos).cloned())
+        .ok_or(QueryError::NotLoaded(chunk_pos))
+}
+
+/// Anything but air.
+pub fn is_solid(chunks: &LoadedChunks, pos: IVec3) -> Result<bool, QueryError> {
+    let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+    chunks
+        .read(&chunk_pos, |_, chunk| chunk.block_at(voxel_pos).is_some())
+        .ok_or(QueryError::NotLoaded(chunk_pos))
+}
+
+/// Visit every block in the box from `min` to `max`, both inclusive, one chunk at a time.
+/// Nothing is visited if a chunk the box touches is not loaded.
+/// `f` runs while its chunk is locked, it must not modify the world.
+pub fn for_each_block(
+    chunks: &LoadedChunks,
+    min: IVec3,
+    max: IVec3,
+    mut f: impl FnMut(IVec3, Option<&BlockState>),
+) -> Result<(), QueryError> {
+    let (min, max) = (min.min(max), min.max(max));
+    let (min_chunk, _) = get_chunk_voxel_position(min);
+    let (max_chunk, _) = get_chunk_voxel_position(max);
+    if let Some(missing) = ivec3_range(min_chunk, max_chunk).find(|pos| !chunks.contains(pos)) {
+        return Err(QueryError::NotLoaded(missing));
+    }
+
+    for chunk_pos in ivec3_range(min_chunk, max_chunk) {
+        let origin = chunk_pos * CHUNK_SIZE as i32;
+        let lo = min.max(origin);
+        let hi = max.min(origin + IVec3::splat(CHUNK_SIZE as i32 - 1));
+        chunks
+            .read(&chunk_pos, |_, chunk| {
+                for pos in ivec3_range(lo, hi) {
+                    f(pos, chunk.block_at((pos - origin).as_uvec3() + 1));
+                }
+            })
+            .ok_or(QueryError::NotLoaded(chunk_pos))?;
+    }
+    Ok(())
+}
+
+/// Y of the highest non-air block of the column at `column` (x, z) within `y`,
+/// `None` if it is all air. Scans down from the top, so only fails on an unloaded
+/// chunk it has to look into.
+pub fn highest_solid(
+    chunks: &LoadedChunks,
+    column: IVec2,
+    y: RangeInclusive<i32>,
+) -> Result<Option<i32>, QueryError> {
+    let (bottom, mut top) = (*y.start(), *y.end());
+    while top >= bottom {
+        let (chunk_pos, voxel_pos) = get_chunk_voxel_position(IVec3::new(column.x, top, column.y));
+        let origin_y = chunk_pos.y * CHUNK_SIZE as i32;
+        let found = chunks
+            .read(&chunk_pos, |_, chunk| {
+                (bottom.max(origin_y)..=top).rev().find(|y| {
+                    let voxel_y = (y - origin_y) as u32 + 1;
+                    chunk
+                        .block_at(UVec3::new(voxel_pos.x, voxel_y, voxel_pos.z))
+                        .is_some()
+                })
+            })
+            .ok_or(QueryError::NotLoaded(chunk_pos))?;
+        if found.is_some() {
+            return Ok(found);
+        }
+        top = origin_y - 1;
+    }
+    Ok(None)
+}
+
+#[test]
+fn test_queries_across_chunks() {
+    use bevy::prelude::Entity;
+
+    use super::chunk::ChunkData;
+
+    let chunks = LoadedChunks::default();
+    let stone = BlockState::parse("core::stone").unwrap();
+    for chunk_pos in [IVec3::ZERO, IVec3::new(-1, 0, 0), IVec3::new(0, 1, 0)] {
+        chunks
+            .insert(chunk_pos, ChunkData::new(chunk_pos, Entity::PLACEHOLDER))
+            .ok();
+    }
+    let set = |pos: IVec3| {
+        let (chunk_pos, voxel_pos) = get_chunk_voxel_position(pos);
+        chunks
+            .update(&chunk_pos, |_, chunk| {
+                chunk.set_block(voxel_pos, &stone).unwrap()
+            })
+            .unwrap();
+    };
+    set(IVec3::new(-1, 3, 0));
+    set(IVec3::new(0, 3, 0));
+    set(IVec3::new(0, 40, 0));
+
+    assert_eq!(
+        get_block(&chunks, IVec3::new(-1, 3, 0)),
+        Ok(Some(stone.clone()))
+    );
+    assert_eq!(get_block(&chunks, IVec3::new(1, 3, 0)), Ok(None));
+    assert_eq!(is_solid(&chunks, IVec3::new(0, 40, 0)), Ok(true));
+    assert_eq!(
+        is_solid(&chunks, IVec3::new(0, -1, 0)),
+        Err(QueryError::NotLoaded(IVec3::new(0, -1, 0)))
+    );
+
+    let mut solid = Vec::new();
+    let mut visited = 0;
+    for_each_block(
+        &chunks,
+        IVec3::new(-2, 0, 0),
+        IVec3::new(1, 5, 0),
+        |pos, block| {
+            visited += 1;
+            if block.is_some() {
+                solid.push(pos);
+            }
+        },
+    )
+    .unwrap();
+    assert_eq!(visited, 4 * 6);
+    assert_eq!(solid, [IVec3::new(-1, 3, 0), IVec3::new(0, 3, 0)]);
+    assert_eq!(
+        for_each_block(&chunks, IVec3::ZERO, IVec3::new(0, 0, 40), |_, _| {}),
+        Err(QueryError::NotLoaded(IVec3::new(0, 0, 1)))
+    );
+
+    assert_eq!(highest_solid(&chunks, IVec2::ZERO, 0..=63), Ok(Some(40)));
+    assert_eq!(highest_solid(&chunks, IVec2::ZERO, 0..=39), Ok(Some(3)));
+    assert_eq!(highest_solid(&chunks, IVec2::new(5, 5), 0..=63), Ok(None));
+    assert_eq!(
+        highest_solid(&chunks, IVec2::ZERO, 0..=64),
+        Err(QueryError::NotLoaded(IVec3::new(0, 2, 0)))
+    );
+}
@@ -0,0 +1,258 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bevy::math::IVec3;
+use bevy::prelude::Resource;
+
+use super::chunk::{ChunkError, ChunkErrorKind};
+use super::config::VoxelConfig;
+use super::storage::WorldStorage;
+use super::voxel_block::BlockState;
+use super::world::{LoadedChunks, VoxelWorld};
+
+/// An encoded chunk waiting to be written.
+pub struct SaveRequest {
+    pub pos: IVec3,
+    pub bytes: Vec<u8>,
+    /// `ChunkData::applied_edits`, dropped from the pending edits in the same commit
+    pub applied_edits: Vec<(IVec3, BlockState)>,
+}
+
+enum SaverMessage {
+    Save(SaveRequest),
+    /// edits to unloaded chunks as (chunk pos, block pos, block id), written after the
+    /// chunks queued before them
+    PendingEdits(Vec<(IVec3, IVec3, BlockState)>),
+    /// commit everything queued before this message, then ack
+    Flush(kanal::Sender<()>),
+    /// commit everything queued before this message, then run on the saver thread
+    Run(Box<dyn FnOnce(&WorldStorage) + Send>),
+}
+
+#[derive(Debug, Default)]
+pub struct SaverStats {
+    pub batches: AtomicU64,
+    pub chunks_written: AtomicU64,
+    pub chunks_failed: AtomicU64,
+    pub last_batch_len: AtomicU64,
+    pub last_commit_micros: AtomicU64,
+}
+
+/// Write-behind chunk saver.
+/// Encoded chunks are queued and committed by a dedicated thread in batched
+/// transactions, bounded by chunk count, byte size and delay.
+/// The queue is bounded, `is_full` tells callers to back off.
+#[derive(Resource, Clone)]
+pub struct ChunkSaver {
+    queue: kanal::Sender<SaverMessage>,
+    stats: Arc<SaverStats>,
+}
+
+struct SaverWorker {
+    queue: kanal::Receiver<SaverMessage>,
+    storage: WorldStorage,
+    /// chunks of a failed batch that are still loaded are marked dirty again
+    loaded_chunks: Arc<LoadedChunks>,
+    saving_chunks: Arc<scc::HashSet<IVec3, ahash::RandomState>>,
+    errors: kanal::Sender<ChunkError>,
+    stats: Arc<SaverStats>,
+    max_chunks: usize,
+    max_bytes: usize,
+    max_delay: Duration,
+}
+
+impl ChunkSaver {
+    pub fn spawn(
+        storage: WorldStorage,
+        world: &VoxelWorld,
+        errors: kanal::Sender<ChunkError>,
+        config: &VoxelConfig,
+    ) -> Self {
+        let (tx, rx) = kanal::bounded(config.save_queue_capacity as usize);
+        let stats = Arc::new(SaverStats::default());
+        let worker = SaverWorker {
+            queue: rx,
+            storage,
+            loaded_chunks: world.loaded_chunks.clone(),
+            saving_chunks: world.saving_chunks.clone(),
+            errors,
+            stats: stats.clone(),
+            max_chunks: config.save_batch_size as usize,
+            max_bytes: config.save_batch_bytes as usize,
+            max_delay: config.save_batch_delay,
+        };
+        std::thread::Builder::new()
+            .name("chunk saver".to_owned())
+            .spawn(move || worker.run())
+            .expect("spawn chunk saver thread failed");
+
+        ChunkSaver { queue: tx, stats }
+    }
+
+    /// Queue a chunk, blocking while the queue is full.
+    pub fn save(&self, request: SaveRequest) {
+        self.queue.send(SaverMessage::Save(request)).ok();
+    }
+
+    /// Queue a chunk, waiting while the queue is full.
+    pub async fn save_async(&self, request: SaveRequest) {
+        self.queue
+            .as_async()
+            .send(SaverMessage::Save(request))
+            .await
+            .ok();
+    }
+
+    /// Queue edits to unloaded chunks, blocking while the queue is full.
+    pub fn save_pending_edits(&self, edits: Vec<(IVec3, IVec3, BlockState)>) {
+        self.queue.send(SaverMessage::PendingEdits(edits)).ok();
+    }
+
+    /// Block until everything queued so far is committed.
+    pub fn flush(&self) {
+        let (tx, rx) = kanal::bounded(1);
+        if self.queue.send(SaverMessage::Flush(tx)).is_ok() {
+            rx.recv().ok();
+        }
+    }
+
+    /// Run `f` on the saver thread after everything queued so far is committed.
+    /// No chunk is written while `f` runs, so it sees a consistent storage.
+    pub fn run(&self, f: impl FnOnce(&WorldStorage) + Send + 'static) {
+        self.queue.send(SaverMessage::Run(Box::new(f))).ok();
+    }
+
+    /// Number of chunks waiting to be committed.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.queue.is_full()
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &SaverStats {
+        &self.stats
+    }
+}
+
+impl SaverWorker {
+    fn run(self) {
+        let mut batch = Vec::with_capacity(self.max_chunks);
+        let mut closed = false;
+
+        while !closed {
+            // wait for the first chunk of the next batch
+            let first = match self.queue.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+            // pending edits, a flush or a run end the batch
+            let mut barrier = None;
+            let mut batch_bytes = 0;
+            match first {
+                SaverMessage::Save(request) => {
+                    batch_bytes += request.bytes.len();
+                    batch.push(request);
+                }
+                message => barrier = Some(message),
+            }
+
+            let deadline = Instant::now() + self.max_delay;
+            while barrier.is_none()
+                && batch.len() < self.max_chunks
+                && batch_bytes < self.max_bytes
+            {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match self.queue.recv_timeout(timeout) {
+                    Ok(SaverMessage::Save(request)) => {
+                        batch_bytes += request.bytes.len();
+                        batch.push(request);
+                    }
+                    Ok(message) => barrier = Some(message),
+                    Err(kanal::ReceiveErrorTimeout::Timeout) => break,
+                    Err(_) => {
+                        closed = true;
+                        break;
+                    }
+                }
+            }
+
+            self.commit(&mut batch);
+            match barrier {
+                Some(SaverMessage::Flush(ack)) => {
+                    ack.send(()).ok();
+                }
+                Some(SaverMessage::Run(f)) => f(&self.storage),
+                Some(SaverMessage::PendingEdits(edits)) => {
+                    if let Err(err) = self.storage.save_pending_edits(&edits) {
+                        tracing::error!("save {} pending edits failed: {:?}", edits.len(), err);
+                    }
+                }
+                Some(SaverMessage::Save(_)) | None => {}
+            }
+        }
+    }
+
+    fn commit(&self, batch: &mut Vec<SaveRequest>) {
+        if batch.is_empty() {
+            return;
+        }
+        let _span = tracing::info_span!("profiling::{save chunk batch}").entered();
+        let start = Instant::now();
+
+        let chunks = batch
+            .iter()
+            .map(|request| (request.pos, &*request.bytes))
+            .collect::<Vec<_>>();
+        let applied_edits = batch
+            .iter()
+            .flat_map(|request| {
+                request
+                    .applied_edits
+                    .iter()
+                    .map(move |(block_pos, state)| (request.pos, *block_pos, state.clone()))
+            })
+            .collect::<Vec<_>>();
+        let result = self.storage.save_applied(&chunks, &applied_edits);
+
+        let len = batch.len() as u64;
+        match result {
+            Ok(()) => {
+                self.stats.chunks_written.fetch_add(len, Ordering::Relaxed);
+            }
+            Err(err) => {
+                tracing::error!("save {} chunks failed: {:?}", len, err);
+                self.stats.chunks_failed.fetch_add(len, Ordering::Relaxed);
+                for request in batch.iter_mut() {
+                    // saved again by the next autosave, unless it is being unloaded
+                    let applied_edits = std::mem::take(&mut request.applied_edits);
+                    self.loaded_chunks.update(&request.pos, |_, chunk| {
+                        chunk.dirty = true;
+                        chunk.applied_edits.extend(applied_edits);
+                    });
+                    self.errors
+                        .send(ChunkError {
+                            pos: request.pos,
+                            kind: ChunkErrorKind::Save,
+                            error: format!("{err:#}"),
+                        })
+                        .ok();
+                }
+            }
+        }
+        self.stats.batches.fetch_add(1, Ordering::Relaxed);
+        self.stats.last_batch_len.store(len, Ordering::Relaxed);
+        self.stats
+            .last_commit_micros
+            .store(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+
+        for request in batch.drain(..) {
+            self.saving_chunks.remove(&request.pos);
+        }
+    }
+}
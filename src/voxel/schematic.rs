@@ -0,0 +1,389 @@
+//! Import and export Sponge Schematic v3 files (`.schem`), the format WorldEdit and most
+//! Minecraft building tools share structures in.
+//!
+//! Only blocks are read and written, block entities, entities and biomes are skipped.
+//! Block names go through a [`BlockMapping`], like Anvil worlds. On import, a property is
+//! kept if the block declares it in the [`Registry`] and allows its value, others are dropped.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use bevy::math::UVec3;
+use fastnbt::{ByteArray, IntArray};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indexmap::IndexSet;
+use serde::{Deserialize, Serialize};
+
+use crate::core::registry::Registry;
+
+use super::anvil::{BlockMapping, EXPORT_DATA_VERSION};
+use super::edit::Clipboard;
+use super::palette::Palette;
+use super::voxel_block::{BlockId, BlockState, VoxelBlock, AIR};
+
+const SPONGE_VERSION: i32 = 3;
+const AIR_NAME: &str = "minecraft:air";
+
+/// The root compound holds a single `Schematic` compound since v3.
+#[derive(Debug, Serialize, Deserialize)]
+struct Root {
+    #[serde(rename = "Schematic")]
+    schematic: Schematic,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Schematic {
+    version: i32,
+    data_version: i32,
+    /// unsigned shorts
+    width: i16,
+    height: i16,
+    length: i16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offset: Option<IntArray>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blocks: Option<BlockContainer>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BlockContainer {
+    /// block state -> index in `data`
+    palette: HashMap<String, i32>,
+    /// varint palette indices, x fastest then z then y
+    data: ByteArray,
+}
+
+#[derive(Debug, Default)]
+pub struct SchematicStats {
+    /// Minecraft or minecrust block name -> blocks read or written as air for lack of a mapping
+    pub unmapped: BTreeMap<String, u64>,
+    /// minecrust block id -> blocks imported as air because the registry does not know them
+    pub unregistered: BTreeMap<String, u64>,
+}
+
+pub fn load(
+    path: impl AsRef<Path>,
+    mapping: &BlockMapping,
+    registry: &Registry,
+) -> anyhow::Result<(Clipboard, SchematicStats)> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).with_context(|| format!("read schematic {}", path.display()))?;
+    read(&bytes, mapping, registry).with_context(|| format!("parse schematic {}", path.display()))
+}
+
+pub fn save(
+    path: impl AsRef<Path>,
+    clipboard: &Clipboard,
+    mapping: &BlockMapping,
+) -> anyhow::Result<SchematicStats> {
+    let path = path.as_ref();
+    let (bytes, stats) = write(clipboard, mapping)?;
+    fs::write(path, bytes).with_context(|| format!("write schematic {}", path.display()))?;
+    Ok(stats)
+}
+
+/// Gzipped NBT, as `.schem` files are stored.
+pub fn read(
+    bytes: &[u8],
+    mapping: &BlockMapping,
+    registry: &Registry,
+) -> anyhow::Result<(Clipboard, SchematicStats)> {
+    let mut nbt = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut nbt)?;
+    let Root { schematic } = fastnbt::from_bytes(&nbt)?;
+    anyhow::ensure!(
+        schematic.version == SPONGE_VERSION,
+        "sponge schematic version {} is not supported, only {}",
+        schematic.version,
+        SPONGE_VERSION
+    );
+    let size = UVec3::new(
+        schematic.width as u16 as u32,
+        schematic.height as u16 as u32,
+        schematic.length as u16 as u32,
+    );
+    let mut stats = SchematicStats::default();
+    let Some(blocks) = schematic.blocks else {
+        return Ok((Clipboard::from_fn(size, |_| None), stats));
+    };
+
+    let mut palette = vec![None; blocks.palette.len()];
+    for (name, idx) in &blocks.palette {
+        let slot = usize::try_from(*idx)
+            .ok()
+            .and_then(|idx| palette.get_mut(idx))
+            .with_context(|| format!("palette index {idx} of `{name}` is out of range"))?;
+        *slot = Some(name.as_str());
+    }
+    let palette = palette
+        .into_iter()
+        .enumerate()
+        .map(|(idx, name)| name.with_context(|| format!("palette has no entry {idx}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let indices = read_varints(&blocks.data)?;
+    let volume = size.x as usize * size.y as usize * size.z as usize;
+    anyhow::ensure!(
+        indices.len() == volume,
+        "{} blocks, expected {}",
+        indices.len(),
+        volume
+    );
+    let mut counts = vec![0u64; palette.len()];
+    for idx in &indices {
+        *counts
+            .get_mut(*idx as usize)
+            .with_context(|| format!("block palette index {idx} is out of range"))? += 1;
+    }
+
+    let states = palette
+        .iter()
+        .zip(&counts)
+        .map(|(name, count)| import_state(name, *count, mapping, registry, &mut stats))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let clipboard = Clipboard::from_fn(size, |offset| {
+        states[indices[sponge_index(size, offset)] as usize].clone()
+    });
+    Ok((clipboard, stats))
+}
+
+/// `None` == Air
+fn import_state(
+    name: &str,
+    count: u64,
+    mapping: &BlockMapping,
+    registry: &Registry,
+    stats: &mut SchematicStats,
+) -> anyhow::Result<Option<BlockState>> {
+    let state = BlockState::parse(name)?;
+    let Some(id) = mapping.to_minecrust(state.id().as_str()) else {
+        if count != 0 {
+            *stats.unmapped.entry(state.id().to_string()).or_default() += count;
+        }
+        return Ok(None);
+    };
+    if *id == *AIR {
+        return Ok(None);
+    }
+    let imported = registry.get_block_with(id, |block| {
+        BlockState::new(
+            id.clone(),
+            state.properties().filter(|(name, value)| {
+                block
+                    .metadata
+                    .properties
+                    .get(*name)
+                    .is_some_and(|kind| kind.allows(value))
+            }),
+        )
+    });
+    if imported.is_none() && count != 0 {
+        *stats.unregistered.entry(id.to_string()).or_default() += count;
+    }
+    Ok(imported)
+}
+
+/// Gzipped NBT of `clipboard`, with its lowest corner as the origin.
+pub fn write(
+    clipboard: &Clipboard,
+    mapping: &BlockMapping,
+) -> anyhow::Result<(Vec<u8>, SchematicStats)> {
+    let size = clipboard.size();
+    anyhow::ensure!(
+        size.cmple(UVec3::splat(u16::MAX as u32)).all(),
+        "schematic of {size} blocks is too large"
+    );
+
+    // the states go through a chunk palette first, then each entry gets its Minecraft name
+    let mut palette = Palette::default();
+    let mut indices = vec![0u16; (size.x * size.y * size.z) as usize];
+    for (i, index) in indices.iter_mut().enumerate() {
+        let i = i as u32;
+        let offset = UVec3::new(i % size.x, i / (size.x * size.z), i / size.x % size.z);
+        let voxel = match clipboard.get(offset.as_ivec3()).flatten() {
+            Some(state) => palette.voxel_block(state)?,
+            None => VoxelBlock::Air,
+        };
+        *index = match voxel {
+            VoxelBlock::Air => 0,
+            VoxelBlock::Solid(idx) => idx,
+        };
+    }
+    let mut counts = vec![0u64; palette.len()];
+    for idx in &indices {
+        counts[*idx as usize] += 1;
+    }
+
+    let mut stats = SchematicStats::default();
+    let mut names = IndexSet::<String>::new();
+    let remap = (0..palette.len() as u16)
+        .map(|idx| {
+            let state = palette.block_state(idx).expect("palette index in range");
+            let name = match mapping.to_minecraft(state.id()) {
+                // Minecraft writes properties the same way, `name[axis=x,...]`
+                Some(name) => BlockState::new(BlockId::new(name), state.properties()).to_string(),
+                None if state.is_air() => AIR_NAME.to_owned(),
+                None => {
+                    if counts[idx as usize] != 0 {
+                        *stats.unmapped.entry(state.id().to_string()).or_default() +=
+                            counts[idx as usize];
+                    }
+                    AIR_NAME.to_owned()
+                }
+            };
+            names.insert_full(name).0 as u32
+        })
+        .collect::<Vec<_>>();
+    let mut data = Vec::with_capacity(indices.len());
+    for idx in indices {
+        write_varint(&mut data, remap[idx as usize]);
+    }
+
+    let root = Root {
+        schematic: Schematic {
+            version: SPONGE_VERSION,
+            data_version: EXPORT_DATA_VERSION,
+            width: size.x as u16 as i16,
+            height: size.y as u16 as i16,
+            length: size.z as u16 as i16,
+            offset: Some(IntArray::new(vec![0, 0, 0])),
+            blocks: Some(BlockContainer {
+                palette: names
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, name)| (name, idx as i32))
+                    .collect(),
+                data: ByteArray::new(data.into_iter().map(|byte| byte as i8).collect()),
+            }),
+        },
+    };
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&fastnbt::to_bytes(&root)?)?;
+    Ok((encoder.finish()?, stats))
+}
+
+fn sponge_index(size: UVec3, offset: UVec3) -> usize {
+    (offset.x + offset.z * size.x + offset.y * size.x * size.z) as usize
+}
+
+/// Unsigned LEB128, 7 bits per byte.
+fn read_varints(data: &[i8]) -> anyhow::Result<Vec<u32>> {
+    let mut values = Vec::new();
+    let (mut value, mut shift) = (0u32, 0);
+    for byte in data.iter().map(|byte| *byte as u8) {
+        anyhow::ensure!(shift < 32, "varint longer than 5 bytes");
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            values.push(value);
+            (value, shift) = (0, 0);
+        } else {
+            shift += 7;
+        }
+    }
+    anyhow::ensure!(shift == 0, "block data ends inside a varint");
+    Ok(values)
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+#[test]
+fn test_varints() {
+    let mut data = Vec::new();
+    for value in [0, 127, 128, 300, u32::MAX] {
+        write_varint(&mut data, value);
+    }
+    assert_eq!(data.len(), 1 + 1 + 2 + 2 + 5);
+    let data = data.into_iter().map(|byte| byte as i8).collect::<Vec<_>>();
+    assert_eq!(read_varints(&data).unwrap(), [0, 127, 128, 300, u32::MAX]);
+    assert!(read_varints(&data[..3]).is_err());
+}
+
+#[test]
+fn test_schematic_roundtrip() {
+    use bevy::math::IVec3;
+
+    let registry = Registry::new();
+    let lua = mlua::Lua::new();
+    lua.globals().set("Registry", registry.clone()).unwrap();
+    lua.globals().set("namespace", "core").unwrap();
+    lua.load(
+        r#"
+        Registry:set_block("stone", { textures = { top = "stone.png" } })
+        Registry:set_block("log", { properties = { axis = { "y", "x", "z" } }, textures = { top = "log.png" } })
+        "#,
+    )
+    .exec()
+    .unwrap();
+    let mapping = BlockMapping::parse(
+        "minecraft:air = core::air\n\
+         minecraft:stone = core::stone\n\
+         minecraft:oak_log = core::log\n\
+         minecraft:gold_block = core::gold",
+    )
+    .unwrap();
+
+    let stone = BlockState::parse("core::stone").unwrap();
+    let log = BlockState::parse("core::log[axis=x]").unwrap();
+    let clipboard = Clipboard::from_fn(UVec3::new(3, 2, 4), |offset| match offset.to_array() {
+        [0, 0, 0] => Some(stone.clone()),
+        [2, 1, 3] => Some(log.clone()),
+        [1, 0, 2] => Some(BlockState::parse("core::diamond").unwrap()),
+        [1, 1, 1] => Some(BlockState::parse("core::gold").unwrap()),
+        _ => None,
+    });
+    let (bytes, stats) = write(&clipboard, &mapping).unwrap();
+    assert_eq!(stats.unmapped.get("core::diamond"), Some(&1));
+
+    let (read_back, stats) = read(&bytes, &mapping, &registry).unwrap();
+    assert_eq!(read_back.size(), clipboard.size());
+    assert_eq!(read_back.get(IVec3::ZERO), Some(Some(&stone)));
+    assert_eq!(read_back.get(IVec3::new(2, 1, 3)), Some(Some(&log)));
+    // written as air, then as a block the registry does not know
+    assert_eq!(read_back.get(IVec3::new(1, 0, 2)), Some(None));
+    assert_eq!(read_back.get(IVec3::new(1, 1, 1)), Some(None));
+    assert_eq!(stats.unregistered.get("core::gold"), Some(&1));
+
+    // Minecraft properties the block does not declare are dropped
+    let root = Root {
+        schematic: Schematic {
+            version: SPONGE_VERSION,
+            data_version: EXPORT_DATA_VERSION,
+            width: 2,
+            height: 1,
+            length: 1,
+            offset: None,
+            blocks: Some(BlockContainer {
+                palette: [
+                    ("minecraft:oak_log[axis=z,waterlogged=false]".to_owned(), 0),
+                    ("minecraft:sponge".to_owned(), 1),
+                ]
+                .into_iter()
+                .collect(),
+                data: ByteArray::new(vec![0, 1]),
+            }),
+        },
+    };
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&fastnbt::to_bytes(&root).unwrap())
+        .unwrap();
+    let (read_back, stats) = read(&encoder.finish().unwrap(), &mapping, &registry).unwrap();
+    assert_eq!(
+        read_back.get(IVec3::ZERO),
+        Some(Some(&BlockState::parse("core::log[axis=z]").unwrap()))
+    );
+    assert_eq!(stats.unmapped.get("minecraft:sponge"), Some(&1));
+}
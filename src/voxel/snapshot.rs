@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ahash::{AHashMap, AHashSet};
+use anyhow::Context;
+use bevy::math::IVec3;
+use bevy::prelude::*;
+use redb::{ReadableTable, TableDefinition};
+
+use super::autosave::save_dirty_chunks;
+use super::chunk::ChunkData;
+use super::saver::ChunkSaver;
+use super::storage::{ChunkStorage, WorldDatabase, CHUNKS, METADATA, PENDING_EDITS};
+use super::world::VoxelWorld;
+
+/// Header hash of every chunk in the world when the snapshot was taken,
+/// including chunks whose bytes live in an earlier snapshot.
+const MANIFEST: TableDefinition<[i32; 3], u64> = TableDefinition::new("manifest");
+const SNAPSHOT_INFO: TableDefinition<&str, &[u8]> = TableDefinition::new("snapshot");
+const SNAPSHOT_INFO_KEY: &str = "info";
+/// chunks restored per write
+const RESTORE_BATCH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub struct SnapshotInfo {
+    pub id: u64,
+    /// the snapshot this one is incremental to, `None` for a full snapshot
+    pub parent: Option<u64>,
+    /// unix seconds
+    pub created: u64,
+    /// chunks in the world
+    pub chunks: u64,
+    /// chunks stored in this snapshot, the rest come from its parents
+    pub stored_chunks: u64,
+    pub pending_edits: u64,
+}
+
+/// A directory of snapshot files, `snapshot-<id>.redb`.
+/// Each is a `WorldDatabase` (chunks, pending edits, metadata) plus a manifest of
+/// chunk hashes, which incremental snapshots diff against.
+#[derive(Resource, Clone, Debug)]
+pub struct Snapshots {
+    dir: PathBuf,
+}
+
+impl Snapshots {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Snapshots { dir: dir.into() }
+    }
+
+    /// `<world>.snapshots` next to the world file.
+    pub fn for_world(world: impl AsRef<Path>) -> Self {
+        Self::new(world.as_ref().with_extension("snapshots"))
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("snapshot-{id}.redb"))
+    }
+
+    fn open(&self, id: u64) -> anyhow::Result<WorldDatabase> {
+        WorldDatabase::open(self.path(id)).with_context(|| format!("open snapshot {id}"))
+    }
+
+    /// Sorted by id, oldest first.
+    pub fn list(&self) -> anyhow::Result<Vec<SnapshotInfo>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("snapshot-"))
+                .and_then(|name| name.strip_suffix(".redb"))
+                .and_then(|id| id.parse().ok());
+            if let Some(id) = id {
+                snapshots.push(read_info(&self.open(id)?)?);
+            }
+        }
+        snapshots.sort_by_key(|info| info.id);
+        Ok(snapshots)
+    }
+
+    /// Copy everything in `storage` into a new snapshot.
+    /// With `incremental`, only chunks whose hash changed since the latest snapshot
+    /// are stored. Nothing may write chunks to `storage` meanwhile, in game run this
+    /// through `ChunkSaver::run`.
+    pub fn take(&self, storage: &dyn ChunkStorage, incremental: bool) -> anyhow::Result<SnapshotInfo> {
+        let _span = tracing::info_span!("profiling::{take snapshot}").entered();
+        fs::create_dir_all(&self.dir)?;
+
+        let latest = self.list()?.pop();
+        let (parent, parent_manifest) = match &latest {
+            Some(latest) if incremental => (Some(latest.id), read_manifest(&self.open(latest.id)?)?),
+            _ => (None, AHashMap::new()),
+        };
+        let id = latest.map_or(0, |latest| latest.id + 1);
+        let mut info = SnapshotInfo {
+            id,
+            parent,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            chunks: 0,
+            stored_chunks: 0,
+            pending_edits: 0,
+        };
+
+        // written under a temporary name, an interrupted snapshot never shows up in `list`
+        let tmp = self.dir.join(format!("snapshot-{id}.redb.tmp"));
+        fs::remove_file(&tmp).ok();
+        let snapshot = WorldDatabase::new(&tmp)?;
+        let txn = snapshot.db.begin_write()?;
+        {
+            let mut chunks = txn.open_table(CHUNKS)?;
+            let mut manifest = txn.open_table(MANIFEST)?;
+            storage.iterate(&mut |pos, bytes| {
+                let hash = ChunkData::read_header(bytes)?;
+                manifest.insert(pos.to_array(), hash)?;
+                info.chunks += 1;
+                if parent_manifest.get(&pos) != Some(&hash) {
+                    chunks.insert(pos.to_array(), bytes)?;
+                    info.stored_chunks += 1;
+                }
+                Ok(())
+            })?;
+
+            let mut pending_edits = txn.open_table(PENDING_EDITS)?;
+            for (chunk_pos, block_pos, block_id) in storage.pending_edits()? {
+                pending_edits.insert((chunk_pos.to_array(), block_pos.to_array()), block_id.as_str())?;
+                info.pending_edits += 1;
+            }
+
+            if let Some(metadata) = storage.metadata()? {
+                txn.open_table(METADATA)?
+                    .insert("world", &*metadata.encode()?)?;
+            }
+            let encoded = bincode::encode_to_vec(&info, bincode::config::standard())?;
+            txn.open_table(SNAPSHOT_INFO)?
+                .insert(SNAPSHOT_INFO_KEY, &*encoded)?;
+        }
+        txn.commit()?;
+        drop(snapshot);
+        fs::rename(&tmp, self.path(id))?;
+        Ok(info)
+    }
+
+    /// Replace everything in `target` with snapshot `id`.
+    /// The game must not be running on `target`.
+    pub fn restore(&self, id: u64, target: &dyn ChunkStorage) -> anyhow::Result<SnapshotInfo> {
+        let _span = tracing::info_span!("profiling::{restore snapshot}").entered();
+
+        // the snapshot, its parent, ..., back to a full snapshot
+        let mut chain = Vec::new();
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let snapshot = self.open(id)?;
+            next = read_info(&snapshot)?.parent;
+            chain.push(snapshot);
+        }
+        let info = read_info(&chain[0])?;
+        let manifest = read_manifest(&chain[0])?;
+
+        // the newest copy of each chunk wins
+        let mut restored = AHashSet::new();
+        for snapshot in &chain {
+            snapshot
+                .read(CHUNKS, |_, table| {
+                    let mut batch = Vec::with_capacity(RESTORE_BATCH);
+                    for entry in table.iter()? {
+                        let (key, value) = entry?;
+                        let pos = IVec3::from_array(key.value());
+                        if manifest.contains_key(&pos) && restored.insert(pos) {
+                            batch.push((pos, value.value().to_vec()));
+                        }
+                        if batch.len() == RESTORE_BATCH {
+                            save_batch(target, &mut batch)?;
+                        }
+                    }
+                    save_batch(target, &mut batch)
+                })
+                .and_then(|v| v)?;
+        }
+        anyhow::ensure!(
+            restored.len() == manifest.len(),
+            "snapshot {id} is missing {} chunks",
+            manifest.len() - restored.len()
+        );
+
+        // chunks created after the snapshot
+        let mut stale = Vec::new();
+        target.iterate(&mut |pos, _| {
+            if !manifest.contains_key(&pos) {
+                stale.push(pos);
+            }
+            Ok(())
+        })?;
+        for pos in stale {
+            target.delete(pos)?;
+        }
+
+        let stale_edits = target
+            .pending_edits()?
+            .into_iter()
+            .map(|(chunk_pos, _, _)| chunk_pos)
+            .collect::<AHashSet<_>>();
+        for chunk_pos in stale_edits {
+            target.remove_pending_edits(chunk_pos)?;
+        }
+        target.save_pending_edits(&chain[0].pending_edits()?)?;
+
+        if let Some(metadata) = chain[0].metadata()? {
+            target.set_metadata(&metadata)?;
+        }
+        Ok(info)
+    }
+}
+
+fn save_batch(target: &dyn ChunkStorage, batch: &mut Vec<(IVec3, Vec<u8>)>) -> anyhow::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let chunks = batch
+        .iter()
+        .map(|(pos, bytes)| (*pos, &**bytes))
+        .collect::<Vec<_>>();
+    target.save(&chunks)?;
+    batch.clear();
+    Ok(())
+}
+
+fn read_info(snapshot: &WorldDatabase) -> anyhow::Result<SnapshotInfo> {
+    let bytes = snapshot
+        .read(SNAPSHOT_INFO, |_, table| {
+            anyhow::Ok(table.get(SNAPSHOT_INFO_KEY)?.map(|v| v.value().to_vec()))
+        })
+        .and_then(|v| v)?
+        .with_context(|| "snapshot has no info")?;
+    let (info, _) = bincode::decode_from_slice(&bytes, bincode::config::standard())
+        .with_context(|| "decode snapshot info")?;
+    Ok(info)
+}
+
+fn read_manifest(snapshot: &WorldDatabase) -> anyhow::Result<AHashMap<IVec3, u64>> {
+    snapshot
+        .read(MANIFEST, |_, table| {
+            let mut manifest = AHashMap::new();
+            for entry in table.iter()? {
+                let (key, value) = entry?;
+                manifest.insert(IVec3::from_array(key.value()), value.value());
+            }
+            anyhow::Ok(manifest)
+        })
+        .and_then(|v| v)
+}
+
+/// Snapshot the running world.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TakeSnapshot {
+    pub incremental: bool,
+}
+
+/// A requested snapshot, waiting for the chunks that were being unloaded then.
+pub struct PendingSnapshot {
+    incremental: bool,
+    unloading: Vec<IVec3>,
+}
+
+/// Saves dirty chunks, then takes the snapshot on the saver thread so no chunk
+/// is written while it is taken. Chunks that were being unloaded are encoded off
+/// the main thread and reach the saver later, so the snapshot is only queued once
+/// they are committed, checked once a frame instead of blocking it.
+pub fn take_snapshot(
+    mut events: EventReader<TakeSnapshot>,
+    world: Res<VoxelWorld>,
+    saver: Res<ChunkSaver>,
+    snapshots: Res<Snapshots>,
+    mut pending: Local<VecDeque<PendingSnapshot>>,
+) {
+    for event in events.read() {
+        save_dirty_chunks(&world, &saver);
+        let mut unloading = Vec::new();
+        world.saving_chunks.scan(|pos| unloading.push(*pos));
+        pending.push_back(PendingSnapshot {
+            incremental: event.incremental,
+            unloading,
+        });
+    }
+
+    // in the order they were requested
+    while let Some(snapshot) = pending.front_mut() {
+        snapshot
+            .unloading
+            .retain(|pos| world.saving_chunks.contains(pos));
+        if !snapshot.unloading.is_empty() {
+            break;
+        }
+        let incremental = snapshot.incremental;
+        pending.pop_front();
+
+        let snapshots = snapshots.clone();
+        saver.run(move |storage| match snapshots.take(&***storage, incremental) {
+            Ok(info) => tracing::info!(
+                "took snapshot {}: {} chunks, {} stored",
+                info.id,
+                info.chunks,
+                info.stored_chunks
+            ),
+            Err(err) => tracing::error!("take snapshot failed: {:?}", err),
+        });
+    }
+}
+
+/// F5 takes an incremental snapshot, Shift+F5 a full one.
+pub fn snapshot_hotkey(keys: Res<ButtonInput<KeyCode>>, mut events: EventWriter<TakeSnapshot>) {
+    if keys.just_pressed(KeyCode::F5) {
+        let full = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        events.send(TakeSnapshot { incremental: !full });
+    }
+}
+
+#[test]
+fn test_incremental_snapshot_restore() {
+    use bevy::math::UVec3;
+
+    use super::storage::MemoryStorage;
+    use super::voxel_block::BlockState;
+
+    let dir = std::env::temp_dir().join(format!("minecrust-snapshots-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let snapshots = Snapshots::new(&dir);
+    let grass = BlockState::parse("core::grass").unwrap();
+
+    let save = |storage: &MemoryStorage, chunk: &mut ChunkData| {
+        chunk.generate_hash();
+        storage.save(&[(chunk.pos, &chunk.encode().unwrap()[..])]).unwrap();
+    };
+    let world = MemoryStorage::new();
+    let mut a = ChunkData::new(IVec3::new(0, 0, 0), Entity::PLACEHOLDER);
+    let mut b = ChunkData::new(IVec3::new(1, 0, 0), Entity::PLACEHOLDER);
+    a.set_block(UVec3::new(1, 1, 1), &grass).unwrap();
+    save(&world, &mut a);
+    save(&world, &mut b);
+    let old_b_hash = b.hash;
+    let full = snapshots.take(&world, true).unwrap();
+    assert_eq!((full.parent, full.chunks, full.stored_chunks), (None, 2, 2));
+
+    // change one chunk, add one, delete one
+    b.set_block(UVec3::new(2, 2, 2), &grass).unwrap();
+    save(&world, &mut b);
+    let mut c = ChunkData::new(IVec3::new(2, 0, 0), Entity::PLACEHOLDER);
+    save(&world, &mut c);
+    world.delete(a.pos).unwrap();
+    world
+        .save_pending_edits(&[(IVec3::new(9, 0, 0), IVec3::new(288, 0, 0), grass.clone())])
+        .unwrap();
+    let incremental = snapshots.take(&world, true).unwrap();
+    assert_eq!(incremental.parent, Some(full.id));
+    assert_eq!((incremental.chunks, incremental.stored_chunks), (2, 2));
+    assert_eq!(snapshots.list().unwrap().len(), 2);
+
+    let target = MemoryStorage::new();
+    snapshots.restore(full.id, &target).unwrap();
+    assert!(target.exists(a.pos).unwrap());
+    assert_eq!(target.load_hash(b.pos).unwrap(), Some(old_b_hash));
+
+    snapshots.restore(incremental.id, &target).unwrap();
+    assert!(!target.exists(a.pos).unwrap());
+    assert_eq!(target.load_hash(b.pos).unwrap(), Some(b.hash));
+    assert!(target.exists(c.pos).unwrap());
+    assert_eq!(target.pending_edits().unwrap().len(), 1);
+
+    fs::remove_dir_all(&dir).ok();
+}
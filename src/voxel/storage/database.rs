@@ -0,0 +1,266 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use bevy::math::IVec3;
+use redb::{ReadOnlyTable, ReadTransaction, ReadableTable, Table, TableDefinition, WriteTransaction};
+
+use super::{ChunkStorage, WorldMetadata};
+use crate::voxel::chunk::ChunkData;
+use crate::voxel::voxel_block::BlockState;
+
+pub const CHUNKS: TableDefinition<[i32; 3], &[u8]> = TableDefinition::new("chunks");
+pub const METADATA: TableDefinition<&str, &[u8]> = TableDefinition::new("metadata");
+/// Edits to chunks that were not loaded yet. (chunk pos, block pos) -> block id
+pub const PENDING_EDITS: TableDefinition<([i32; 3], [i32; 3]), &str> =
+    TableDefinition::new("pending_edits");
+/// Raw bytes of chunks that failed to decode.
+pub const QUARANTINE: TableDefinition<[i32; 3], &[u8]> = TableDefinition::new("quarantine");
+
+const WORLD_METADATA_KEY: &str = "world";
+
+/// Single-file world backed by redb.
+#[derive(Clone)]
+pub struct WorldDatabase {
+    pub db: Arc<redb::Database>,
+}
+
+impl WorldDatabase {
+    pub fn write<R, K, V>(
+        &self,
+        table: TableDefinition<K, V>,
+        f: impl FnOnce(&WriteTransaction, Table<K, V>) -> R,
+    ) -> anyhow::Result<R>
+    where
+        K: redb::Key + 'static,
+        V: redb::Value + 'static,
+    {
+        let txn = self.db.begin_write()?;
+        let table = txn.open_table(table)?;
+        let ret = f(&txn, table);
+        txn.commit()?;
+        Ok(ret)
+    }
+
+    pub fn read<R, K, V>(
+        &self,
+        table: TableDefinition<K, V>,
+        f: impl FnOnce(&ReadTransaction, ReadOnlyTable<K, V>) -> R,
+    ) -> anyhow::Result<R>
+    where
+        K: redb::Key + 'static,
+        V: redb::Value + 'static,
+    {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(table)?;
+        let ret = f(&txn, table);
+        Ok(ret)
+    }
+
+    /// Open the world at `path`, creating the file and its tables if needed.
+    pub fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let db = WorldDatabase {
+            db: Arc::new(redb::Database::builder().create(path)?),
+        };
+        // create tables
+        let txn = db.db.begin_write()?;
+        txn.open_table(CHUNKS)?;
+        txn.open_table(PENDING_EDITS)?;
+        txn.open_table(METADATA)?;
+        txn.open_table(QUARANTINE)?;
+        txn.commit()?;
+        Ok(db)
+    }
+
+    /// Open an existing world without creating or modifying anything.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(WorldDatabase {
+            db: Arc::new(redb::Database::builder().open(path)?),
+        })
+    }
+
+    /// Compact the database file until no further space can be reclaimed.
+    /// Fails if any other clone of this `WorldDatabase` is alive.
+    pub fn compact(&mut self) -> anyhow::Result<()> {
+        let db = Arc::get_mut(&mut self.db)
+            .with_context(|| "compact database but it is still shared")?;
+        while db.compact()? {}
+        Ok(())
+    }
+}
+
+impl ChunkStorage for WorldDatabase {
+    fn load(&self, pos: IVec3) -> anyhow::Result<Option<Vec<u8>>> {
+        self.read(CHUNKS, |_, table| {
+            anyhow::Ok(table.get(pos.to_array())?.map(|v| v.value().to_vec()))
+        })
+        .and_then(|v| v)
+    }
+
+    fn save(&self, chunks: &[(IVec3, &[u8])]) -> anyhow::Result<()> {
+        self.write(CHUNKS, |_, mut table| {
+            for (pos, bytes) in chunks {
+                table.insert(pos.to_array(), *bytes)?;
+            }
+            anyhow::Ok(())
+        })
+        .and_then(|v| v)
+    }
+
+    fn delete(&self, pos: IVec3) -> anyhow::Result<bool> {
+        self.write(CHUNKS, |_, mut table| {
+            let removed = table.remove(pos.to_array())?.is_some();
+            anyhow::Ok(removed)
+        })
+        .and_then(|v| v)
+    }
+
+    fn exists(&self, pos: IVec3) -> anyhow::Result<bool> {
+        self.read(CHUNKS, |_, table| {
+            anyhow::Ok(table.get(pos.to_array())?.is_some())
+        })
+        .and_then(|v| v)
+    }
+
+    fn iterate(
+        &self,
+        f: &mut dyn FnMut(IVec3, &[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.read(CHUNKS, |_, table| {
+            for entry in table.iter()? {
+                let (key, value) = entry?;
+                f(IVec3::from_array(key.value()), value.value())?;
+            }
+            anyhow::Ok(())
+        })
+        .and_then(|v| v)
+    }
+
+    fn load_hash(&self, pos: IVec3) -> anyhow::Result<Option<u64>> {
+        self.read(CHUNKS, |_, table| {
+            table
+                .get(pos.to_array())?
+                .map(|v| ChunkData::read_header(v.value()))
+                .transpose()
+        })
+        .and_then(|v| v)
+    }
+
+    fn quarantine(&self, pos: IVec3, bytes: &[u8]) -> anyhow::Result<()> {
+        let txn = self.db.begin_write()?;
+        txn.open_table(QUARANTINE)?.insert(pos.to_array(), bytes)?;
+        txn.open_table(CHUNKS)?.remove(pos.to_array())?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn quarantined(&self) -> anyhow::Result<Vec<IVec3>> {
+        let txn = self.db.begin_read()?;
+        // worlds older than the table
+        let table = match txn.open_table(QUARANTINE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut positions = Vec::new();
+        for entry in table.iter()? {
+            let (key, _) = entry?;
+            positions.push(IVec3::from_array(key.value()));
+        }
+        Ok(positions)
+    }
+
+    fn save_pending_edits(&self, edits: &[(IVec3, IVec3, BlockState)]) -> anyhow::Result<()> {
+        self.write(PENDING_EDITS, |_, mut table| {
+            for (chunk_pos, block_pos, state) in edits {
+                table.insert((chunk_pos.to_array(), block_pos.to_array()), state.as_str())?;
+            }
+            anyhow::Ok(())
+        })
+        .and_then(|v| v)
+    }
+
+    fn load_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<Vec<(IVec3, BlockState)>> {
+        let chunk_pos = chunk_pos.to_array();
+        self.read(PENDING_EDITS, |_, table| {
+            let mut edits = Vec::new();
+            for entry in table.range((chunk_pos, [i32::MIN; 3])..=(chunk_pos, [i32::MAX; 3]))? {
+                let (key, value) = entry?;
+                let (_, block_pos) = key.value();
+                edits.push((IVec3::from_array(block_pos), BlockState::parse(value.value())?));
+            }
+            anyhow::Ok(edits)
+        })
+        .and_then(|v| v)
+    }
+
+    fn remove_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<()> {
+        let chunk_pos = chunk_pos.to_array();
+        self.write(PENDING_EDITS, |_, mut table| {
+            table.retain_in((chunk_pos, [i32::MIN; 3])..=(chunk_pos, [i32::MAX; 3]), |_, _| false)
+        })??;
+        Ok(())
+    }
+
+    fn save_applied(
+        &self,
+        chunks: &[(IVec3, &[u8])],
+        applied_edits: &[(IVec3, IVec3, BlockState)],
+    ) -> anyhow::Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(CHUNKS)?;
+            for (pos, bytes) in chunks {
+                table.insert(pos.to_array(), *bytes)?;
+            }
+            let mut pending = txn.open_table(PENDING_EDITS)?;
+            for (chunk_pos, block_pos, state) in applied_edits {
+                let key = (chunk_pos.to_array(), block_pos.to_array());
+                let applied = pending
+                    .get(key)?
+                    .is_some_and(|id| id.value() == state.as_str());
+                if applied {
+                    pending.remove(key)?;
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn pending_edits(&self) -> anyhow::Result<Vec<(IVec3, IVec3, BlockState)>> {
+        self.read(PENDING_EDITS, |_, table| {
+            let mut edits = Vec::new();
+            for entry in table.iter()? {
+                let (key, value) = entry?;
+                let (chunk_pos, block_pos) = key.value();
+                edits.push((
+                    IVec3::from_array(chunk_pos),
+                    IVec3::from_array(block_pos),
+                    BlockState::parse(value.value())?,
+                ));
+            }
+            anyhow::Ok(edits)
+        })
+        .and_then(|v| v)
+    }
+
+    fn metadata(&self) -> anyhow::Result<Option<WorldMetadata>> {
+        self.read(METADATA, |_, table| {
+            table
+                .get(WORLD_METADATA_KEY)?
+                .map(|bytes| WorldMetadata::decode(bytes.value()))
+                .transpose()
+        })
+        .and_then(|v| v)
+    }
+
+    fn set_metadata(&self, metadata: &WorldMetadata) -> anyhow::Result<()> {
+        let bytes = metadata.encode()?;
+        self.write(METADATA, |_, mut table| {
+            table.insert(WORLD_METADATA_KEY, &*bytes)?;
+            anyhow::Ok(())
+        })
+        .and_then(|v| v)
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use ahash::AHashMap;
+use bevy::math::IVec3;
+use parking_lot::RwLock;
+
+use super::{ChunkStorage, WorldMetadata};
+use crate::voxel::voxel_block::BlockState;
+
+/// Keeps everything in memory, for tests and throwaway worlds.
+#[derive(Default)]
+pub struct MemoryStorage {
+    chunks: RwLock<AHashMap<IVec3, Vec<u8>>>,
+    quarantine: RwLock<AHashMap<IVec3, Vec<u8>>>,
+    pending_edits: RwLock<BTreeMap<([i32; 3], [i32; 3]), BlockState>>,
+    metadata: RwLock<Option<WorldMetadata>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChunkStorage for MemoryStorage {
+    fn load(&self, pos: IVec3) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.chunks.read().get(&pos).cloned())
+    }
+
+    fn save(&self, chunks: &[(IVec3, &[u8])]) -> anyhow::Result<()> {
+        let mut map = self.chunks.write();
+        for (pos, bytes) in chunks {
+            map.insert(*pos, bytes.to_vec());
+        }
+        Ok(())
+    }
+
+    fn delete(&self, pos: IVec3) -> anyhow::Result<bool> {
+        Ok(self.chunks.write().remove(&pos).is_some())
+    }
+
+    fn exists(&self, pos: IVec3) -> anyhow::Result<bool> {
+        Ok(self.chunks.read().contains_key(&pos))
+    }
+
+    fn iterate(
+        &self,
+        f: &mut dyn FnMut(IVec3, &[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        for (pos, bytes) in self.chunks.read().iter() {
+            f(*pos, bytes)?;
+        }
+        Ok(())
+    }
+
+    fn quarantine(&self, pos: IVec3, bytes: &[u8]) -> anyhow::Result<()> {
+        self.quarantine.write().insert(pos, bytes.to_vec());
+        self.chunks.write().remove(&pos);
+        Ok(())
+    }
+
+    fn quarantined(&self) -> anyhow::Result<Vec<IVec3>> {
+        Ok(self.quarantine.read().keys().copied().collect())
+    }
+
+    fn save_pending_edits(&self, edits: &[(IVec3, IVec3, BlockState)]) -> anyhow::Result<()> {
+        let mut map = self.pending_edits.write();
+        for (chunk_pos, block_pos, state) in edits {
+            map.insert((chunk_pos.to_array(), block_pos.to_array()), state.clone());
+        }
+        Ok(())
+    }
+
+    fn load_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<Vec<(IVec3, BlockState)>> {
+        let chunk_pos = chunk_pos.to_array();
+        Ok(self
+            .pending_edits
+            .read()
+            .range((chunk_pos, [i32::MIN; 3])..=(chunk_pos, [i32::MAX; 3]))
+            .map(|((_, block_pos), id)| (IVec3::from_array(*block_pos), id.clone()))
+            .collect())
+    }
+
+    fn remove_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<()> {
+        let chunk_pos = chunk_pos.to_array();
+        self.pending_edits
+            .write()
+            .retain(|(pos, _), _| *pos != chunk_pos);
+        Ok(())
+    }
+
+    fn save_applied(
+        &self,
+        chunks: &[(IVec3, &[u8])],
+        applied_edits: &[(IVec3, IVec3, BlockState)],
+    ) -> anyhow::Result<()> {
+        self.save(chunks)?;
+        let mut map = self.pending_edits.write();
+        for (chunk_pos, block_pos, state) in applied_edits {
+            let key = (chunk_pos.to_array(), block_pos.to_array());
+            if map.get(&key) == Some(state) {
+                map.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    fn pending_edits(&self) -> anyhow::Result<Vec<(IVec3, IVec3, BlockState)>> {
+        Ok(self
+            .pending_edits
+            .read()
+            .iter()
+            .map(|((chunk_pos, block_pos), id)| {
+                (
+                    IVec3::from_array(*chunk_pos),
+                    IVec3::from_array(*block_pos),
+                    id.clone(),
+                )
+            })
+            .collect())
+    }
+
+    fn metadata(&self) -> anyhow::Result<Option<WorldMetadata>> {
+        Ok(self.metadata.read().clone())
+    }
+
+    fn set_metadata(&self, metadata: &WorldMetadata) -> anyhow::Result<()> {
+        *self.metadata.write() = Some(metadata.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use bevy::math::IVec3;
+use bevy::prelude::{Deref, Resource};
+
+use super::chunk::ChunkData;
+use super::generator::noise::NoiseConfig;
+use super::generator::GeneratorKind;
+use super::voxel_block::BlockState;
+
+pub use database::{WorldDatabase, CHUNKS, METADATA, PENDING_EDITS, QUARANTINE};
+pub use memory::MemoryStorage;
+pub use region::RegionStorage;
+
+mod database;
+mod memory;
+mod region;
+
+/// Version of the world layout (tables and metadata), not of the chunk payload.
+/// Version 2 stores the generator parameters in the metadata.
+pub const WORLD_FORMAT_VERSION: u32 = 2;
+
+/// Written once when the world is created, read back every time it is opened.
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub struct WorldMetadata {
+    pub format_version: u32,
+    pub seed: u32,
+    pub generator: GeneratorKind,
+    pub spawn: [i32; 3],
+}
+
+impl WorldMetadata {
+    #[inline]
+    pub fn spawn(&self) -> IVec3 {
+        IVec3::from_array(self.spawn)
+    }
+
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        bincode::encode_to_vec(self, bincode::config::standard())
+            .with_context(|| "encode world metadata")
+    }
+
+    /// Older metadata is upgraded, but keeps its `format_version` so `WorldStorage::open`
+    /// knows to write it back.
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let config = bincode::config::standard();
+        let (format_version, _) = bincode::decode_from_slice::<u32, _>(bytes, config)
+            .with_context(|| "decode world format version")?;
+        anyhow::ensure!(
+            format_version <= WORLD_FORMAT_VERSION,
+            "world format version {} is newer than supported version {}",
+            format_version,
+            WORLD_FORMAT_VERSION
+        );
+        if format_version == 1 {
+            let (metadata, _) = bincode::decode_from_slice::<MetadataV1, _>(bytes, config)
+                .with_context(|| "decode version 1 world metadata")?;
+            return Ok(metadata.into());
+        }
+        let (metadata, _) = bincode::decode_from_slice::<WorldMetadata, _>(bytes, config)
+            .with_context(|| "decode world metadata")?;
+        Ok(metadata)
+    }
+}
+
+/// Version 1 only knew which generator a world used, its parameters were hardcoded.
+#[derive(bincode::Decode)]
+struct MetadataV1 {
+    format_version: u32,
+    seed: u32,
+    generator: GeneratorKindV1,
+    spawn: [i32; 3],
+}
+
+#[derive(bincode::Decode)]
+enum GeneratorKindV1 {
+    Flat,
+    Noise,
+}
+
+impl From<MetadataV1> for WorldMetadata {
+    fn from(metadata: MetadataV1) -> Self {
+        WorldMetadata {
+            format_version: metadata.format_version,
+            seed: metadata.seed,
+            generator: match metadata.generator {
+                GeneratorKindV1::Flat => GeneratorKind::Flat,
+                GeneratorKindV1::Noise => GeneratorKind::Noise(NoiseConfig::default()),
+            },
+            spawn: metadata.spawn,
+        }
+    }
+}
+
+impl Default for WorldMetadata {
+    /// Matches what worlds were generated with before metadata existed,
+    /// so old worlds without a metadata table open without seams.
+    fn default() -> Self {
+        WorldMetadata {
+            format_version: WORLD_FORMAT_VERSION,
+            seed: 1234,
+            generator: GeneratorKind::Noise(NoiseConfig::default()),
+            spawn: [50, 5, 50],
+        }
+    }
+}
+
+/// Where a world keeps its chunks, the edits waiting for unloaded chunks and its metadata.
+/// Chunks are stored encoded (see `ChunkData::encode`), backends never decode them.
+pub trait ChunkStorage: Send + Sync + 'static {
+    fn load(&self, pos: IVec3) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Write all `chunks`, in a single transaction if the backend has them.
+    fn save(&self, chunks: &[(IVec3, &[u8])]) -> anyhow::Result<()>;
+
+    /// Returns `true` if a chunk was removed.
+    fn delete(&self, pos: IVec3) -> anyhow::Result<bool>;
+
+    fn exists(&self, pos: IVec3) -> anyhow::Result<bool>;
+
+    /// Visit every stored chunk, in no particular order.
+    fn iterate(
+        &self,
+        f: &mut dyn FnMut(IVec3, &[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>;
+
+    /// Hash from the header of the stored chunk.
+    fn load_hash(&self, pos: IVec3) -> anyhow::Result<Option<u64>> {
+        self.load(pos)?
+            .map(|bytes| ChunkData::read_header(&bytes))
+            .transpose()
+    }
+
+    /// Move the bytes of an undecodable chunk out of the way, so the chunk can be
+    /// regenerated without losing the original data.
+    fn quarantine(&self, pos: IVec3, bytes: &[u8]) -> anyhow::Result<()>;
+
+    fn quarantined(&self) -> anyhow::Result<Vec<IVec3>>;
+
+    /// Persist edits to unloaded chunks as (chunk pos, block pos, block id).
+    fn save_pending_edits(&self, edits: &[(IVec3, IVec3, BlockState)]) -> anyhow::Result<()>;
+
+    fn load_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<Vec<(IVec3, BlockState)>>;
+
+    fn remove_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<()>;
+
+    /// Write `chunks` like `save` and drop the pending edits they applied, as (chunk pos,
+    /// block pos, block id), in the same transaction if the backend has them. An edit is
+    /// only dropped while it is still the one pending for its block.
+    fn save_applied(
+        &self,
+        chunks: &[(IVec3, &[u8])],
+        applied_edits: &[(IVec3, IVec3, BlockState)],
+    ) -> anyhow::Result<()>;
+
+    /// Every pending edit as (chunk pos, block pos, block id).
+    fn pending_edits(&self) -> anyhow::Result<Vec<(IVec3, IVec3, BlockState)>>;
+
+    fn metadata(&self) -> anyhow::Result<Option<WorldMetadata>>;
+
+    fn set_metadata(&self, metadata: &WorldMetadata) -> anyhow::Result<()>;
+}
+
+/// The storage backend of the current world.
+#[derive(Resource, Clone, Deref)]
+pub struct WorldStorage(#[deref] pub Arc<dyn ChunkStorage>);
+
+impl WorldStorage {
+    /// `metadata` is only called (and written) when the storage has no metadata yet.
+    pub fn open(
+        storage: impl ChunkStorage,
+        metadata: impl FnOnce() -> WorldMetadata,
+    ) -> anyhow::Result<Self> {
+        match storage.metadata()? {
+            Some(existing) => {
+                anyhow::ensure!(
+                    existing.format_version <= WORLD_FORMAT_VERSION,
+                    "world format version {} is newer than supported version {}",
+                    existing.format_version,
+                    WORLD_FORMAT_VERSION
+                );
+                if existing.format_version < WORLD_FORMAT_VERSION {
+                    storage.set_metadata(&WorldMetadata {
+                        format_version: WORLD_FORMAT_VERSION,
+                        ..existing
+                    })?;
+                }
+            }
+            None => storage.set_metadata(&metadata())?,
+        }
+        Ok(WorldStorage(Arc::new(storage)))
+    }
+
+    /// Always present once the storage went through `open`.
+    pub fn world_metadata(&self) -> anyhow::Result<WorldMetadata> {
+        self.0.metadata()?.with_context(|| "world has no metadata")
+    }
+}
+
+#[test]
+fn test_upgrade_metadata_v1() {
+    // format version, seed, `GeneratorKindV1::Noise`, spawn
+    let v1 = bincode::encode_to_vec((1u32, 99u32, 1u32, [1, 2, 3]), bincode::config::standard())
+        .unwrap();
+    let metadata = WorldMetadata::decode(&v1).unwrap();
+    assert_eq!(
+        metadata,
+        WorldMetadata {
+            format_version: 1,
+            seed: 99,
+            generator: GeneratorKind::Noise(NoiseConfig::default()),
+            spawn: [1, 2, 3],
+        }
+    );
+
+    let storage = MemoryStorage::new();
+    storage.set_metadata(&metadata).unwrap();
+    let storage = WorldStorage::open(storage, WorldMetadata::default).unwrap();
+    let upgraded = storage.world_metadata().unwrap();
+    assert_eq!(upgraded.format_version, WORLD_FORMAT_VERSION);
+    assert_eq!(upgraded.seed, 99);
+    assert_eq!(
+        WorldMetadata::decode(&upgraded.encode().unwrap()).unwrap(),
+        upgraded
+    );
+
+    let future = WorldMetadata {
+        format_version: WORLD_FORMAT_VERSION + 1,
+        ..upgraded
+    };
+    assert!(WorldMetadata::decode(&future.encode().unwrap()).is_err());
+}
+
+#[test]
+fn test_save_applied() {
+    let path = std::env::temp_dir().join(format!("minecrust-applied-{}.db", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let backends: [Box<dyn ChunkStorage>; 2] = [
+        Box::new(MemoryStorage::new()),
+        Box::new(WorldDatabase::new(&path).unwrap()),
+    ];
+
+    let chunk = IVec3::new(1, 0, 0);
+    let (a, b) = (IVec3::new(32, 0, 0), IVec3::new(33, 0, 0));
+    let grass = BlockState::parse("core::grass").unwrap();
+    let stone = BlockState::parse("core::stone").unwrap();
+    for storage in backends {
+        storage
+            .save_pending_edits(&[(chunk, a, grass.clone()), (chunk, b, grass.clone())])
+            .unwrap();
+        // b was edited again after the chunk applied it
+        storage
+            .save_pending_edits(&[(chunk, b, stone.clone())])
+            .unwrap();
+        storage
+            .save_applied(
+                &[(chunk, &b"chunk"[..])],
+                &[(chunk, a, grass.clone()), (chunk, b, grass.clone())],
+            )
+            .unwrap();
+        assert_eq!(storage.load(chunk).unwrap().as_deref(), Some(&b"chunk"[..]));
+        assert_eq!(
+            storage.load_pending_edits(chunk).unwrap(),
+            vec![(b, stone.clone())]
+        );
+    }
+    std::fs::remove_file(&path).ok();
+}
@@ -0,0 +1,581 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use ahash::{AHashMap, AHashSet};
+use anyhow::Context;
+use bevy::math::IVec3;
+use parking_lot::Mutex;
+
+use super::{ChunkStorage, WorldMetadata};
+use crate::voxel::voxel_block::BlockState;
+
+/// Chunks per region file along each axis.
+pub const REGION_SIZE: i32 = 8;
+
+const RECORD_HEADER_LEN: u64 = 16;
+const TOMBSTONE: u32 = u32::MAX;
+/// Region files smaller than this are never compacted.
+const MIN_COMPACT_LEN: u64 = 1 << 20;
+/// Region files kept open, the least recently used one is closed to open another.
+const MAX_OPEN_REGIONS: usize = 64;
+const METADATA_FILE: &str = "metadata.bin";
+const PENDING_EDITS_FILE: &str = "pending_edits.bin";
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// A directory with one file per `REGION_SIZE`³ chunks, named `r.<x>.<y>.<z>.region`,
+/// plus `metadata.bin`, `pending_edits.bin` and `quarantine/c.<x>.<y>.<z>.bin`.
+///
+/// A region file is an append-only log of records `[x, y, z: i32][len: u32][bytes]`
+/// (little endian), `len == u32::MAX` deletes the chunk. The last record of a chunk wins,
+/// and a file is rewritten once less than half of it is still in use.
+pub struct RegionStorage {
+    dir: PathBuf,
+    regions: Mutex<AHashMap<IVec3, Region>>,
+    pending_edits: Mutex<BTreeMap<([i32; 3], [i32; 3]), BlockState>>,
+}
+
+struct Region {
+    path: PathBuf,
+    file: File,
+    /// chunk pos -> (offset of the chunk bytes, length)
+    index: AHashMap<IVec3, (u64, u32)>,
+    /// file length
+    len: u64,
+    /// bytes of records that are still the latest of their chunk
+    live: u64,
+    last_used: Instant,
+}
+
+impl RegionStorage {
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let pending_edits = match fs::read(dir.join(PENDING_EDITS_FILE)) {
+            Ok(bytes) => {
+                let (edits, _) = bincode::decode_from_slice::<
+                    Vec<([i32; 3], [i32; 3], String)>,
+                    _,
+                >(&bytes, bincode::config::standard())
+                .with_context(|| "decode pending edits")?;
+                edits
+                    .into_iter()
+                    .map(|(chunk_pos, block_pos, state)| {
+                        Ok(((chunk_pos, block_pos), BlockState::parse(&state)?))
+                    })
+                    .collect::<anyhow::Result<_>>()?
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(RegionStorage {
+            dir,
+            regions: Default::default(),
+            pending_edits: Mutex::new(pending_edits),
+        })
+    }
+
+    #[inline]
+    pub fn region_pos(chunk_pos: IVec3) -> IVec3 {
+        chunk_pos.div_euclid(IVec3::splat(REGION_SIZE))
+    }
+
+    fn region_path(&self, region_pos: IVec3) -> PathBuf {
+        self.dir.join(format!(
+            "r.{}.{}.{}.region",
+            region_pos.x, region_pos.y, region_pos.z
+        ))
+    }
+
+    /// `None` if the region has no file and `create` is false.
+    /// Opening one past `MAX_OPEN_REGIONS` closes the least recently used.
+    fn region<'a>(
+        &self,
+        regions: &'a mut AHashMap<IVec3, Region>,
+        region_pos: IVec3,
+        create: bool,
+    ) -> anyhow::Result<Option<&'a mut Region>> {
+        if !regions.contains_key(&region_pos) {
+            let path = self.region_path(region_pos);
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            if regions.len() >= MAX_OPEN_REGIONS {
+                close_least_recently_used(regions)?;
+            }
+            regions.insert(region_pos, Region::open(path)?);
+        }
+        let region = regions.get_mut(&region_pos).expect("region just opened");
+        region.last_used = Instant::now();
+        Ok(Some(region))
+    }
+
+    fn region_files(&self) -> anyhow::Result<Vec<IVec3>> {
+        let mut regions = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(coords) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("r."))
+                .and_then(|name| name.strip_suffix(".region"))
+            else {
+                continue;
+            };
+            let coords = coords
+                .split('.')
+                .map(str::parse)
+                .collect::<Result<Vec<i32>, _>>();
+            if let Ok(&[x, y, z]) = coords.as_deref() {
+                regions.push(IVec3::new(x, y, z));
+            }
+        }
+        Ok(regions)
+    }
+
+    fn write_pending_edits(
+        &self,
+        edits: &BTreeMap<([i32; 3], [i32; 3]), BlockState>,
+    ) -> anyhow::Result<()> {
+        let list = edits
+            .iter()
+            .map(|((chunk_pos, block_pos), id)| (*chunk_pos, *block_pos, id.as_str()))
+            .collect::<Vec<_>>();
+        let bytes = bincode::encode_to_vec(list, bincode::config::standard())?;
+        write_atomic(&self.dir.join(PENDING_EDITS_FILE), &bytes)
+    }
+}
+
+impl Region {
+    fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open region file {}", path.display()))?;
+        let mut region = Region {
+            path,
+            file,
+            index: AHashMap::new(),
+            len: 0,
+            live: 0,
+            last_used: Instant::now(),
+        };
+        region.scan()?;
+        Ok(region)
+    }
+
+    /// Rebuild the index from the log.
+    /// A torn record at the end, left by a crash while appending, is cut off.
+    fn scan(&mut self) -> anyhow::Result<()> {
+        let file_len = self.file.metadata()?.len();
+        let mut reader = BufReader::new(&self.file);
+        reader.seek(SeekFrom::Start(0))?;
+        self.index.clear();
+        self.live = 0;
+
+        let mut offset = 0;
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        while offset + RECORD_HEADER_LEN <= file_len {
+            reader.read_exact(&mut header)?;
+            let (pos, len) = decode_record_header(&header);
+            let data_len = if len == TOMBSTONE { 0 } else { len as u64 };
+            if offset + RECORD_HEADER_LEN + data_len > file_len {
+                break;
+            }
+
+            if let Some((_, old_len)) = self.index.remove(&pos) {
+                self.live -= RECORD_HEADER_LEN + old_len as u64;
+            }
+            if len != TOMBSTONE {
+                self.index.insert(pos, (offset + RECORD_HEADER_LEN, len));
+                self.live += RECORD_HEADER_LEN + data_len;
+            }
+            reader.seek_relative(data_len as i64)?;
+            offset += RECORD_HEADER_LEN + data_len;
+        }
+        drop(reader);
+
+        if offset != file_len {
+            tracing::warn!(
+                "region file {} has a torn record, truncating {} bytes",
+                self.path.display(),
+                file_len - offset
+            );
+            self.file.set_len(offset)?;
+        }
+        self.len = offset;
+        Ok(())
+    }
+
+    fn read(&mut self, pos: IVec3) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(&(offset, len)) = self.index.get(&pos) else {
+            return Ok(None);
+        };
+        let mut bytes = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+
+    /// `None` appends a tombstone.
+    fn append(&mut self, pos: IVec3, bytes: Option<&[u8]>) -> anyhow::Result<()> {
+        self.file.seek(SeekFrom::Start(self.len))?;
+        let written = write_record(&mut self.file, pos, bytes)?;
+
+        if let Some((_, old_len)) = self.index.remove(&pos) {
+            self.live -= RECORD_HEADER_LEN + old_len as u64;
+        }
+        if let Some(bytes) = bytes {
+            self.index
+                .insert(pos, (self.len + RECORD_HEADER_LEN, bytes.len() as u32));
+            self.live += written;
+        }
+        self.len += written;
+        Ok(())
+    }
+
+    fn maybe_compact(&mut self) -> anyhow::Result<()> {
+        if self.len < MIN_COMPACT_LEN || self.live * 2 > self.len {
+            return Ok(());
+        }
+        let _span = tracing::info_span!("profiling::{compact region}").entered();
+
+        let tmp = self.path.with_extension("region.tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        let entries = self.index.clone();
+        for (pos, (offset, len)) in entries {
+            let mut bytes = vec![0; len as usize];
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut bytes)?;
+            write_record(&mut out, pos, Some(&bytes))?;
+        }
+        out.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)?;
+        self.scan()
+    }
+}
+
+/// Writes are synced as they are made, the sync here only reports a failure left over.
+fn close_least_recently_used(regions: &mut AHashMap<IVec3, Region>) -> anyhow::Result<()> {
+    let Some(oldest) = regions
+        .iter()
+        .min_by_key(|(_, region)| region.last_used)
+        .map(|(region_pos, _)| *region_pos)
+    else {
+        return Ok(());
+    };
+    regions[&oldest].file.sync_all()?;
+    regions.remove(&oldest);
+    Ok(())
+}
+
+fn decode_record_header(header: &[u8; RECORD_HEADER_LEN as usize]) -> (IVec3, u32) {
+    let word = |i: usize| [header[i], header[i + 1], header[i + 2], header[i + 3]];
+    let pos = IVec3::new(
+        i32::from_le_bytes(word(0)),
+        i32::from_le_bytes(word(4)),
+        i32::from_le_bytes(word(8)),
+    );
+    (pos, u32::from_le_bytes(word(12)))
+}
+
+/// Returns the number of bytes written.
+fn write_record(writer: &mut impl Write, pos: IVec3, bytes: Option<&[u8]>) -> anyhow::Result<u64> {
+    let len = match bytes {
+        Some(bytes) => u32::try_from(bytes.len())
+            .ok()
+            .filter(|len| *len != TOMBSTONE)
+            .with_context(|| "chunk too large for a region file")?,
+        None => TOMBSTONE,
+    };
+    let mut header = [0; RECORD_HEADER_LEN as usize];
+    for (i, v) in pos.to_array().into_iter().enumerate() {
+        header[i * 4..i * 4 + 4].copy_from_slice(&v.to_le_bytes());
+    }
+    header[12..16].copy_from_slice(&len.to_le_bytes());
+
+    writer.write_all(&header)?;
+    if let Some(bytes) = bytes {
+        writer.write_all(bytes)?;
+    }
+    Ok(RECORD_HEADER_LEN + bytes.map_or(0, |bytes| bytes.len() as u64))
+}
+
+/// Replace `path` so readers never see a half written file.
+fn write_atomic(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+impl ChunkStorage for RegionStorage {
+    fn load(&self, pos: IVec3) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut regions = self.regions.lock();
+        match self.region(&mut regions, Self::region_pos(pos), false)? {
+            Some(region) => region.read(pos),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, chunks: &[(IVec3, &[u8])]) -> anyhow::Result<()> {
+        let mut regions = self.regions.lock();
+        let mut touched = AHashSet::new();
+        for (pos, bytes) in chunks {
+            let region_pos = Self::region_pos(*pos);
+            if let Some(region) = self.region(&mut regions, region_pos, true)? {
+                region.append(*pos, Some(bytes))?;
+            }
+            touched.insert(region_pos);
+        }
+        for region_pos in touched {
+            if let Some(region) = regions.get_mut(&region_pos) {
+                region.file.sync_data()?;
+                region.maybe_compact()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&self, pos: IVec3) -> anyhow::Result<bool> {
+        let mut regions = self.regions.lock();
+        let Some(region) = self.region(&mut regions, Self::region_pos(pos), false)? else {
+            return Ok(false);
+        };
+        if !region.index.contains_key(&pos) {
+            return Ok(false);
+        }
+        region.append(pos, None)?;
+        region.file.sync_data()?;
+        Ok(true)
+    }
+
+    fn exists(&self, pos: IVec3) -> anyhow::Result<bool> {
+        let mut regions = self.regions.lock();
+        Ok(self
+            .region(&mut regions, Self::region_pos(pos), false)?
+            .is_some_and(|region| region.index.contains_key(&pos)))
+    }
+
+    fn iterate(
+        &self,
+        f: &mut dyn FnMut(IVec3, &[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        for region_pos in self.region_files()? {
+            // read a whole region, then release the lock before calling `f`
+            let chunks = {
+                let mut regions = self.regions.lock();
+                let Some(region) = self.region(&mut regions, region_pos, false)? else {
+                    continue;
+                };
+                let positions = region.index.keys().copied().collect::<Vec<_>>();
+                let mut chunks = Vec::with_capacity(positions.len());
+                for pos in positions {
+                    if let Some(bytes) = region.read(pos)? {
+                        chunks.push((pos, bytes));
+                    }
+                }
+                chunks
+            };
+            for (pos, bytes) in chunks {
+                f(pos, &bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn quarantine(&self, pos: IVec3, bytes: &[u8]) -> anyhow::Result<()> {
+        let dir = self.dir.join(QUARANTINE_DIR);
+        fs::create_dir_all(&dir)?;
+        write_atomic(
+            &dir.join(format!("c.{}.{}.{}.bin", pos.x, pos.y, pos.z)),
+            bytes,
+        )?;
+        self.delete(pos)?;
+        Ok(())
+    }
+
+    fn quarantined(&self) -> anyhow::Result<Vec<IVec3>> {
+        let dir = self.dir.join(QUARANTINE_DIR);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut positions = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let Some(coords) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("c."))
+                .and_then(|name| name.strip_suffix(".bin"))
+            else {
+                continue;
+            };
+            let coords = coords
+                .split('.')
+                .map(str::parse)
+                .collect::<Result<Vec<i32>, _>>();
+            if let Ok(&[x, y, z]) = coords.as_deref() {
+                positions.push(IVec3::new(x, y, z));
+            }
+        }
+        Ok(positions)
+    }
+
+    fn save_pending_edits(&self, edits: &[(IVec3, IVec3, BlockState)]) -> anyhow::Result<()> {
+        let mut map = self.pending_edits.lock();
+        for (chunk_pos, block_pos, state) in edits {
+            map.insert((chunk_pos.to_array(), block_pos.to_array()), state.clone());
+        }
+        self.write_pending_edits(&map)
+    }
+
+    fn load_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<Vec<(IVec3, BlockState)>> {
+        let chunk_pos = chunk_pos.to_array();
+        Ok(self
+            .pending_edits
+            .lock()
+            .range((chunk_pos, [i32::MIN; 3])..=(chunk_pos, [i32::MAX; 3]))
+            .map(|((_, block_pos), id)| (IVec3::from_array(*block_pos), id.clone()))
+            .collect())
+    }
+
+    fn remove_pending_edits(&self, chunk_pos: IVec3) -> anyhow::Result<()> {
+        let chunk_pos = chunk_pos.to_array();
+        let mut map = self.pending_edits.lock();
+        let before = map.len();
+        map.retain(|(pos, _), _| *pos != chunk_pos);
+        if map.len() != before {
+            self.write_pending_edits(&map)?;
+        }
+        Ok(())
+    }
+
+    /// No transactions here: the chunks are written first, so a crash in between leaves
+    /// edits pending that only place the blocks the chunk already has.
+    fn save_applied(
+        &self,
+        chunks: &[(IVec3, &[u8])],
+        applied_edits: &[(IVec3, IVec3, BlockState)],
+    ) -> anyhow::Result<()> {
+        self.save(chunks)?;
+        let mut map = self.pending_edits.lock();
+        let before = map.len();
+        for (chunk_pos, block_pos, state) in applied_edits {
+            let key = (chunk_pos.to_array(), block_pos.to_array());
+            if map.get(&key) == Some(state) {
+                map.remove(&key);
+            }
+        }
+        if map.len() != before {
+            self.write_pending_edits(&map)?;
+        }
+        Ok(())
+    }
+
+    fn pending_edits(&self) -> anyhow::Result<Vec<(IVec3, IVec3, BlockState)>> {
+        Ok(self
+            .pending_edits
+            .lock()
+            .iter()
+            .map(|((chunk_pos, block_pos), id)| {
+                (
+                    IVec3::from_array(*chunk_pos),
+                    IVec3::from_array(*block_pos),
+                    id.clone(),
+                )
+            })
+            .collect())
+    }
+
+    fn metadata(&self) -> anyhow::Result<Option<WorldMetadata>> {
+        match fs::read(self.dir.join(METADATA_FILE)) {
+            Ok(bytes) => WorldMetadata::decode(&bytes).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn set_metadata(&self, metadata: &WorldMetadata) -> anyhow::Result<()> {
+        write_atomic(&self.dir.join(METADATA_FILE), &metadata.encode()?)
+    }
+}
+
+#[test]
+fn test_region_log_replay() {
+    let dir = std::env::temp_dir().join(format!("minecrust-region-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+
+    let a = IVec3::new(0, 0, 0);
+    let b = IVec3::new(-1, 3, 9);
+    let c = IVec3::new(1, 0, 0);
+    {
+        let storage = RegionStorage::open(&dir).unwrap();
+        storage.save(&[(a, &b"first"[..]), (b, &b"other region"[..])]).unwrap();
+        storage.save(&[(a, &b"second"[..]), (c, &b"gone"[..])]).unwrap();
+        assert!(storage.delete(c).unwrap());
+        assert!(!storage.delete(c).unwrap());
+    }
+
+    // append a torn record, as if the game crashed mid write
+    let path = dir.join("r.0.0.0.region");
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[1, 2, 3]).unwrap();
+    drop(file);
+
+    let storage = RegionStorage::open(&dir).unwrap();
+    assert_eq!(storage.load(a).unwrap().as_deref(), Some(&b"second"[..]));
+    assert_eq!(storage.load(b).unwrap().as_deref(), Some(&b"other region"[..]));
+    assert_eq!(storage.load(c).unwrap(), None);
+    assert!(!storage.exists(c).unwrap());
+
+    let mut count = 0;
+    storage
+        .iterate(&mut |_, _| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(count, 2);
+
+    drop(storage);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_region_close_least_recently_used() {
+    let dir = std::env::temp_dir().join(format!("minecrust-regions-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+
+    let storage = RegionStorage::open(&dir).unwrap();
+    let chunks = (0..MAX_OPEN_REGIONS as i32 + 8)
+        .map(|x| IVec3::new(x * REGION_SIZE, 0, 0))
+        .collect::<Vec<_>>();
+    for pos in &chunks {
+        storage.save(&[(*pos, &pos.x.to_le_bytes()[..])]).unwrap();
+    }
+    assert_eq!(storage.regions.lock().len(), MAX_OPEN_REGIONS);
+    // closed regions are opened again
+    for pos in &chunks {
+        assert_eq!(
+            storage.load(*pos).unwrap().as_deref(),
+            Some(&pos.x.to_le_bytes()[..])
+        );
+    }
+    assert_eq!(storage.regions.lock().len(), MAX_OPEN_REGIONS);
+
+    drop(storage);
+    fs::remove_dir_all(&dir).ok();
+}
@@ -1,5 +1,12 @@
 use bevy::math::IVec3;
 
+/// Every position in the box from `min` to `max`, both inclusive, z fastest.
+pub fn ivec3_range(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3> {
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+    })
+}
+
 #[inline]
 pub fn index_to_ivec3_bounds(i: i32, bounds: i32) -> IVec3 {
     let x = i % bounds;
@@ -1,3 +1,8 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+
+use anyhow::Context;
 use block_mesh::{MergeVoxel, Voxel};
 use once_cell::sync::Lazy;
 
@@ -7,6 +12,188 @@ pub static AIR: Lazy<BlockId> = Lazy::new(|| Atom::new("core::air"));
 
 pub type BlockId = Atom;
 
+/// A block and the values of its properties, written `core::log[axis=x,lit=false]`
+/// with properties sorted by name. A bare id is the state that sets no properties,
+/// which is also what palettes saved before block states read back as.
+#[derive(Clone)]
+pub struct BlockState {
+    /// the whole string, compared and hashed
+    state: Atom,
+    id: BlockId,
+}
+
+impl BlockState {
+    /// A property given twice keeps its last value.
+    pub fn new<'a>(id: BlockId, properties: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let properties = properties.into_iter().collect::<BTreeMap<_, _>>();
+        if properties.is_empty() {
+            return BlockState::from(id);
+        }
+        let list = properties
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        BlockState {
+            state: Atom::new(format!("{id}[{list}]")),
+            id,
+        }
+    }
+
+    /// `namespace::id` or `namespace::id[name=value,...]`, properties in any order.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let Some((id, rest)) = s.split_once('[') else {
+            anyhow::ensure!(!s.is_empty(), "empty block state");
+            return Ok(BlockState::from(BlockId::new(s)));
+        };
+        let list = rest
+            .strip_suffix(']')
+            .with_context(|| format!("block state `{s}` is missing `]`"))?;
+        let mut properties = Vec::new();
+        for property in list.split(',').filter(|property| !property.is_empty()) {
+            let (name, value) = property
+                .split_once('=')
+                .filter(|(name, value)| {
+                    !name.is_empty() && !value.is_empty() && !value.contains(['[', ']', '='])
+                })
+                .with_context(|| {
+                    format!("block state `{s}`: expected `name=value`, got `{property}`")
+                })?;
+            anyhow::ensure!(
+                properties.iter().all(|(other, _)| *other != name),
+                "block state `{s}` sets `{name}` twice"
+            );
+            properties.push((name, value));
+        }
+        Ok(BlockState::new(BlockId::new(id), properties))
+    }
+
+    #[inline]
+    pub fn id(&self) -> &BlockId {
+        &self.id
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        self.state.as_str()
+    }
+
+    #[inline]
+    pub fn is_air(&self) -> bool {
+        self.id == *AIR
+    }
+
+    /// (name, value), sorted by name.
+    pub fn properties(&self) -> impl Iterator<Item = (&str, &str)> {
+        let list = self
+            .as_str()
+            .split_once('[')
+            .map_or("", |(_, rest)| &rest[..rest.len() - 1]);
+        list.split(',')
+            .filter_map(|property| property.split_once('='))
+    }
+
+    /// `None` if the state does not set `name`.
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties()
+            .find(|(other, _)| *other == name)
+            .map(|(_, value)| value)
+    }
+
+    /// This state with `name` set to `value`.
+    pub fn with(&self, name: &str, value: &str) -> Self {
+        BlockState::new(
+            self.id.clone(),
+            self.properties()
+                .filter(|(other, _)| *other != name)
+                .chain([(name, value)]),
+        )
+    }
+}
+
+impl From<BlockId> for BlockState {
+    fn from(id: BlockId) -> Self {
+        BlockState {
+            state: id.clone(),
+            id,
+        }
+    }
+}
+
+impl PartialEq for BlockState {
+    fn eq(&self, other: &Self) -> bool {
+        self.state == other.state
+    }
+}
+
+impl Eq for BlockState {}
+
+impl Hash for BlockState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.state.hash(state);
+    }
+}
+
+impl Display for BlockState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl Debug for BlockState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl bincode::Encode for BlockState {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.as_str().encode(encoder)
+    }
+}
+
+impl bincode::Decode for BlockState {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let s = String::decode(decoder)?;
+        BlockState::parse(&s)
+            .map_err(|err| bincode::error::DecodeError::OtherString(err.to_string()))
+    }
+}
+
+impl<'de> bincode::BorrowDecode<'de> for BlockState {
+    fn borrow_decode<D: bincode::de::BorrowDecoder<'de>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let s = <&str>::borrow_decode(decoder)?;
+        BlockState::parse(s)
+            .map_err(|err| bincode::error::DecodeError::OtherString(err.to_string()))
+    }
+}
+
+impl serde::Serialize for BlockState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for BlockState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        BlockState::parse(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub enum VoxelBlock {
     #[default]
@@ -80,3 +267,25 @@ impl bincode::Decode for VoxelBlock {
 }
 
 bincode::impl_borrow_decode!(VoxelBlock);
+
+#[test]
+fn test_block_state_parse() {
+    let state = BlockState::parse("core::furnace[lit=true,facing=north]").unwrap();
+    assert_eq!(state.as_str(), "core::furnace[facing=north,lit=true]");
+    assert_eq!(state.id(), &BlockId::new("core::furnace"));
+    assert_eq!(state.property("lit"), Some("true"));
+    assert_eq!(state.property("axis"), None);
+    assert_eq!(
+        state.with("lit", "false"),
+        BlockState::parse("core::furnace[facing=north,lit=false]").unwrap()
+    );
+
+    let bare = BlockState::parse("core::grass").unwrap();
+    assert_eq!(bare, BlockState::from(BlockId::new("core::grass")));
+    assert_eq!(bare.properties().count(), 0);
+    assert_eq!(bare.with("snowy", "true").as_str(), "core::grass[snowy=true]");
+
+    assert!(BlockState::parse("core::log[axis=x").is_err());
+    assert!(BlockState::parse("core::log[axis]").is_err());
+    assert!(BlockState::parse("core::log[axis=x,axis=y]").is_err());
+}
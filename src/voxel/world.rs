@@ -1,29 +1,58 @@
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
 use ahash::AHashMap;
 use bevy::math::bounding::Aabb3d;
-use bevy::math::{IVec3, Vec3A};
-use bevy::prelude::{Component, Entity, Resource};
+use bevy::math::{IVec2, IVec3, Vec3A};
+use bevy::prelude::{Component, Entity, EventReader, Res, Resource};
+use mlua::{LuaSerdeExt, UserData, UserDataRef};
 
-use super::chunk::ChunkData;
+use crate::core::registry::Registry;
+use crate::script::LuaEngine;
+
+use super::anvil::BlockMapping;
+use super::block_entity::{self, BlockEntity, BlockValue};
+use super::chunk::{BlockChanged, ChunkData};
+use super::edit::{BulkEdit, Clipboard, Shape};
 use super::generator::flat::FlatGenerator;
 use super::generator::Generator;
+use super::heightmap::{self, HeightmapKind};
+use super::history::HistoryStep;
+use super::modifier::VoxelModifier;
+use super::query::{self, QueryError};
+use super::schematic;
+use super::storage::WorldMetadata;
+use super::voxel_block::BlockState;
 
 // All chunks in the world are children of root
 #[derive(Component)]
 pub struct WorldRoot;
 
+pub type LoadedChunks = scc::HashMap<IVec3, ChunkData, ahash::RandomState>;
+pub type ChunkPositions = scc::HashSet<IVec3, ahash::RandomState>;
+
 #[derive(Resource)]
 pub struct VoxelWorld {
-    pub loaded_chunks: scc::HashMap<IVec3, ChunkData, ahash::RandomState>,
+    pub loaded_chunks: Arc<LoadedChunks>,
+    /// keys of `loaded_chunks`, listed without locking any chunk
+    pub loaded_positions: Arc<scc::HashSet<IVec3, ahash::RandomState>>,
     pub loading_chunks: scc::HashSet<IVec3, ahash::RandomState>,
     pub saving_chunks: Arc<scc::HashSet<IVec3, ahash::RandomState>>,
+    /// chunks whose block entities changed after their last `generate_hash`
+    pub unhashed: Arc<ChunkPositions>,
     pub generator: Arc<dyn Generator>,
     pub bounds: Aabb3d,
     pub root: Entity,
 }
 
 impl VoxelWorld {
+    pub fn from_metadata(metadata: &WorldMetadata) -> Self {
+        VoxelWorld {
+            generator: metadata.generator.build(metadata.seed),
+            ..Default::default()
+        }
+    }
+
     pub fn with_generator(mut self, generator: impl Generator + 'static) -> Self {
         self.generator = Arc::new(generator);
         self
@@ -35,6 +64,68 @@ impl VoxelWorld {
             || self.saving_chunks.contains(&pos))
     }
 
+    /// `None` == Air
+    pub fn get_block(&self, pos: IVec3) -> Result<Option<BlockState>, QueryError> {
+        query::get_block(&self.loaded_chunks, pos)
+    }
+
+    pub fn is_solid(&self, pos: IVec3) -> Result<bool, QueryError> {
+        query::is_solid(&self.loaded_chunks, pos)
+    }
+
+    /// Visit every block from `min` to `max`, both inclusive. `f` must not modify the world.
+    pub fn for_each_block(
+        &self,
+        min: IVec3,
+        max: IVec3,
+        f: impl FnMut(IVec3, Option<&BlockState>),
+    ) -> Result<(), QueryError> {
+        query::for_each_block(&self.loaded_chunks, min, max, f)
+    }
+
+    /// Y of the highest non-air block of column `column` (x, z) within `y`.
+    pub fn highest_solid(
+        &self,
+        column: IVec2,
+        y: RangeInclusive<i32>,
+    ) -> Result<Option<i32>, QueryError> {
+        query::highest_solid(&self.loaded_chunks, column, y)
+    }
+
+    /// Y of the highest block of `kind` in column `column` (x, z) within `y`,
+    /// widened to whole chunks. Reads heightmaps instead of blocks.
+    pub fn height(
+        &self,
+        kind: HeightmapKind,
+        column: IVec2,
+        y: RangeInclusive<i32>,
+    ) -> Result<Option<i32>, QueryError> {
+        heightmap::height(&self.loaded_chunks, kind, column, y)
+    }
+
+    /// Copy the box from `min` to `max`, both inclusive, to be pasted with `BulkEdit::Paste`.
+    pub fn copy(&self, min: IVec3, max: IVec3) -> Result<Clipboard, QueryError> {
+        Clipboard::copy(&self.loaded_chunks, min, max)
+    }
+
+    /// `None` if the block has no entity or its chunk is not loaded.
+    pub fn block_entity(&self, pos: IVec3) -> Option<BlockEntity> {
+        block_entity::get(&self.loaded_chunks, pos)
+    }
+
+    /// Attach `data` to the block at `pos`, which has to be loaded and not air.
+    pub fn set_block_entity(
+        &self,
+        pos: IVec3,
+        data: BlockValue,
+    ) -> anyhow::Result<Option<BlockEntity>> {
+        block_entity::set(&self.loaded_chunks, &self.unhashed, pos, data)
+    }
+
+    pub fn remove_block_entity(&self, pos: IVec3) -> Option<BlockEntity> {
+        block_entity::remove(&self.loaded_chunks, &self.unhashed, pos)
+    }
+
     /* pub fn get_chunk_ref(&self, pos: IVec3) -> ChunkRef {
         let mut chunks = ChunkRef {
             refs: Vec::with_capacity(27),
@@ -54,11 +145,264 @@ impl Default for VoxelWorld {
     fn default() -> Self {
         Self {
             loaded_chunks: Default::default(),
+            loaded_positions: Default::default(),
             loading_chunks: Default::default(),
             saving_chunks: Default::default(),
+            unhashed: Default::default(),
             bounds: Aabb3d::new(Vec3A::ZERO, Vec3A::ZERO),
             root: Entity::PLACEHOLDER,
             generator: Arc::new(FlatGenerator::new()),
         }
     }
 }
+
+/// `World` in Lua, a handle to the loaded chunks.
+#[derive(Clone)]
+pub struct LuaWorld {
+    chunks: Arc<LoadedChunks>,
+    unhashed: Arc<ChunkPositions>,
+    edits: kanal::Sender<BulkEdit>,
+    history: kanal::Sender<HistoryStep>,
+    registry: Registry,
+    /// Minecraft block names of schematics
+    mapping: Arc<BlockMapping>,
+}
+
+impl LuaWorld {
+    pub fn new(world: &VoxelWorld, modifier: &VoxelModifier, registry: &Registry) -> Self {
+        LuaWorld {
+            chunks: world.loaded_chunks.clone(),
+            unhashed: world.unhashed.clone(),
+            edits: modifier.bulk.0.clone(),
+            history: modifier.history.0.clone(),
+            registry: registry.clone(),
+            mapping: Arc::new(BlockMapping::builtin()),
+        }
+    }
+
+    fn apply(&self, edit: BulkEdit) -> mlua::Result<()> {
+        edit.check()
+            .map_err(|err| mlua::Error::runtime(err.to_string()))?;
+        self.edits.send(edit).map_err(mlua::Error::external)
+    }
+}
+
+/// Lua registry key of the functions passed to `World:on_block_changed`.
+const BLOCK_CHANGED_CALLBACKS: &str = "block_changed_callbacks";
+
+/// Call every `World:on_block_changed` callback with `(x, y, z, old, new, cause)`,
+/// `cause` being one of `edit`, `bulk`, `undo`, `redo` and `deferred`.
+pub fn run_block_changed_callbacks(lua: Res<LuaEngine>, mut events: EventReader<BlockChanged>) {
+    if events.is_empty() {
+        return;
+    }
+    let callbacks = match lua.named_registry_value::<Option<mlua::Table>>(BLOCK_CHANGED_CALLBACKS) {
+        Ok(Some(callbacks)) => callbacks,
+        Ok(None) => {
+            events.clear();
+            return;
+        }
+        Err(err) => {
+            tracing::error!("read block changed callbacks failed: {}", err);
+            events.clear();
+            return;
+        }
+    };
+    for event in events.read() {
+        for callback in callbacks.sequence_values::<mlua::Function>() {
+            let result = callback.and_then(|callback| {
+                callback.call::<()>((
+                    event.pos.x,
+                    event.pos.y,
+                    event.pos.z,
+                    event.old.as_str(),
+                    event.new.as_str(),
+                    event.cause.as_str(),
+                ))
+            });
+            if let Err(err) = result {
+                tracing::error!("block changed callback failed: {}", err);
+            }
+        }
+    }
+}
+
+fn parse_state(state: &str) -> mlua::Result<BlockState> {
+    BlockState::parse(state).map_err(|err| mlua::Error::runtime(format!("{err:#}")))
+}
+
+impl UserData for LuaWorld {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        // `nil` for air, an error if the chunk is not loaded
+        methods.add_method::<_, (i32, i32, i32), _>("get_block", |_, this, (x, y, z)| {
+            query::get_block(&this.chunks, IVec3::new(x, y, z))
+                .map(|state| state.map(|state| state.to_string()))
+                .map_err(mlua::Error::external)
+        });
+        methods.add_method::<_, (i32, i32, i32), _>("is_solid", |_, this, (x, y, z)| {
+            query::is_solid(&this.chunks, IVec3::new(x, y, z)).map_err(mlua::Error::external)
+        });
+        methods.add_method::<_, (i32, i32, i32, i32), _>(
+            "highest_solid",
+            |_, this, (x, z, bottom, top)| {
+                query::highest_solid(&this.chunks, IVec2::new(x, z), bottom..=top)
+                    .map_err(mlua::Error::external)
+            },
+        );
+        // kind is "world_surface", "motion_blocking" or "ocean_floor"
+        methods.add_method::<_, (i32, i32, String, i32, i32), _>(
+            "height",
+            |_, this, (x, z, kind, bottom, top)| {
+                let kind = HeightmapKind::parse(&kind)
+                    .ok_or_else(|| mlua::Error::runtime(format!("unknown heightmap `{kind}`")))?;
+                heightmap::height(&this.chunks, kind, IVec2::new(x, z), bottom..=top)
+                    .map_err(mlua::Error::external)
+            },
+        );
+        // bulk edits are applied at the start of the next frame
+        methods.add_method::<_, (i32, i32, i32, i32, i32, i32, String, Option<bool>), _>(
+            "fill",
+            |_, this, (x1, y1, z1, x2, y2, z2, state, hollow)| {
+                this.apply(BulkEdit::Fill {
+                    shape: Shape::Box {
+                        min: IVec3::new(x1, y1, z1),
+                        max: IVec3::new(x2, y2, z2),
+                    },
+                    state: parse_state(&state)?,
+                    hollow: hollow.unwrap_or(false),
+                })
+            },
+        );
+        methods.add_method::<_, (i32, i32, i32, i32, i32, i32, String, String), _>(
+            "replace",
+            |_, this, (x1, y1, z1, x2, y2, z2, from, to)| {
+                this.apply(BulkEdit::Replace {
+                    shape: Shape::Box {
+                        min: IVec3::new(x1, y1, z1),
+                        max: IVec3::new(x2, y2, z2),
+                    },
+                    from: parse_state(&from)?,
+                    to: parse_state(&to)?,
+                })
+            },
+        );
+        methods.add_method::<_, (i32, i32, i32, u32, String, Option<bool>), _>(
+            "sphere",
+            |_, this, (x, y, z, radius, state, hollow)| {
+                this.apply(BulkEdit::Fill {
+                    shape: Shape::Sphere {
+                        center: IVec3::new(x, y, z),
+                        radius,
+                    },
+                    state: parse_state(&state)?,
+                    hollow: hollow.unwrap_or(false),
+                })
+            },
+        );
+        methods.add_method::<_, (i32, i32, i32, u32, u32, String, Option<bool>), _>(
+            "cylinder",
+            |_, this, (x, y, z, radius, height, state, hollow)| {
+                this.apply(BulkEdit::Fill {
+                    shape: Shape::Cylinder {
+                        base: IVec3::new(x, y, z),
+                        radius,
+                        height,
+                    },
+                    state: parse_state(&state)?,
+                    hollow: hollow.unwrap_or(false),
+                })
+            },
+        );
+        methods.add_method::<_, (i32, i32, i32, i32, i32, i32), _>(
+            "copy",
+            |_, this, (x1, y1, z1, x2, y2, z2)| {
+                Clipboard::copy(&this.chunks, IVec3::new(x1, y1, z1), IVec3::new(x2, y2, z2))
+                    .map_err(mlua::Error::external)
+            },
+        );
+        methods.add_method::<_, (UserDataRef<Clipboard>, i32, i32, i32), _>(
+            "paste",
+            |_, this, (clipboard, x, y, z)| {
+                this.apply(BulkEdit::Paste {
+                    origin: IVec3::new(x, y, z),
+                    clipboard: clipboard.clone(),
+                })
+            },
+        );
+        // called after edits are applied, see `run_block_changed_callbacks`
+        methods.add_method("on_block_changed", |lua, _, callback: mlua::Function| {
+            let callbacks =
+                match lua.named_registry_value::<Option<mlua::Table>>(BLOCK_CHANGED_CALLBACKS)? {
+                    Some(callbacks) => callbacks,
+                    None => {
+                        let callbacks = lua.create_table()?;
+                        lua.set_named_registry_value(BLOCK_CHANGED_CALLBACKS, &callbacks)?;
+                        callbacks
+                    }
+                };
+            callbacks.push(callback)
+        });
+        // a `.schem` file as a clipboard, blocks without a mapping are air
+        methods.add_method("load_schematic", |_, this, path: String| {
+            let (clipboard, stats) = schematic::load(&path, &this.mapping, &this.registry)
+                .map_err(|err| mlua::Error::runtime(format!("{err:#}")))?;
+            if !stats.unmapped.is_empty() || !stats.unregistered.is_empty() {
+                tracing::warn!(
+                    "schematic {} has blocks imported as air, unmapped: {:?}, unregistered: {:?}",
+                    path,
+                    stats.unmapped,
+                    stats.unregistered
+                );
+            }
+            Ok(clipboard)
+        });
+        methods.add_method::<_, (String, UserDataRef<Clipboard>), _>(
+            "save_schematic",
+            |_, this, (path, clipboard)| {
+                let stats = schematic::save(&path, &clipboard, &this.mapping)
+                    .map_err(|err| mlua::Error::runtime(format!("{err:#}")))?;
+                if !stats.unmapped.is_empty() {
+                    tracing::warn!(
+                        "schematic {} has blocks saved as air, unmapped: {:?}",
+                        path,
+                        stats.unmapped
+                    );
+                }
+                Ok(())
+            },
+        );
+        // the last fill, replace, paste or frame of single edits, applied after this frame's edits
+        methods.add_method("undo", |_, this, ()| {
+            this.history
+                .send(HistoryStep::Undo)
+                .map_err(mlua::Error::external)
+        });
+        methods.add_method("redo", |_, this, ()| {
+            this.history
+                .send(HistoryStep::Redo)
+                .map_err(mlua::Error::external)
+        });
+        methods.add_method::<_, (i32, i32, i32), _>("get_block_entity", |lua, this, (x, y, z)| {
+            block_entity::get(&this.chunks, IVec3::new(x, y, z))
+                .map(|entity| lua.to_value(&entity.data))
+                .transpose()
+        });
+        methods.add_method::<_, (i32, i32, i32, mlua::Value), _>(
+            "set_block_entity",
+            |lua, this, (x, y, z, data)| {
+                let data = lua.from_value::<BlockValue>(data)?;
+                block_entity::set(&this.chunks, &this.unhashed, IVec3::new(x, y, z), data)
+                    .map_err(|err| mlua::Error::runtime(format!("{err:#}")))?;
+                Ok(())
+            },
+        );
+        methods.add_method::<_, (i32, i32, i32), _>(
+            "remove_block_entity",
+            |lua, this, (x, y, z)| {
+                block_entity::remove(&this.chunks, &this.unhashed, IVec3::new(x, y, z))
+                    .map(|entity| lua.to_value(&entity.data))
+                    .transpose()
+            },
+        );
+    }
+}